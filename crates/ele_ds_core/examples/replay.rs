@@ -0,0 +1,25 @@
+// 在电脑上回放按键录制文件, 用默认按键绑定打印每一步的动作和页面:
+// cargo run --example replay --target x86_64-unknown-linux-gnu -- keys.jsonl
+use ele_ds_core::device_config::key_binding::default_key_bindings;
+use ele_ds_core::simulator::Simulator;
+use ele_ds_core::ActivePage;
+
+fn main() -> anyhow::Result<()> {
+    let Some(path) = std::env::args().nth(1) else {
+        anyhow::bail!("usage: replay <keys.jsonl>");
+    };
+    let content = std::fs::read_to_string(&path)?;
+    let bindings = default_key_bindings();
+    let mut sim = Simulator::new(&bindings, ActivePage::default());
+    for step in sim.replay_str(&content)? {
+        println!(
+            "{:>8.3}s key {} {:?} -> {:?}, page {:?}",
+            step.at.as_secs_f32(),
+            step.key.idx,
+            step.key.click_type,
+            step.action,
+            step.page
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,360 @@
+// ly6806 用三根线输出 pwm 驱动 4 个电量灯, 单片机读到的是高频翻转的电平, 不能直接读 io 判断.
+// 这里把一段时间内的采样按 100ms 分桶, 桶内有一次低电平就算这个桶亮,
+// 再根据亮灭的规律判断每根线是常亮, 闪烁还是熄灭, 最后得到电量和充电状态. 不依赖硬件, 方便用采样数据测试
+use serde::{Deserialize, Serialize};
+
+pub const SAMPLE_INTERVAL_MS: u64 = 10; // 采样间隔
+pub const SAMPLE_WINDOW_MS: u64 = 1500; // 一次采样的总时长, 至少要包含一个慢闪周期
+const BUCKET_MS: u64 = 100; // 分桶时长, 远大于 pwm 周期, 小于闪烁周期
+const SOLID_MIN_RATIO: f32 = 0.9; // 亮的桶超过这个比例算常亮, 容忍偶尔漏采
+const FAST_BLINK_MAX_PERIOD_MS: u64 = 500; // 闪烁周期小于这个算快闪, 表示电量过低
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DeviceBatteryType {
+    PercentVbat100,
+    PercentVbat100_75,
+    PercentVbat75_50,
+    PercentVbat50_25,
+    PercentVbat25_0,
+}
+
+impl DeviceBatteryType {
+    /// 电量区间, (下限, 上限). 每个灯代表 25%, 只能知道电量在哪个区间
+    pub fn percent_range(&self) -> (u8, u8) {
+        match self {
+            DeviceBatteryType::PercentVbat100 => (100, 100),
+            DeviceBatteryType::PercentVbat100_75 => (75, 100),
+            DeviceBatteryType::PercentVbat75_50 => (50, 75),
+            DeviceBatteryType::PercentVbat50_25 => (25, 50),
+            DeviceBatteryType::PercentVbat25_0 => (0, 25),
+        }
+    }
+
+    /// 状态栏显示的电量, 充满时显示 100%, 其他显示区间, 比如 25-50%
+    pub fn percent_text(&self) -> String {
+        match self.percent_range() {
+            (low, high) if low == high => format!("{high}%"),
+            (low, high) => format!("{low}-{high}%"),
+        }
+    }
+}
+
+/// 单根线的亮灯规律
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinePattern {
+    Off,
+    Solid,
+    Blink { period_ms: u64 }, // 估算的闪烁周期
+}
+
+impl LinePattern {
+    fn is_active(&self) -> bool {
+        *self != LinePattern::Off
+    }
+
+    fn is_fast_blink(&self) -> bool {
+        matches!(self, LinePattern::Blink { period_ms } if *period_ms <= FAST_BLINK_MAX_PERIOD_MS)
+    }
+
+    fn is_slow_blink(&self) -> bool {
+        matches!(self, LinePattern::Blink { period_ms } if *period_ms > FAST_BLINK_MAX_PERIOD_MS)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChargeState {
+    Charging,    // 有灯慢闪
+    Full,        // 三根线都常亮
+    Discharging, // 有灯常亮但没有闪烁
+    Unknown,     // 灯都不亮, 芯片不显示电量时读不到状态
+}
+
+/// 解码后的电池状态
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BatteryStatus {
+    pub level: Option<DeviceBatteryType>, // 灯都不亮时为 None
+    pub state: ChargeState,
+    pub critical: bool, // 第一个灯快闪, 电量过低
+}
+
+impl BatteryStatus {
+    /// 是否接着充电器, 充电中或者已充满
+    pub fn is_charging(&self) -> bool {
+        matches!(self.state, ChargeState::Charging | ChargeState::Full)
+    }
+
+    /// 编码成整数保存到 rtc 内存, 0 表示没有状态
+    pub fn to_bits(&self) -> u32 {
+        let level = self.level.map_or(0, |level| level as u32 + 1);
+        let state = self.state as u32;
+        (1 << 8) | (self.critical as u32) << 6 | state << 3 | level
+    }
+
+    pub fn from_bits(bits: u32) -> Option<BatteryStatus> {
+        if bits >> 8 != 1 {
+            return None;
+        }
+        let level = match bits & 0x7 {
+            0 => None,
+            1 => Some(DeviceBatteryType::PercentVbat100),
+            2 => Some(DeviceBatteryType::PercentVbat100_75),
+            3 => Some(DeviceBatteryType::PercentVbat75_50),
+            4 => Some(DeviceBatteryType::PercentVbat50_25),
+            5 => Some(DeviceBatteryType::PercentVbat25_0),
+            _ => return None,
+        };
+        let state = match (bits >> 3) & 0x7 {
+            0 => ChargeState::Charging,
+            1 => ChargeState::Full,
+            2 => ChargeState::Discharging,
+            3 => ChargeState::Unknown,
+            _ => return None,
+        };
+        Some(BatteryStatus {
+            level,
+            state,
+            critical: (bits >> 6) & 1 == 1,
+        })
+    }
+}
+
+/// 根据一根线的采样判断亮灯规律, samples 中 true 表示采到了低电平 (灯亮)
+pub fn classify_line(samples: &[bool], sample_interval_ms: u64) -> LinePattern {
+    let per_bucket = (BUCKET_MS / sample_interval_ms.max(1)).max(1) as usize;
+    let buckets: Vec<bool> = samples
+        .chunks(per_bucket)
+        .map(|chunk| chunk.iter().any(|s| *s))
+        .collect();
+    let active = buckets.iter().filter(|b| **b).count();
+    if active == 0 {
+        return LinePattern::Off;
+    }
+    if active as f32 >= buckets.len() as f32 * SOLID_MIN_RATIO {
+        return LinePattern::Solid;
+    }
+    // 一个周期有两次跳变, 用窗口时长和跳变次数估算周期
+    let transitions = buckets.windows(2).filter(|w| w[0] != w[1]).count().max(1) as u64;
+    let window_ms = buckets.len() as u64 * per_bucket as u64 * sample_interval_ms;
+    LinePattern::Blink {
+        period_ms: 2 * window_ms / transitions,
+    }
+}
+
+/// 根据三根线的亮灯规律得到电池状态, 线的顺序和电量灯从低到高的顺序一致.
+/// 电量取最高的常亮灯, 和原来只读 io 的判断保持一致, 充电时正在闪的灯表示还没充到
+pub fn decode_battery(lines: [LinePattern; 3]) -> BatteryStatus {
+    let full = lines.iter().all(|l| *l == LinePattern::Solid);
+    let critical = lines[0].is_fast_blink() && !lines[1].is_active() && !lines[2].is_active();
+    let state = if lines.iter().any(LinePattern::is_slow_blink) {
+        ChargeState::Charging
+    } else if full {
+        ChargeState::Full
+    } else if lines.iter().any(LinePattern::is_active) {
+        ChargeState::Discharging
+    } else {
+        ChargeState::Unknown
+    };
+    let highest_solid = lines.iter().rposition(|l| *l == LinePattern::Solid);
+    let level = match highest_solid {
+        Some(2) if full => Some(DeviceBatteryType::PercentVbat100),
+        Some(2) => Some(DeviceBatteryType::PercentVbat100_75),
+        Some(1) => Some(DeviceBatteryType::PercentVbat75_50),
+        Some(_) => Some(DeviceBatteryType::PercentVbat50_25),
+        None if lines.iter().any(LinePattern::is_active) => {
+            Some(DeviceBatteryType::PercentVbat25_0)
+        }
+        None => None,
+    };
+    BatteryStatus {
+        level,
+        state,
+        critical,
+    }
+}
+
+/// 解码一段三根线的采样, 每个元素是同一时刻三根线的电平
+pub fn decode_trace(trace: &[[bool; 3]], sample_interval_ms: u64) -> BatteryStatus {
+    let line = |idx: usize| {
+        let samples: Vec<bool> = trace.iter().map(|s| s[idx]).collect();
+        classify_line(&samples, sample_interval_ms)
+    };
+    decode_battery([line(0), line(1), line(2)])
+}
+
+/// 采样记录的文本格式: 三行分别是三根线, 每个字符是一次采样, '1' 表示采到低电平 (灯亮).
+/// 串口命令 sensor trace 用这个格式输出, 板子上采到的记录可以放进 testdata 测试
+pub fn format_trace(trace: &[[bool; 3]]) -> String {
+    (0..3)
+        .map(|idx| {
+            let line: String = trace
+                .iter()
+                .map(|s| if s[idx] { '1' } else { '0' })
+                .collect();
+            line + "\n"
+        })
+        .collect()
+}
+
+/// 解析 format_trace 的输出, 空行和 # 开头的注释行会被跳过
+pub fn parse_trace(text: &str) -> anyhow::Result<Vec<[bool; 3]>> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let [line_1, line_2, line_3] = lines[..] else {
+        anyhow::bail!("trace needs 3 lines, got {}", lines.len());
+    };
+    if line_1.len() != line_2.len() || line_1.len() != line_3.len() {
+        anyhow::bail!("trace lines have different lengths");
+    }
+    let level = |c: u8| match c {
+        b'0' => Ok(false),
+        b'1' => Ok(true),
+        _ => Err(anyhow::anyhow!("bad sample {:?} in trace", c as char)),
+    };
+    line_1
+        .bytes()
+        .zip(line_2.bytes())
+        .zip(line_3.bytes())
+        .map(|((a, b), c)| Ok([level(a)?, level(b)?, level(c)?]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 把一根线的记录转成 10ms 采样, 每个字符代表 100ms, '1' 表示灯亮.
+    /// 灯亮时模拟 pwm, 只有一半的采样是低电平
+    fn line_samples(pattern: &str) -> Vec<bool> {
+        pattern
+            .chars()
+            .flat_map(|c| (0..10).map(move |i| c == '1' && i % 2 == 0))
+            .collect()
+    }
+
+    fn trace(lines: [&str; 3]) -> Vec<[bool; 3]> {
+        let lines = lines.map(line_samples);
+        (0..lines[0].len())
+            .map(|i| [lines[0][i], lines[1][i], lines[2][i]])
+            .collect()
+    }
+
+    const SOLID: &str = "111111111111111";
+    const OFF: &str = "000000000000000";
+    const SLOW: &str = "111110000011111"; // 亮灭各 500ms
+    const FAST: &str = "110011001100110"; // 亮灭各 200ms
+
+    #[test]
+    fn classify_patterns() {
+        assert_eq!(classify_line(&line_samples(OFF), 10), LinePattern::Off);
+        assert_eq!(classify_line(&line_samples(SOLID), 10), LinePattern::Solid);
+        // 偶尔漏采一个桶还是常亮
+        assert_eq!(
+            classify_line(&line_samples("111111101111111"), 10),
+            LinePattern::Solid
+        );
+        assert!(classify_line(&line_samples(SLOW), 10).is_slow_blink());
+        assert!(classify_line(&line_samples(FAST), 10).is_fast_blink());
+    }
+
+    #[test]
+    fn charging_shows_blinking_next_level() {
+        let status = decode_trace(&trace([SOLID, SLOW, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(status.state, ChargeState::Charging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat50_25));
+        assert!(status.is_charging());
+        assert!(!status.critical);
+    }
+
+    #[test]
+    fn charging_from_empty() {
+        let status = decode_trace(&trace([SLOW, OFF, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(status.state, ChargeState::Charging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat25_0));
+    }
+
+    #[test]
+    fn all_solid_is_full() {
+        let status = decode_trace(&trace([SOLID, SOLID, SOLID]), SAMPLE_INTERVAL_MS);
+        assert_eq!(status.state, ChargeState::Full);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat100));
+        assert!(status.is_charging());
+    }
+
+    #[test]
+    fn discharging_levels() {
+        let status = decode_trace(&trace([SOLID, SOLID, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(status.state, ChargeState::Discharging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat75_50));
+        assert!(!status.is_charging());
+    }
+
+    #[test]
+    fn fast_blink_is_critical() {
+        let status = decode_trace(&trace([FAST, OFF, OFF]), SAMPLE_INTERVAL_MS);
+        assert!(status.critical);
+        assert_eq!(status.state, ChargeState::Discharging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat25_0));
+    }
+
+    #[test]
+    fn status_bits_round_trip() {
+        let status = decode_trace(&trace([FAST, OFF, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(BatteryStatus::from_bits(status.to_bits()), Some(status));
+        let status = decode_trace(&trace([OFF, OFF, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(BatteryStatus::from_bits(status.to_bits()), Some(status));
+        assert_eq!(BatteryStatus::from_bits(0), None);
+    }
+
+    fn decode_file(text: &str) -> BatteryStatus {
+        let trace = parse_trace(text).unwrap();
+        assert_eq!(trace.len() as u64, SAMPLE_WINDOW_MS / SAMPLE_INTERVAL_MS);
+        decode_trace(&trace, SAMPLE_INTERVAL_MS)
+    }
+
+    #[test]
+    fn sample_traces() {
+        let status = decode_file(include_str!("testdata/charging.trace"));
+        assert_eq!(status.state, ChargeState::Charging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat50_25));
+        assert!(!status.critical);
+
+        let status = decode_file(include_str!("testdata/full.trace"));
+        assert_eq!(status.state, ChargeState::Full);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat100));
+
+        let status = decode_file(include_str!("testdata/discharging.trace"));
+        assert_eq!(status.state, ChargeState::Discharging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat75_50));
+
+        let status = decode_file(include_str!("testdata/critical.trace"));
+        assert!(status.critical);
+        assert_eq!(status.state, ChargeState::Discharging);
+        assert_eq!(status.level, Some(DeviceBatteryType::PercentVbat25_0));
+    }
+
+    #[test]
+    fn trace_text_round_trip() {
+        let samples = trace([SOLID, SLOW, OFF]);
+        assert_eq!(parse_trace(&format_trace(&samples)).unwrap(), samples);
+        assert!(parse_trace("0101\n0101").is_err());
+        assert!(parse_trace("0101\n0101\n010").is_err());
+        assert!(parse_trace("0101\n0101\n01x1").is_err());
+    }
+
+    #[test]
+    fn percent_text() {
+        assert_eq!(DeviceBatteryType::PercentVbat100.percent_text(), "100%");
+        assert_eq!(DeviceBatteryType::PercentVbat50_25.percent_text(), "25-50%");
+        assert_eq!(DeviceBatteryType::PercentVbat25_0.percent_text(), "0-25%");
+    }
+
+    #[test]
+    fn all_off_is_unknown() {
+        let status = decode_trace(&trace([OFF, OFF, OFF]), SAMPLE_INTERVAL_MS);
+        assert_eq!(status.state, ChargeState::Unknown);
+        assert_eq!(status.level, None);
+    }
+}
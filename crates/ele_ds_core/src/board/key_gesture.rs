@@ -0,0 +1,422 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeyClickedType {
+    NoClick,
+    SingleClicked,
+    DoubleClicked,
+    TripleClicked,
+    LongPressed,      // 按住超过 hold_ms, 只发送一次
+    HoldRepeat,       // 长按后继续按住, 每隔 repeat_ms 发送一次
+    Released,         // 长按后松开
+    Chord(usize),     // 和另一个按键同时按下, 参数是另一个按键的索引, idx 是较小的索引
+    ChordHeld(usize), // 组合键一直按住超过 chord_hold_ms, 只发送一次, 参数和 Chord 一样
+}
+
+/// 按下按键时发送的消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressedKeyInfo {
+    pub idx: usize,                 // 按键索引, 按照传入的容器顺序
+    pub click_type: KeyClickedType, // 按下按键类型
+}
+
+/// 恢复出厂设置的按键: 左右两个按键同时按住 5 秒, 不能通过按键绑定修改, 绑定配置错了也能恢复.
+/// 只认实体按键, 回放的按键里会被去掉
+pub const FACTORY_RESET_KEYS: PressedKeyInfo = PressedKeyInfo {
+    idx: 0,
+    click_type: KeyClickedType::ChordHeld(2),
+};
+
+/// 手势识别的时间参数, 单位: 毫秒
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    pub debounce_ms: u64,   // 电平保持这么久才算稳定, 按下和松开都消抖
+    pub release_ms: u64,    // 松开后等待下一次按下的时间, 超时后结算点击次数
+    pub hold_ms: u64,       // 按住超过这个时间算长按
+    pub repeat_ms: u64,     // 长按后重复发送 HoldRepeat 的间隔
+    pub chord_hold_ms: u64, // 组合键按住超过这个时间发送 ChordHeld, 用于恢复出厂设置这种不能误触的操作
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 20,
+            release_ms: 150,
+            hold_ms: 500,
+            repeat_ms: 500,
+            chord_hold_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    Pressed { since: u64 },    // 消抖后按下, 还没到长按时间
+    Held { last_repeat: u64 }, // 长按中
+    Released { since: u64 },   // 点击后松开, 等待下一次点击
+}
+
+/// 单个按键的手势状态机, 不依赖硬件, 输入 (时间戳, 电平) 采样输出按键事件, 方便在电脑上测试
+#[derive(Debug, Clone)]
+pub struct KeyGesture {
+    config: GestureConfig,
+    raw_level: bool,    // 最近一次采样的电平, true 表示按下
+    raw_since: u64,     // raw_level 开始的时间
+    stable_level: bool, // 消抖后的电平
+    phase: Phase,
+    clicks: u8,
+}
+
+impl KeyGesture {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            raw_level: false,
+            raw_since: 0,
+            stable_level: false,
+            phase: Phase::Idle,
+            clicks: 0,
+        }
+    }
+
+    /// 消抖后是否处于按下状态
+    pub fn is_down(&self) -> bool {
+        matches!(self.phase, Phase::Pressed { .. } | Phase::Held { .. })
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.phase == Phase::Idle
+    }
+
+    /// 输入一次采样, now 是毫秒时间戳, pressed 为 true 表示按下, 有事件时返回
+    pub fn update(&mut self, now: u64, pressed: bool) -> Option<KeyClickedType> {
+        if pressed != self.raw_level {
+            self.raw_level = pressed;
+            self.raw_since = now;
+        }
+        // 电平稳定超过消抖时间才认为发生了跳变, 跳变时间按电平开始变化的时间算
+        let edge = if self.raw_level != self.stable_level
+            && now.saturating_sub(self.raw_since) >= self.config.debounce_ms
+        {
+            self.stable_level = self.raw_level;
+            Some(self.raw_since)
+        } else {
+            None
+        };
+
+        match (self.phase, edge) {
+            (Phase::Idle, Some(at)) if self.stable_level => {
+                self.clicks = 1;
+                self.phase = Phase::Pressed { since: at };
+                None
+            }
+            (Phase::Pressed { .. }, Some(at)) => {
+                self.phase = Phase::Released { since: at };
+                None
+            }
+            (Phase::Pressed { since }, None) => {
+                // 已经松开但还在消抖时不算长按
+                if !self.raw_level || now.saturating_sub(since) < self.config.hold_ms {
+                    return None;
+                }
+                // 长按不算点击
+                self.clicks = 0;
+                self.phase = Phase::Held { last_repeat: now };
+                Some(KeyClickedType::LongPressed)
+            }
+            (Phase::Held { .. }, Some(_)) => {
+                self.phase = Phase::Idle;
+                Some(KeyClickedType::Released)
+            }
+            (Phase::Held { last_repeat }, None) => {
+                if now.saturating_sub(last_repeat) < self.config.repeat_ms {
+                    return None;
+                }
+                self.phase = Phase::Held { last_repeat: now };
+                Some(KeyClickedType::HoldRepeat)
+            }
+            (Phase::Released { .. }, Some(at)) => {
+                self.clicks = self.clicks.saturating_add(1);
+                self.phase = Phase::Pressed { since: at };
+                None
+            }
+            (Phase::Released { since }, None) => {
+                if now.saturating_sub(since) < self.config.release_ms {
+                    return None;
+                }
+                self.phase = Phase::Idle;
+                match std::mem::take(&mut self.clicks) {
+                    1 => Some(KeyClickedType::SingleClicked),
+                    2 => Some(KeyClickedType::DoubleClicked),
+                    3 => Some(KeyClickedType::TripleClicked),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 正在按下的组合键
+#[derive(Debug, Clone, Copy)]
+struct ChordState {
+    first: usize,
+    second: usize,
+    since: u64,
+    held: bool, // 已经发送过 ChordHeld
+}
+
+/// 多个按键的手势识别, 额外识别两个按键同时按下的组合键
+#[derive(Debug, Clone)]
+pub struct KeyGestureGroup {
+    keys: Vec<KeyGesture>,
+    in_chord: Vec<bool>, // 正在作为组合键按下, 回到空闲前不再发送单键事件
+    chord: Option<ChordState>,
+    chord_hold_ms: u64,
+}
+
+impl KeyGestureGroup {
+    pub fn new(key_num: usize, config: GestureConfig) -> Self {
+        Self {
+            keys: vec![KeyGesture::new(config); key_num],
+            in_chord: vec![false; key_num],
+            chord: None,
+            chord_hold_ms: config.chord_hold_ms,
+        }
+    }
+
+    /// 输入所有按键的一次采样, levels 的顺序和按键索引一致
+    pub fn update(&mut self, now: u64, levels: &[bool]) -> Vec<PressedKeyInfo> {
+        let mut key_msgs = Vec::new();
+        for (idx, (key, pressed)) in self.keys.iter_mut().zip(levels).enumerate() {
+            let click_type = key.update(now, *pressed);
+            if let Some(click_type) = click_type.filter(|_| !self.in_chord[idx]) {
+                key_msgs.push(PressedKeyInfo { idx, click_type });
+            }
+            if key.is_idle() {
+                self.in_chord[idx] = false;
+            }
+        }
+
+        // 两个按键同时按下算组合键, 组合键松开前只发送一次
+        let down_keys: Vec<usize> = (0..self.keys.len())
+            .filter(|idx| self.keys[*idx].is_down())
+            .collect();
+        if let [first, second] = down_keys[..] {
+            if !self.in_chord[first] && !self.in_chord[second] {
+                key_msgs.push(PressedKeyInfo {
+                    idx: first,
+                    click_type: KeyClickedType::Chord(second),
+                });
+            }
+            if self.chord.is_none() {
+                self.in_chord[first] = true;
+                self.in_chord[second] = true;
+                self.chord = Some(ChordState {
+                    first,
+                    second,
+                    since: now,
+                    held: false,
+                });
+            }
+        }
+
+        // 组合键的两个按键一直按住才算, 松开任意一个就重新开始
+        if let Some(chord) = self.chord.as_mut() {
+            if !self.keys[chord.first].is_down() || !self.keys[chord.second].is_down() {
+                self.chord = None;
+            } else if !chord.held && now.saturating_sub(chord.since) >= self.chord_hold_ms {
+                chord.held = true;
+                key_msgs.push(PressedKeyInfo {
+                    idx: chord.first,
+                    click_type: KeyClickedType::ChordHeld(chord.second),
+                });
+            }
+        }
+        key_msgs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 5ms 采样一个按键, edges 是 (时间, 电平) 的跳变点, 返回所有事件和发生时间
+    fn run(edges: &[(u64, bool)], until: u64) -> Vec<(u64, KeyClickedType)> {
+        let mut key = KeyGesture::new(GestureConfig::default());
+        let mut events = Vec::new();
+        for now in (0..=until).step_by(5) {
+            let level = edges
+                .iter()
+                .rev()
+                .find(|(t, _)| *t <= now)
+                .is_some_and(|(_, level)| *level);
+            if let Some(event) = key.update(now, level) {
+                events.push((now, event));
+            }
+        }
+        events
+    }
+
+    fn types(events: &[(u64, KeyClickedType)]) -> Vec<KeyClickedType> {
+        events.iter().map(|(_, t)| *t).collect()
+    }
+
+    #[test]
+    fn single_click() {
+        let events = run(&[(100, true), (200, false)], 600);
+        assert_eq!(types(&events), [KeyClickedType::SingleClicked]);
+        // 松开后等满 release_ms 才结算
+        assert!(events[0].0 >= 200 + 150);
+    }
+
+    #[test]
+    fn double_and_triple_click() {
+        let double = run(&[(100, true), (180, false), (250, true), (330, false)], 800);
+        assert_eq!(types(&double), [KeyClickedType::DoubleClicked]);
+
+        let triple = run(
+            &[
+                (100, true),
+                (180, false),
+                (250, true),
+                (330, false),
+                (400, true),
+                (480, false),
+            ],
+            1000,
+        );
+        assert_eq!(types(&triple), [KeyClickedType::TripleClicked]);
+    }
+
+    #[test]
+    fn too_many_clicks_are_ignored() {
+        let mut edges = Vec::new();
+        for i in 0..4 {
+            edges.push((100 + i * 150, true));
+            edges.push((180 + i * 150, false));
+        }
+        assert!(run(&edges, 1500).is_empty());
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        assert!(run(&[(100, true), (110, false)], 600).is_empty());
+    }
+
+    #[test]
+    fn press_bounce_counts_as_one_click() {
+        // 按下时抖动几次后稳定
+        let edges = [
+            (100, true),
+            (105, false),
+            (110, true),
+            (115, false),
+            (120, true),
+            (250, false),
+        ];
+        assert_eq!(types(&run(&edges, 700)), [KeyClickedType::SingleClicked]);
+    }
+
+    #[test]
+    fn release_bounce_is_not_a_double_click() {
+        // 松开时抖动, 抖动的按下时间小于消抖时间
+        let edges = [
+            (100, true),
+            (200, false),
+            (205, true),
+            (210, false),
+            (215, true),
+            (220, false),
+        ];
+        assert_eq!(types(&run(&edges, 700)), [KeyClickedType::SingleClicked]);
+    }
+
+    #[test]
+    fn long_press_repeat_and_release() {
+        let events = run(&[(100, true), (1700, false)], 2000);
+        assert_eq!(
+            types(&events),
+            [
+                KeyClickedType::LongPressed,
+                KeyClickedType::HoldRepeat,
+                KeyClickedType::HoldRepeat,
+                KeyClickedType::Released,
+            ]
+        );
+        assert_eq!(events[0].0, 600);
+        assert_eq!(events[1].0, 1100);
+    }
+
+    #[test]
+    fn click_then_hold_is_long_press_only() {
+        let events = run(
+            &[(100, true), (180, false), (250, true), (900, false)],
+            1300,
+        );
+        assert_eq!(
+            types(&events),
+            [KeyClickedType::LongPressed, KeyClickedType::Released]
+        );
+    }
+
+    #[test]
+    fn release_just_before_hold_is_click() {
+        let events = run(&[(100, true), (590, false)], 1000);
+        assert_eq!(types(&events), [KeyClickedType::SingleClicked]);
+    }
+
+    #[test]
+    fn chord_suppresses_single_key_events() {
+        let mut group = KeyGestureGroup::new(3, GestureConfig::default());
+        let mut events = Vec::new();
+        for now in (0..=2000).step_by(5) {
+            let left = (100..800).contains(&now);
+            let right = (130..820).contains(&now);
+            events.extend(group.update(now, &[left, false, right]));
+        }
+        assert_eq!(
+            events,
+            [PressedKeyInfo {
+                idx: 0,
+                click_type: KeyClickedType::Chord(2),
+            }]
+        );
+
+        // 组合键松开后单键恢复正常
+        let mut events = Vec::new();
+        for now in (2000..=2600).step_by(5) {
+            events.extend(group.update(now, &[(2100..2200).contains(&now), false, false]));
+        }
+        assert_eq!(
+            events,
+            [PressedKeyInfo {
+                idx: 0,
+                click_type: KeyClickedType::SingleClicked,
+            }]
+        );
+    }
+
+    #[test]
+    fn chord_held() {
+        let mut group = KeyGestureGroup::new(3, GestureConfig::default());
+        let mut events = Vec::new();
+        // 中途松开右键, 重新按下后重新计时
+        for now in (0..=14000).step_by(5) {
+            let left = (100..13000).contains(&now);
+            let right = (100..3000).contains(&now) || (4000..12000).contains(&now);
+            events.extend(group.update(now, &[left, false, right]));
+        }
+        let held: Vec<_> = events
+            .iter()
+            .filter(|event| event.click_type == KeyClickedType::ChordHeld(2))
+            .collect();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].idx, 0);
+        assert!(!events.contains(&PressedKeyInfo {
+            idx: 0,
+            click_type: KeyClickedType::LongPressed,
+        }));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::board::key_gesture::{PressedKeyInfo, FACTORY_RESET_KEYS};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+pub const DEFAULT_KEY_RECORD_PATH: &str = "/fat/record/keys.jsonl"; // 按键录制文件, 每行一条 json
+const MAX_REPLAY_GAP_MS: u64 = 5000; // 回放时两次按键的最大间隔, 跳过深度睡眠这类很长的空闲
+const MAX_RECORD_FILE_SIZE: u64 = 64 * 1024; // 录制文件超过这个大小后换成 .old, 只保留最近两个文件
+
+static REPLAYING: AtomicBool = AtomicBool::new(false); // 回放时不录制, 避免回放的按键再被记录
+
+/// 一条按键记录
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyRecord {
+    pub at_ms: i64, // 按键时间, unix 时间戳毫秒, 跨深度睡眠也能保持顺序
+    #[serde(flatten)]
+    pub key: PressedKeyInfo,
+}
+
+/// 旧的录制文件, 可以用 replay <path> 手动回放
+fn old_record_path(path: &str) -> String {
+    format!("{path}.old")
+}
+
+/// 按键录制, 追加写到文件, 文件太大时换一个新文件
+pub struct KeyRecorder {
+    path: String,
+    file: Option<File>, // 换文件时要先关闭旧文件才能改名
+    size: u64,
+}
+
+impl KeyRecorder {
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        log::info!("key record to: {path}, size: {size}");
+        Ok(Self {
+            path: path.to_string(),
+            file: Some(file),
+            size,
+        })
+    }
+
+    /// 当前文件改名为 .old, 覆盖更早的记录, 再重新创建录制文件
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file = None;
+        let old = old_record_path(&self.path);
+        let _ = fs::remove_file(&old); // fat 上改名的目标不能已经存在
+        fs::rename(&self.path, &old)?;
+        self.file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?,
+        );
+        self.size = 0;
+        log::info!("key record rotated to: {old}");
+        Ok(())
+    }
+
+    pub fn record(&mut self, key: &PressedKeyInfo) -> anyhow::Result<()> {
+        if REPLAYING.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let record = KeyRecord {
+            at_ms: chrono::Local::now().timestamp_millis(),
+            key: key.clone(),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        if self.file.is_none() || self.size + line.len() as u64 > MAX_RECORD_FILE_SIZE {
+            self.rotate()?;
+        }
+        let Some(file) = self.file.as_mut() else {
+            anyhow::bail!("key record file not open");
+        };
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// 解析录制内容, 空行和 # 开头的注释行会被跳过, 方便手写脚本.
+/// 恢复出厂设置的组合键只能用实体按键触发, 回放时去掉
+pub fn parse_key_records(content: &str) -> anyhow::Result<Vec<KeyRecord>> {
+    let records = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("parse key record failed: {e}, line: {line}"))
+        })
+        .collect::<anyhow::Result<Vec<KeyRecord>>>()?;
+    let (records, reset): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|record| record.key != FACTORY_RESET_KEYS);
+    if !reset.is_empty() {
+        log::warn!("skip {} factory reset keys in replay", reset.len());
+    }
+    Ok(records)
+}
+
+/// 计算每条记录回放前要等待的时间, 第一条不等待, 间隔过长的按 MAX_REPLAY_GAP_MS 处理
+pub fn replay_delays(records: &[KeyRecord]) -> Vec<Duration> {
+    let mut last_at = records.first().map_or(0, |r| r.at_ms);
+    records
+        .iter()
+        .map(|r| {
+            let gap = r.at_ms.saturating_sub(last_at).max(0) as u64;
+            last_at = r.at_ms;
+            Duration::from_millis(gap.min(MAX_REPLAY_GAP_MS))
+        })
+        .collect()
+}
+
+/// 在新线程里按原来的时间间隔把按键注入按键通道
+pub fn replay_key_records(
+    records: Vec<KeyRecord>,
+    key_tx: Sender<PressedKeyInfo>,
+) -> anyhow::Result<()> {
+    if REPLAYING.swap(true, Ordering::Relaxed) {
+        anyhow::bail!("key replay already running");
+    }
+    log::info!("start replay {} keys", records.len());
+    std::thread::Builder::new()
+        .name(String::from("key_replay"))
+        .spawn(move || {
+            for (delay, record) in replay_delays(&records).into_iter().zip(records) {
+                std::thread::sleep(delay);
+                if let Err(e) = key_tx.send(record.key) {
+                    log::warn!("replay key send failed: {e:?}");
+                    break;
+                }
+            }
+            REPLAYING.store(false, Ordering::Relaxed);
+            log::info!("key replay end");
+        })
+        .inspect_err(|_| REPLAYING.store(false, Ordering::Relaxed))?;
+    Ok(())
+}
+
+/// 回放录制文件
+pub fn replay_key_file(path: &str, key_tx: Sender<PressedKeyInfo>) -> anyhow::Result<()> {
+    let content = fs::read_to_string(path)?;
+    replay_key_records(parse_key_records(&content)?, key_tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::key_gesture::KeyClickedType;
+
+    fn record(at_ms: i64) -> KeyRecord {
+        KeyRecord {
+            at_ms,
+            key: PressedKeyInfo {
+                idx: 0,
+                click_type: KeyClickedType::SingleClicked,
+            },
+        }
+    }
+
+    #[test]
+    fn parse_records() {
+        let content = r#"
+            # 手写的回放脚本
+            {"at_ms": 100, "idx": 1, "click_type": "DoubleClicked"}
+
+            {"at_ms": 300, "idx": 2, "click_type": {"Chord": 0}}
+        "#;
+        let records = parse_key_records(content).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].key.idx, 1);
+        assert_eq!(records[0].key.click_type, KeyClickedType::DoubleClicked);
+        assert_eq!(records[1].key.click_type, KeyClickedType::Chord(0));
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        assert!(parse_key_records(r#"{"at_ms": 100, "idx": 1"#).is_err());
+        assert!(parse_key_records(r#"{"at_ms": 100, "idx": 1, "click_type": "Swipe"}"#).is_err());
+        // 有一行错误就整个文件都不回放
+        let err = parse_key_records(
+            "{\"at_ms\": 1, \"idx\": 0, \"click_type\": \"SingleClicked\"}\nnot json",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("not json"));
+        assert!(parse_key_records("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn factory_reset_not_replayed() {
+        let content = r#"
+            {"at_ms": 100, "idx": 0, "click_type": {"ChordHeld": 2}}
+            {"at_ms": 200, "idx": 0, "click_type": {"Chord": 2}}
+        "#;
+        let records = parse_key_records(content).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key.click_type, KeyClickedType::Chord(2));
+    }
+
+    #[test]
+    fn delays() {
+        assert!(replay_delays(&[]).is_empty());
+        let records = [record(1000), record(1250), record(100_000), record(90_000)];
+        assert_eq!(
+            replay_delays(&records),
+            [
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_millis(MAX_REPLAY_GAP_MS), // 中间睡眠过
+                Duration::ZERO,                           // 时间被调整过, 不等待
+            ]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod battery_decoder;
+pub mod key_gesture;
+pub mod key_record;
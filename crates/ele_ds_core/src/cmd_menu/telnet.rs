@@ -0,0 +1,70 @@
+// telnet 协议只处理最少的部分: 去掉客户端发来的协商命令, 让客户端关闭本地回显并逐字符发送
+const IAC: u8 = 255;
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// 连接后发给客户端, 由设备回显, 输入密码时不回显
+pub const NEGOTIATION: [u8; 6] = [IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Data,
+    Iac,    // 收到 IAC, 下一个字节是命令
+    Option, // WILL/WONT/DO/DONT 后面的选项
+    Sub,    // 子协商内容, 直到 IAC SE
+    SubIac,
+}
+
+#[derive(Debug, Default)]
+pub struct TelnetFilter {
+    state: State,
+}
+
+impl TelnetFilter {
+    /// 输入一个收到的字节, 返回去掉协商命令后的数据, 回车后面的 \0 也去掉
+    pub fn push(&mut self, byte: u8) -> Option<u8> {
+        let (state, data) = match (self.state, byte) {
+            (State::Data, IAC) => (State::Iac, None),
+            (State::Data, 0) => (State::Data, None),
+            (State::Data, byte) => (State::Data, Some(byte)),
+            (State::Iac, IAC) => (State::Data, Some(IAC)), // 转义的 255
+            (State::Iac, WILL | WONT | DO | DONT) => (State::Option, None),
+            (State::Iac, SB) => (State::Sub, None),
+            (State::Iac, _) => (State::Data, None),
+            (State::Option, _) => (State::Data, None),
+            (State::Sub, IAC) => (State::SubIac, None),
+            (State::Sub, _) => (State::Sub, None),
+            (State::SubIac, SE) => (State::Data, None),
+            (State::SubIac, _) => (State::Sub, None),
+        };
+        self.state = state;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(input: &[u8]) -> Vec<u8> {
+        let mut filter = TelnetFilter::default();
+        input.iter().filter_map(|byte| filter.push(*byte)).collect()
+    }
+
+    #[test]
+    fn strip_negotiation() {
+        // putty 连接时发送的协商和窗口大小子协商
+        let input = [
+            IAC, DO, ECHO, b'l', IAC, SB, 31, 0, 80, 0, 24, IAC, SE, b's', b'\r', 0, IAC, IAC,
+        ];
+        assert_eq!(filter(&input), vec![b'l', b's', b'\r', IAC]);
+        assert_eq!(filter(b"help\r\n"), b"help\r\n".to_vec());
+    }
+}
@@ -0,0 +1,261 @@
+// 配网模式用到的协议处理, 不依赖硬件: 所有域名都解析到设备自己的 dns 应答,
+// 配网表单的解析和校验, 以及表单页面和手机扫码加入热点的二维码内容
+use crate::device_config::config_patch::{check_text, check_time_zone};
+use percent_encoding::percent_decode_str;
+use std::net::Ipv4Addr;
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_TTL: u32 = 60; // 秒, 配网结束后手机很快就会重新解析
+
+/// 对 dns 查询回复 ip, 只回答 A 和 ANY 查询, 其他类型回复空结果. 不是合法查询时返回 None
+pub fn dns_reply(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < DNS_HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let qd_count = u16::from_be_bytes([query[4], query[5]]);
+    // 只处理标准查询
+    if flags & 0x8000 != 0 || (flags >> 11) & 0x0f != 0 || qd_count == 0 {
+        return None;
+    }
+    // 只回答第一个问题
+    let mut pos = DNS_HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xc0 != 0 {
+            return None; // 查询里的名字不会是压缩指针
+        }
+        pos += 1 + len;
+    }
+    let question_end = pos + 4;
+    let question = query.get(DNS_HEADER_LEN..question_end)?;
+    let qtype = u16::from_be_bytes([query[pos], query[pos + 1]]);
+    let answer = qtype == DNS_TYPE_A || qtype == DNS_TYPE_ANY;
+
+    let mut reply = Vec::with_capacity(question_end + 16);
+    reply.extend_from_slice(&query[0..2]); // id
+    reply.extend_from_slice(&(0x8080 | (flags & 0x0100)).to_be_bytes()); // 回复, 保留 RD 并设置 RA
+    reply.extend_from_slice(&1u16.to_be_bytes()); // 问题数
+    reply.extend_from_slice(&(answer as u16).to_be_bytes()); // 回答数
+    reply.extend_from_slice(&[0, 0, 0, 0]); // 授权和附加记录数
+    reply.extend_from_slice(question);
+    if answer {
+        reply.extend_from_slice(&0xc00cu16.to_be_bytes()); // 指向问题里的名字
+        reply.extend_from_slice(&DNS_TYPE_A.to_be_bytes());
+        reply.extend_from_slice(&1u16.to_be_bytes()); // IN
+        reply.extend_from_slice(&DNS_TTL.to_be_bytes());
+        reply.extend_from_slice(&4u16.to_be_bytes());
+        reply.extend_from_slice(&ip.octets());
+    }
+    Some(reply)
+}
+
+/// 解析 application/x-www-form-urlencoded 的表单
+pub fn parse_form(body: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .to_string()
+    };
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+/// 配网表单提交的内容, 城市和时区为空时不修改
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionForm {
+    pub ssid: String,
+    pub password: String,
+    pub city_name: Option<String>,
+    pub time_zone: Option<String>,
+}
+
+impl ProvisionForm {
+    pub fn from_pairs(pairs: &[(String, String)]) -> anyhow::Result<Self> {
+        let field = |name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let Some(ssid) = field("ssid") else {
+            anyhow::bail!("Wi-Fi name is required");
+        };
+        if ssid.len() > 32 {
+            anyhow::bail!("Wi-Fi name is longer than 32 bytes");
+        }
+        // 密码前后的空格也是密码的一部分, 不去掉
+        let password = pairs
+            .iter()
+            .find(|(key, _)| key == "password")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        if !password.is_empty() && !(8..=63).contains(&password.len()) {
+            anyhow::bail!("Wi-Fi password must be 8 to 63 characters, or empty for open networks");
+        }
+        // 和网页修改配置用同样的检查, 空着表示不修改
+        let time_zone = field("time_zone");
+        if let Some(time_zone) = &time_zone {
+            check_time_zone(time_zone)?;
+        }
+        let city_name = field("city_name");
+        if let Some(city_name) = &city_name {
+            check_text("city_name", city_name, 64)?;
+        }
+        Ok(Self {
+            ssid,
+            password,
+            city_name,
+            time_zone,
+        })
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// 配网表单页面, 扫描到的网络作为输入提示, message 是上次提交的结果
+pub fn portal_page(
+    scanned: &[String],
+    city_name: &str,
+    time_zone: &str,
+    message: Option<&str>,
+) -> String {
+    let mut html = String::new();
+    html.push_str("<html><head><meta charset='utf-8'>");
+    html.push_str("<meta name='viewport' content='width=device-width,initial-scale=1'>");
+    html.push_str("<title>Device setup</title>");
+    html.push_str("<style>body{font-family:sans-serif;padding:20px;max-width:420px;margin:auto;}\
+                   input{width:100%;padding:8px;margin:4px 0 12px;box-sizing:border-box;}\
+                   .msg{border:1px solid #ff4444;background:#fff5f5;padding:10px;border-radius:5px;}</style>");
+    html.push_str("</head><body><h2>Device setup</h2>");
+    if let Some(message) = message {
+        html.push_str(&format!("<p class='msg'>{}</p>", html_escape(message)));
+    }
+    html.push_str("<form method='post' action='/save'>");
+    html.push_str("<label>Wi-Fi name</label><input name='ssid' list='networks' required>");
+    html.push_str("<datalist id='networks'>");
+    for ssid in scanned {
+        html.push_str(&format!("<option value=\"{}\">", html_escape(ssid)));
+    }
+    html.push_str("</datalist>");
+    html.push_str("<label>Wi-Fi password</label><input name='password' type='password'>");
+    html.push_str(&format!(
+        "<label>City</label><input name='city_name' value=\"{}\">",
+        html_escape(city_name)
+    ));
+    html.push_str(&format!(
+        "<label>Time zone (POSIX, e.g. CST-8)</label><input name='time_zone' value=\"{}\">",
+        html_escape(time_zone)
+    ));
+    html.push_str("<input type='submit' value='Save and restart'></form></body></html>");
+    html
+}
+
+/// 手机扫码加入开放热点的二维码内容
+pub fn wifi_qr_text(ssid: &str) -> String {
+    let mut escaped = String::new();
+    for c in ssid.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    format!("WIFI:T:nopass;S:{escaped};;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// id 0x1234, RD, 一个问题 connectivitycheck.gstatic.com A IN
+    fn query(qtype: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in ["connectivitycheck", "gstatic", "com"] {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&1u16.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn dns_answers_with_own_ip() {
+        let ip = Ipv4Addr::new(192, 168, 71, 1);
+        let a_query = query(DNS_TYPE_A);
+        let reply = dns_reply(&a_query, ip).unwrap();
+        assert_eq!(&reply[0..2], &[0x12, 0x34]);
+        assert_eq!(&reply[2..4], &[0x81, 0x80]);
+        assert_eq!(&reply[6..8], &[0, 1]);
+        assert_eq!(&reply[12..a_query.len()], &a_query[12..]);
+        assert_eq!(&reply[reply.len() - 4..], &[192, 168, 71, 1]);
+
+        // AAAA 查询回复空结果, 手机会继续用 A 记录
+        let reply = dns_reply(&query(28), ip).unwrap();
+        assert_eq!(&reply[6..8], &[0, 0]);
+        assert_eq!(reply.len(), a_query.len());
+    }
+
+    #[test]
+    fn dns_rejects_garbage() {
+        let ip = Ipv4Addr::new(192, 168, 71, 1);
+        assert!(dns_reply(&[0; 5], ip).is_none());
+        let query = query(DNS_TYPE_A);
+        assert!(dns_reply(&query[..query.len() - 2], ip).is_none());
+        let mut response = query.clone();
+        response[2] |= 0x80;
+        assert!(dns_reply(&response, ip).is_none());
+    }
+
+    #[test]
+    fn parse_provision_form() {
+        let pairs = parse_form(
+            "ssid=My+Home%2B&password=%20secret%20pw&city_name=%E7%A6%8F%E5%B7%9E&time_zone=",
+        );
+        let form = ProvisionForm::from_pairs(&pairs).unwrap();
+        assert_eq!(form.ssid, "My Home+");
+        assert_eq!(form.password, " secret pw");
+        assert_eq!(form.city_name.as_deref(), Some("福州"));
+        assert_eq!(form.time_zone, None);
+
+        let open = ProvisionForm::from_pairs(&parse_form("ssid=cafe&password=")).unwrap();
+        assert!(open.password.is_empty());
+        assert!(ProvisionForm::from_pairs(&parse_form("ssid=&password=12345678")).is_err());
+        assert!(ProvisionForm::from_pairs(&parse_form("ssid=home&password=short")).is_err());
+
+        let form =
+            ProvisionForm::from_pairs(&parse_form("ssid=home&time_zone=%3C%2B08%3E-8")).unwrap();
+        assert_eq!(form.time_zone.as_deref(), Some("<+08>-8"));
+        let long_city = format!("city_name={}", "a".repeat(65));
+        for bad in ["time_zone=8", "time_zone=CST-8+%E4%B8%AD", &long_city] {
+            let pairs = parse_form(&format!("ssid=home&{bad}"));
+            assert!(ProvisionForm::from_pairs(&pairs).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn escape_user_text() {
+        let page = portal_page(&["<b>\"x\"".to_string()], "Fuzhou", "CST-8", None);
+        assert!(page.contains("&lt;b&gt;&quot;x&quot;"));
+        assert_eq!(wifi_qr_text("a;b"), "WIFI:T:nopass;S:a\\;b;;");
+    }
+}
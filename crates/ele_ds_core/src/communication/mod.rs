@@ -0,0 +1 @@
+pub mod captive_portal;
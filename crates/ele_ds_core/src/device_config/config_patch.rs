@@ -0,0 +1,324 @@
+// 通过 http 修改配置. 请求里只带要修改的字段, 全部校验通过后才写到配置里,
+// 返回给 http 的配置去掉所有密码和 key
+use crate::device_config::key_binding::{KeyAction, KeyBinding};
+use crate::device_config::power_policy::PowerPolicyConfig;
+use crate::device_config::wifi_networks::WifiNetwork;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const KEY_COUNT: usize = 3; // 左, 中, 右三个按键
+
+/// PATCH /api/config 的请求体, 没有出现的字段不修改. wifi_networks 整个替换, 没带密码的网络保留原来的密码.
+/// 导出配置也用这个格式, 导出时所有字段都有值
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPatch {
+    pub device_name: Option<String>, // 空字符串表示用默认名称
+    pub time_zone: Option<String>,
+    pub city_name: Option<String>,
+    pub city_name_show: Option<String>,
+    pub weather_api_key: Option<String>,
+    pub user_password: Option<String>, // 远程命令行的登录密码, 空字符串表示关闭远程命令行. http 修改时要带旧密码认证
+    pub requery_upgrade_time_minutes: Option<u32>,
+    pub wifi_max_link_time: Option<u8>,
+    pub wifi_connect_interval: Option<u32>,
+    pub provision_after_failures: Option<u32>,
+    pub muted: Option<bool>,
+    pub key_record_enable: Option<bool>,
+    pub power_policy: Option<PowerPolicyConfig>,
+    pub key_bindings: Option<Vec<KeyBinding>>,
+    pub wifi_networks: Option<Vec<WifiNetwork>>,
+}
+
+/// 修改配置后需要做的处理
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PatchEffects {
+    pub time_zone: bool, // 重新设置系统时区
+    pub weather: bool,   // 城市或者 key 变了, 重新获取天气
+    pub refresh: bool,   // 刷新当前页面
+    pub mdns: bool,      // 设备名称变了, 重新广播 mdns
+}
+
+/// posix 时区, 比如 CST-8, <+08>-8, 这里只检查大概的格式, 写错时 libc 按 UTC 处理
+pub(crate) fn check_time_zone(time_zone: &str) -> anyhow::Result<()> {
+    let starts_ok = time_zone
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '<');
+    if !(3..=32).contains(&time_zone.len())
+        || !starts_ok
+        || !time_zone.chars().all(|c| c.is_ascii_graphic())
+    {
+        anyhow::bail!("time_zone must be a POSIX TZ string like CST-8");
+    }
+    Ok(())
+}
+
+/// 设备名称用作 mdns 主机名, 只能是字母, 数字和中间的 -, 空字符串表示用默认名称
+fn check_device_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    if name.len() > 63
+        || name.starts_with('-')
+        || name.ends_with('-')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        anyhow::bail!("device_name must be up to 63 letters, digits or inner '-'");
+    }
+    Ok(())
+}
+
+pub(crate) fn check_text(name: &str, value: &str, max_len: usize) -> anyhow::Result<()> {
+    if value.trim().is_empty() || value.len() > max_len {
+        anyhow::bail!("{name} must be 1 to {max_len} bytes");
+    }
+    Ok(())
+}
+
+fn check_power_policy(policy: &PowerPolicyConfig) -> anyhow::Result<()> {
+    let minutes = [
+        ("refresh_minutes", policy.refresh_minutes),
+        (
+            "low_battery_refresh_minutes",
+            policy.low_battery_refresh_minutes,
+        ),
+        ("low_battery_wifi_factor", policy.low_battery_wifi_factor),
+        ("critical_sleep_minutes", policy.critical_sleep_minutes),
+        ("night_sleep_minutes", policy.night_sleep_minutes),
+        ("idle_sleep_minutes", policy.idle_sleep_minutes),
+    ];
+    if let Some((name, _)) = minutes.iter().find(|(_, value)| *value == 0) {
+        anyhow::bail!("power_policy.{name} must be greater than 0");
+    }
+    if policy.low_battery_percent > 100 {
+        anyhow::bail!("power_policy.low_battery_percent must be 0 to 100");
+    }
+    if policy.night_start_hour > 23 || policy.night_end_hour > 23 {
+        anyhow::bail!("power_policy night hours must be 0 to 23");
+    }
+    Ok(())
+}
+
+fn check_key_bindings(bindings: &[KeyBinding]) -> anyhow::Result<()> {
+    for binding in bindings {
+        if binding.button >= KEY_COUNT {
+            anyhow::bail!("key_bindings: button {} does not exist", binding.button);
+        }
+        if matches!(&binding.action, KeyAction::PlaySound(path) if !path.starts_with("/fat/")) {
+            anyhow::bail!("key_bindings: sound file must be under /fat/");
+        }
+    }
+    Ok(())
+}
+
+fn check_wifi_networks(networks: &[WifiNetwork]) -> anyhow::Result<()> {
+    for (i, network) in networks.iter().enumerate() {
+        check_text("wifi_networks.ssid", &network.ssid, 32)?;
+        if networks[..i].iter().any(|other| other.ssid == network.ssid) {
+            anyhow::bail!("wifi_networks: duplicate ssid {}", network.ssid);
+        }
+        let password = network.password.expose();
+        if !network.password.is_missing()
+            && !password.is_empty()
+            && !(8..=63).contains(&password.len())
+        {
+            anyhow::bail!(
+                "wifi_networks: password of {} must be 8 to 63 characters, or empty",
+                network.ssid
+            );
+        }
+        if let Some(static_ip) = &network.static_ip {
+            if !(1..=32).contains(&static_ip.prefix_len) {
+                anyhow::bail!(
+                    "wifi_networks: prefix_len of {} must be 1 to 32",
+                    network.ssid
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+impl ConfigPatch {
+    /// 检查所有字段, 有一个不合法就整个请求都不生效
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(device_name) = &self.device_name {
+            check_device_name(device_name)?;
+        }
+        if let Some(time_zone) = &self.time_zone {
+            check_time_zone(time_zone)?;
+        }
+        if let Some(city_name) = &self.city_name {
+            check_text("city_name", city_name, 64)?;
+        }
+        if let Some(city_name_show) = &self.city_name_show {
+            check_text("city_name_show", city_name_show, 32)?;
+            // 屏幕字体只能显示英文
+            if !city_name_show.is_ascii() {
+                anyhow::bail!("city_name_show must be ASCII");
+            }
+        }
+        if let Some(key) = &self.weather_api_key {
+            check_text("weather_api_key", key, 64)?;
+        }
+        if let Some(password) = &self.user_password {
+            if !password.is_empty() && !(8..=63).contains(&password.len()) {
+                anyhow::bail!("user_password must be 8 to 63 bytes, or empty to disable");
+            }
+        }
+        if self.requery_upgrade_time_minutes == Some(0) {
+            anyhow::bail!("requery_upgrade_time_minutes must be greater than 0");
+        }
+        if let Some(time) = self.wifi_max_link_time {
+            if !(5..=120).contains(&time) {
+                anyhow::bail!("wifi_max_link_time must be 5 to 120 seconds");
+            }
+        }
+        if let Some(policy) = &self.power_policy {
+            check_power_policy(policy)?;
+        }
+        if let Some(bindings) = &self.key_bindings {
+            check_key_bindings(bindings)?;
+        }
+        if let Some(networks) = &self.wifi_networks {
+            check_wifi_networks(networks)?;
+        }
+        Ok(())
+    }
+
+    /// wifi, 按键和更新间隔下次用到时自然生效, 不需要额外处理
+    pub fn effects(&self) -> PatchEffects {
+        let time_zone = self.time_zone.is_some();
+        let weather = self.city_name.is_some() || self.weather_api_key.is_some();
+        let mdns = self.device_name.is_some();
+        PatchEffects {
+            time_zone,
+            weather,
+            refresh: time_zone
+                || weather
+                || mdns
+                || self.city_name_show.is_some()
+                || self.power_policy.is_some(),
+            mdns,
+        }
+    }
+}
+
+/// 去掉配置里的密码和 key. 正常情况下它们已经在加密 nvs 里, 不会序列化出来, 存不进 nvs 时才会是明文
+pub fn redact(config: &mut Value) {
+    let Some(map) = config.as_object_mut() else {
+        return;
+    };
+    map.remove("weather_api_key");
+    if let Some(user_info) = map.get_mut("user_info").and_then(Value::as_object_mut) {
+        user_info.remove("password");
+    }
+    if let Some(networks) = map.get_mut("wifi_networks").and_then(Value::as_array_mut) {
+        for network in networks.iter_mut().filter_map(Value::as_object_mut) {
+            network.remove("password");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_config::secret::Secret;
+    use serde_json::json;
+
+    fn patch(value: Value) -> anyhow::Result<ConfigPatch> {
+        let patch: ConfigPatch = serde_json::from_value(value)?;
+        patch.validate()?;
+        Ok(patch)
+    }
+
+    #[test]
+    fn validate_fields() {
+        let ok =
+            patch(json!({"time_zone": "<+08>-8", "city_name": "福州", "muted": true})).unwrap();
+        assert_eq!(
+            ok.effects(),
+            PatchEffects {
+                time_zone: true,
+                weather: true,
+                refresh: true,
+                mdns: false
+            }
+        );
+        assert_eq!(
+            patch(json!({"muted": false})).unwrap().effects(),
+            PatchEffects::default()
+        );
+
+        assert!(
+            patch(json!({"device_name": "kitchen-ds"}))
+                .unwrap()
+                .effects()
+                .mdns
+        );
+        assert!(patch(json!({"device_name": ""})).is_ok());
+        assert!(patch(json!({"device_name": "-kitchen"})).is_err());
+        assert!(patch(json!({"device_name": "kitchen.local"})).is_err());
+        assert!(patch(json!({"time_zone": "8"})).is_err());
+        assert!(patch(json!({"city_name_show": "福州"})).is_err());
+        assert!(patch(json!({"wifi_max_link_time": 1})).is_err());
+        assert!(patch(json!({"user_password": "1234"})).is_err());
+        assert!(patch(json!({"user_password": ""})).is_ok());
+        assert!(patch(json!({"boot_times": 3})).is_err()); // 运行状态不能通过 http 修改
+        assert!(patch(json!({"power_policy": {"refresh_minutes": 0}})).is_err());
+        assert!(patch(json!({"key_bindings": [
+            {"button": 3, "gesture": "SingleClicked", "page": null, "action": "FullRefresh"}
+        ]}))
+        .is_err());
+    }
+
+    #[test]
+    fn validate_wifi_networks() {
+        let ok = patch(json!({"wifi_networks": [
+            {"ssid": "home", "priority": 1},
+            {"ssid": "cafe", "password": ""},
+            {"ssid": "office", "password": "12345678"}
+        ]}))
+        .unwrap();
+        assert_eq!(ok.wifi_networks.unwrap().len(), 3);
+        assert!(patch(json!({"wifi_networks": [{"ssid": "a"}, {"ssid": "a"}]})).is_err());
+        assert!(patch(json!({"wifi_networks": [{"ssid": "a", "password": "short"}]})).is_err());
+    }
+
+    #[test]
+    fn export_round_trip() {
+        // 导出的密码是明文, 导入后和 PATCH 一样存进 nvs
+        let exported = ConfigPatch {
+            time_zone: Some("CST-8".to_string()),
+            user_password: Some(String::new()),
+            wifi_networks: Some(vec![WifiNetwork::new("home", Secret::new("12345678"))]),
+            ..Default::default()
+        };
+        let text = serde_json::to_string(&exported).unwrap();
+        let imported = patch(serde_json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(imported.time_zone.as_deref(), Some("CST-8"));
+        let networks = imported.wifi_networks.unwrap();
+        assert!(networks[0].password.is_plain());
+        assert_eq!(networks[0].password.expose(), "12345678");
+    }
+
+    #[test]
+    fn redact_secrets() {
+        let mut config = json!({
+            "city_name": "Fuzhou",
+            "weather_api_key": "key",
+            "user_info": {"username": "me", "password": "pw"},
+            "wifi_networks": [{"ssid": "home", "password": "12345678"}]
+        });
+        redact(&mut config);
+        assert_eq!(
+            config,
+            json!({
+                "city_name": "Fuzhou",
+                "user_info": {"username": "me"},
+                "wifi_networks": [{"ssid": "home"}]
+            })
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::board::key_gesture::{KeyClickedType, PressedKeyInfo};
+use crate::ActivePage;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MUSIC_PATH: &str = "/fat/system/audio/audio.wav";
+
+/// 按键可以触发的动作
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum KeyAction {
+    SwitchPage(ActivePage),
+    ReconnectWifi,
+    PlaySound(String), // 播放音乐, 路径
+    ToggleMute,        // 切换按键和弹窗提示音
+    OtaCheck,
+    FullRefresh,
+    TestPopup,
+    NotificationScroll(i32), // 通知页面滚动, 负数向上
+    NotificationClear,
+    StartProvisioning, // 重启进入配网模式
+}
+
+impl KeyAction {
+    /// 执行动作前是否需要响一下提示音, 播放音乐时不响避免打断
+    pub fn need_beep(&self) -> bool {
+        !matches!(self, KeyAction::PlaySound(_))
+    }
+}
+
+/// 一条按键绑定, (按键, 手势) 映射到动作
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyBinding {
+    pub button: usize,            // 按键索引, 0: 左, 1: 中, 2: 右
+    pub gesture: KeyClickedType,  // 按键手势
+    pub page: Option<ActivePage>, // 只在这个页面生效, None 表示所有页面, 页面绑定优先
+    pub action: KeyAction,
+}
+
+impl KeyBinding {
+    pub fn new(button: usize, gesture: KeyClickedType, action: KeyAction) -> Self {
+        Self {
+            button,
+            gesture,
+            page: None,
+            action,
+        }
+    }
+
+    pub fn on_page(mut self, page: ActivePage) -> Self {
+        self.page = Some(page);
+        self
+    }
+}
+
+/// 默认按键绑定
+pub fn default_key_bindings() -> Vec<KeyBinding> {
+    use KeyClickedType::*;
+    vec![
+        KeyBinding::new(0, SingleClicked, KeyAction::SwitchPage(ActivePage::Sensor)),
+        KeyBinding::new(1, SingleClicked, KeyAction::SwitchPage(ActivePage::Home)),
+        KeyBinding::new(2, SingleClicked, KeyAction::SwitchPage(ActivePage::Image)),
+        KeyBinding::new(0, DoubleClicked, KeyAction::TestPopup),
+        KeyBinding::new(
+            1,
+            DoubleClicked,
+            KeyAction::SwitchPage(ActivePage::Notification),
+        ),
+        KeyBinding::new(2, DoubleClicked, KeyAction::TestPopup),
+        KeyBinding::new(0, TripleClicked, KeyAction::ReconnectWifi),
+        KeyBinding::new(1, TripleClicked, KeyAction::SwitchPage(ActivePage::About)),
+        KeyBinding::new(
+            2,
+            TripleClicked,
+            KeyAction::PlaySound(DEFAULT_MUSIC_PATH.to_string()),
+        ),
+        KeyBinding::new(0, LongPressed, KeyAction::StartProvisioning),
+        KeyBinding::new(1, LongPressed, KeyAction::FullRefresh),
+        // 通知页面, 左右单击和其他页面一样切换页面, 双击滚动
+        KeyBinding::new(0, DoubleClicked, KeyAction::NotificationScroll(-1))
+            .on_page(ActivePage::Notification),
+        KeyBinding::new(2, DoubleClicked, KeyAction::NotificationScroll(1))
+            .on_page(ActivePage::Notification),
+        KeyBinding::new(1, DoubleClicked, KeyAction::NotificationClear)
+            .on_page(ActivePage::Notification),
+    ]
+}
+
+/// 查找按键对应的动作, 当前页面的绑定优先于通用绑定
+pub fn find_key_action<'a>(
+    bindings: &'a [KeyBinding],
+    key_info: &PressedKeyInfo,
+    cur_page: ActivePage,
+) -> Option<&'a KeyAction> {
+    let matched = |b: &&KeyBinding| b.button == key_info.idx && b.gesture == key_info.click_type;
+    bindings
+        .iter()
+        .filter(matched)
+        .find(|b| b.page == Some(cur_page))
+        .or_else(|| bindings.iter().filter(matched).find(|b| b.page.is_none()))
+        .map(|b| &b.action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyClickedType::*;
+
+    fn key(idx: usize, click_type: KeyClickedType) -> PressedKeyInfo {
+        PressedKeyInfo { idx, click_type }
+    }
+
+    #[test]
+    fn default_bindings() {
+        let bindings = default_key_bindings();
+        assert_eq!(
+            find_key_action(&bindings, &key(1, SingleClicked), ActivePage::Sensor),
+            Some(&KeyAction::SwitchPage(ActivePage::Home))
+        );
+        // 通知页面的绑定只在通知页面生效
+        assert_eq!(
+            find_key_action(&bindings, &key(1, DoubleClicked), ActivePage::Notification),
+            Some(&KeyAction::NotificationClear)
+        );
+        assert_eq!(
+            find_key_action(&bindings, &key(1, DoubleClicked), ActivePage::Home),
+            Some(&KeyAction::SwitchPage(ActivePage::Notification))
+        );
+        // 通知页面左右单击也是切换页面
+        assert_eq!(
+            find_key_action(&bindings, &key(0, SingleClicked), ActivePage::Notification),
+            Some(&KeyAction::SwitchPage(ActivePage::Sensor))
+        );
+    }
+
+    #[test]
+    fn page_binding_overrides() {
+        let bindings = vec![
+            KeyBinding::new(2, SingleClicked, KeyAction::FullRefresh),
+            KeyBinding::new(2, SingleClicked, KeyAction::ToggleMute).on_page(ActivePage::Image),
+        ];
+        assert_eq!(
+            find_key_action(&bindings, &key(2, SingleClicked), ActivePage::Image),
+            Some(&KeyAction::ToggleMute)
+        );
+        assert_eq!(
+            find_key_action(&bindings, &key(2, SingleClicked), ActivePage::Home),
+            Some(&KeyAction::FullRefresh)
+        );
+    }
+
+    #[test]
+    fn unbound_key() {
+        let bindings = default_key_bindings();
+        assert_eq!(
+            find_key_action(&bindings, &key(2, LongPressed), ActivePage::Home),
+            None
+        );
+        assert_eq!(
+            find_key_action(&[], &key(0, SingleClicked), ActivePage::Home),
+            None
+        );
+        // 只绑定在其他页面的按键不生效
+        let bindings =
+            vec![KeyBinding::new(0, HoldRepeat, KeyAction::OtaCheck).on_page(ActivePage::About)];
+        assert_eq!(
+            find_key_action(&bindings, &key(0, HoldRepeat), ActivePage::Home),
+            None
+        );
+    }
+}
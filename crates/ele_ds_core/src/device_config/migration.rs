@@ -0,0 +1,222 @@
+// 配置文件的版本升级. 在 serde_json::Value 上修改, 不依赖 DeviceConfig 当前的结构,
+// 旧版本的配置按顺序一步一步升级到 CONFIG_VERSION, 之后再交给 serde 解析
+use crate::ActivePage;
+use serde_json::{Map, Value};
+
+pub const CONFIG_VERSION: u32 = 3; // 当前配置文件的版本, 字段改名或者改变含义时加一, 并增加迁移函数
+const VERSION_KEY: &str = "config_version";
+
+type Migration = fn(&mut Map<String, Value>);
+
+// v3 以前和设置一起保存在配置文件里的运行状态和天气, 现在分别保存在 runtime_state 和 data_cache 的文件里
+const LEGACY_RUNTIME_KEYS: [&str; 6] = [
+    "boot_times",
+    "current_page",
+    "last_update_weather",
+    "weather",
+    "ip_info",
+    "device_info",
+];
+
+// 升级时先把旧的运行状态放到这个字段里, 由 take_legacy_runtime 取走
+const LEGACY_RUNTIME_KEY: &str = "legacy_runtime";
+
+/// MIGRATIONS[n] 把版本 n 的配置升级到 n + 1
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// 配置文件的版本, 没有版本号的是加入版本号之前的配置, 算作 0
+pub fn config_version(config: &Value) -> u32 {
+    config
+        .get(VERSION_KEY)
+        .and_then(Value::as_u64)
+        .map_or(0, |version| version as u32)
+}
+
+/// 把配置升级到当前版本, 返回是否有修改. 比当前固件新的配置不修改, 不认识的字段由 serde 忽略
+pub fn migrate(config: &mut Value) -> anyhow::Result<bool> {
+    let version = config_version(config);
+    let Some(map) = config.as_object_mut() else {
+        anyhow::bail!("config is not a json object");
+    };
+    if version >= CONFIG_VERSION {
+        if version > CONFIG_VERSION {
+            log::warn!("config version {version} is newer than firmware {CONFIG_VERSION}");
+        }
+        return Ok(false);
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        log::info!("migrate config from v{from} to v{}", from + 1);
+        migration(map);
+    }
+    map.insert(VERSION_KEY.to_string(), CONFIG_VERSION.into());
+    Ok(true)
+}
+
+/// 取出升级到 v3 时分出来的运行状态和天气, 在 migrate 之后调用, 没有时返回 None
+pub fn take_legacy_runtime(config: &mut Value) -> Option<Value> {
+    config.as_object_mut()?.remove(LEGACY_RUNTIME_KEY)
+}
+
+/// v0 是加入版本号之前的配置. ip_info 是运行状态, 以前也保存到了文件, 去掉;
+/// current_page 可能是已经删除的页面, 解析不了时去掉, 用默认页面
+fn migrate_v0_to_v1(config: &mut Map<String, Value>) {
+    config.remove("ip_info");
+    let page_valid = config
+        .get("current_page")
+        .is_some_and(|page| serde_json::from_value::<ActivePage>(page.clone()).is_ok());
+    if !page_valid {
+        config.remove("current_page");
+    }
+}
+
+/// v2 支持多个 wifi 网络, 原来的 wifi_ssid 和 wifi_password 移到 wifi_networks 里.
+/// 密码已经存进 nvs 的配置没有 wifi_password, 标记 legacy_password, 由 Settings 加载时从 nvs 的旧位置取回
+fn migrate_v1_to_v2(config: &mut Map<String, Value>) {
+    let ssid = config.remove("wifi_ssid");
+    let password = config.remove("wifi_password");
+    if config.contains_key("wifi_networks") {
+        return;
+    }
+    let Some(Value::String(ssid)) = ssid else {
+        return;
+    };
+    let mut network = Map::new();
+    network.insert("ssid".to_string(), ssid.into());
+    match password {
+        Some(password) => network.insert("password".to_string(), password),
+        None => network.insert("legacy_password".to_string(), true.into()),
+    };
+    config.insert(
+        "wifi_networks".to_string(),
+        Value::Array(vec![Value::Object(network)]),
+    );
+}
+
+/// v3 把运行状态和天气从设置里分出去, 先放到 LEGACY_RUNTIME_KEY 下面, 加载时转存到各自的文件
+fn migrate_v2_to_v3(config: &mut Map<String, Value>) {
+    let legacy: Map<String, Value> = LEGACY_RUNTIME_KEYS
+        .iter()
+        .filter_map(|key| config.remove_entry(*key))
+        .collect();
+    if !legacy.is_empty() {
+        config.insert(LEGACY_RUNTIME_KEY.to_string(), Value::Object(legacy));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(sample: &str) -> Value {
+        serde_json::from_str(sample).unwrap()
+    }
+
+    #[test]
+    fn migrate_v0_keeps_user_settings() {
+        let mut config = load(include_str!("testdata/config_v0.json"));
+        assert_eq!(config_version(&config), 0);
+        assert!(migrate(&mut config).unwrap());
+
+        assert_eq!(config_version(&config), CONFIG_VERSION);
+        assert_eq!(config["wifi_networks"][0]["ssid"], "home-2.4G");
+        assert_eq!(config["wifi_networks"][0]["password"], "my secret");
+        assert!(config.get("wifi_ssid").is_none());
+        assert!(config.get("wifi_password").is_none());
+        assert_eq!(
+            config["weather_api_key"],
+            "0123456789abcdef0123456789abcdef"
+        );
+        assert_eq!(config[LEGACY_RUNTIME_KEY]["boot_times"], 1234);
+        assert_eq!(config[LEGACY_RUNTIME_KEY]["current_page"], "FullTime");
+        assert!(config[LEGACY_RUNTIME_KEY].get("ip_info").is_none());
+    }
+
+    #[test]
+    fn migrate_v0_drops_removed_page() {
+        let mut config = load(include_str!("testdata/config_v0_removed_page.json"));
+        assert!(migrate(&mut config).unwrap());
+        assert!(config[LEGACY_RUNTIME_KEY].get("current_page").is_none());
+        assert_eq!(config["wifi_networks"][0]["ssid"], "office");
+        assert_eq!(config[LEGACY_RUNTIME_KEY]["boot_times"], 7);
+    }
+
+    #[test]
+    fn migrate_v1_moves_wifi_into_list() {
+        // 密码已经存进 nvs 的 v1 配置
+        let mut config = load(r#"{"config_version": 1, "wifi_ssid": "home"}"#);
+        assert!(migrate(&mut config).unwrap());
+        assert_eq!(
+            config["wifi_networks"],
+            serde_json::json!([{"ssid": "home", "legacy_password": true}])
+        );
+        assert!(config.get("wifi_ssid").is_none());
+
+        // 手动加过 wifi_networks 的不覆盖
+        let mut config = load(
+            r#"{"config_version": 1, "wifi_ssid": "home", "wifi_networks": [{"ssid": "office"}]}"#,
+        );
+        migrate(&mut config).unwrap();
+        assert_eq!(config["wifi_networks"][0]["ssid"], "office");
+        assert!(config.get("wifi_ssid").is_none());
+    }
+
+    #[test]
+    fn current_version_is_untouched() {
+        let mut config = load(include_str!("testdata/config_v0.json"));
+        migrate(&mut config).unwrap();
+        let migrated = config.clone();
+        assert!(!migrate(&mut config).unwrap());
+        assert_eq!(config, migrated);
+
+        // 新固件写的配置不降级
+        config[VERSION_KEY] = (CONFIG_VERSION + 1).into();
+        config["future_field"] = true.into();
+        assert!(!migrate(&mut config).unwrap());
+        assert_eq!(config["future_field"], true);
+    }
+
+    #[test]
+    fn runtime_state_leaves_config() {
+        let mut config = load(include_str!("testdata/config_v0.json"));
+        migrate(&mut config).unwrap();
+        let legacy = take_legacy_runtime(&mut config).unwrap();
+        assert_eq!(legacy["boot_times"], 1234);
+        assert_eq!(legacy["current_page"], "FullTime");
+        assert!(config.get("boot_times").is_none());
+        assert!(config.get("current_page").is_none());
+        assert_eq!(config["wifi_networks"][0]["ssid"], "home-2.4G");
+        // 新固件写的配置里没有这些字段
+        assert!(take_legacy_runtime(&mut config).is_none());
+
+        // 分开保存以前的 v2 配置
+        let mut config =
+            load(r#"{"config_version": 2, "boot_times": 9, "weather": {}, "wifi_networks": []}"#);
+        assert!(migrate(&mut config).unwrap());
+        let legacy = take_legacy_runtime(&mut config).unwrap();
+        assert_eq!(legacy["boot_times"], 9);
+        assert!(legacy.get("weather").is_some());
+        assert!(config.get("weather").is_none());
+    }
+
+    #[test]
+    fn runtime_state_only_taken_by_migration() {
+        // 已经是 v3 的配置不再当作旧的运行状态处理
+        let mut config = load(r#"{"config_version": 3, "boot_times": 9}"#);
+        assert!(!migrate(&mut config).unwrap());
+        assert!(take_legacy_runtime(&mut config).is_none());
+        assert_eq!(config["boot_times"], 9);
+
+        // 没有运行状态的 v2 配置只改版本号
+        let mut config = load(r#"{"config_version": 2, "wifi_networks": []}"#);
+        assert!(migrate(&mut config).unwrap());
+        assert!(take_legacy_runtime(&mut config).is_none());
+        assert_eq!(config_version(&config), 3);
+    }
+
+    #[test]
+    fn reject_non_object() {
+        let mut config = load("[1, 2, 3]");
+        assert!(migrate(&mut config).is_err());
+    }
+}
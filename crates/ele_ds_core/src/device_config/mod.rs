@@ -0,0 +1,7 @@
+pub mod config_patch;
+pub mod key_binding;
+pub mod migration;
+pub mod power_policy;
+pub mod rtc_state;
+pub mod secret;
+pub mod wifi_networks;
@@ -0,0 +1,338 @@
+use crate::board::battery_decoder::BatteryStatus;
+use crate::ActivePage;
+use serde::{Deserialize, Serialize};
+
+/// 电源策略参数, 时间单位: 分钟
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerPolicyConfig {
+    pub refresh_minutes: u32,             // 正常情况下的刷新间隔
+    pub low_battery_percent: u8,          // 电量区间的上限不高于这个值算低电量, 默认只有最后一格
+    pub low_battery_refresh_minutes: u32, // 低电量时的刷新间隔
+    pub low_battery_wifi_factor: u32,     // 低电量时 wifi 同步间隔放大的倍数
+    pub critical_sleep_minutes: u32,      // 电量过低时的睡眠时间, 不刷新时钟也不连 wifi
+    pub night_start_hour: u32, // 夜间开始的小时, 和结束相同时表示不启用夜间模式, 省电和安静时段共用
+    pub night_end_hour: u32,   // 夜间结束的小时
+    pub night_sleep_minutes: u32, // 夜间的刷新间隔
+    pub idle_sleep_minutes: u32, // 当前页面不需要定时刷新时的睡眠时间, 醒来只读传感器和同步 wifi
+    pub night_follow_sun: bool, // 有天气数据时用日落到日出作为夜间, 代替上面的小时
+    pub night_mute: bool,      // 夜间关闭按键和弹窗的提示音, 闹钟仍然响
+    pub night_show_sleeping: bool, // 夜间显示睡眠页面, 不再刷新时钟
+}
+
+impl Default for PowerPolicyConfig {
+    fn default() -> Self {
+        Self {
+            refresh_minutes: 1,
+            low_battery_percent: 25,
+            low_battery_refresh_minutes: 5,
+            low_battery_wifi_factor: 4,
+            critical_sleep_minutes: 30,
+            night_start_hour: 23,
+            night_end_hour: 7,
+            night_sleep_minutes: 30,
+            idle_sleep_minutes: 60,
+            night_follow_sun: false,
+            night_mute: true,
+            night_show_sleeping: false,
+        }
+    }
+}
+
+impl PowerPolicyConfig {
+    /// 夜间的开始和结束, 单位: 一天中的分钟. sun 是日出和日落的分钟, 启用 night_follow_sun 时使用
+    pub fn night_window(&self, sun: Option<(u32, u32)>) -> (u32, u32) {
+        match sun {
+            Some((sunrise, sunset)) if self.night_follow_sun => (sunset, sunrise),
+            _ => (self.night_start_hour * 60, self.night_end_hour * 60),
+        }
+    }
+
+    /// minute_of_day 是否在夜间, 支持跨零点, 比如 23 点到 7 点
+    pub fn is_night(&self, minute_of_day: u32, sun: Option<(u32, u32)>) -> bool {
+        let (start, end) = self.night_window(sun);
+        if start <= end {
+            (start..end).contains(&minute_of_day)
+        } else {
+            minute_of_day >= start || minute_of_day < end
+        }
+    }
+}
+
+/// 解析 "06:12" 这样的时间, 返回一天中的分钟
+pub fn parse_clock_minutes(time: &str) -> Option<u32> {
+    let (hour, minute) = time.trim().split_once(':')?;
+    let (hour, minute) = (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?);
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+/// 电源策略的判断结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerDecision {
+    pub sleep_minutes: u32,                 // 下次醒来的间隔, 按整分钟对齐
+    pub wifi_connect_interval: Option<u32>, // 每隔多少次启动连一次 wifi, None 表示不连, 0 表示每次都连
+    pub refresh_clock: bool,                // 醒来后是否刷新屏幕
+    pub stay_awake: bool,                   // 接着充电器时不睡眠
+    pub low_battery_protect: bool, // 电量过低保护, 显示充电提示后长时间睡眠, 不连 wifi 不响铃
+    pub night: bool,               // 处于夜间安静时段
+}
+
+/// 根据电池状态, 当前页面和是否夜间决定刷新和睡眠策略, wifi_interval 是配置里的 wifi 连接间隔.
+/// low_battery_mode 表示已经处于电量过低保护, 保护一直持续到检测到充电
+pub fn power_decision(
+    config: &PowerPolicyConfig,
+    battery: Option<BatteryStatus>,
+    page: ActivePage,
+    night: bool,
+    wifi_interval: u32,
+    low_battery_mode: bool,
+) -> PowerDecision {
+    let page_need_refresh = page.cur_set_page_is_need_refresh();
+    // 接着充电器时不用省电, 每分钟刷新
+    if battery.is_some_and(|b| b.is_charging()) {
+        return PowerDecision {
+            sleep_minutes: 1,
+            wifi_connect_interval: Some(wifi_interval),
+            refresh_clock: page_need_refresh,
+            stay_awake: true,
+            low_battery_protect: false,
+            night,
+        };
+    }
+    if low_battery_mode || battery.is_some_and(|b| b.critical) {
+        return PowerDecision {
+            sleep_minutes: config.critical_sleep_minutes.max(1),
+            wifi_connect_interval: None,
+            refresh_clock: false,
+            stay_awake: false,
+            low_battery_protect: true,
+            night,
+        };
+    }
+    // 电量灯只能给出区间, 按上限比较, 整个区间都低于阈值才算低电量
+    let low_battery = battery
+        .and_then(|b| b.level)
+        .is_some_and(|level| level.percent_range().1 <= config.low_battery_percent);
+    let wifi_connect_interval = if low_battery {
+        Some(wifi_interval.saturating_mul(config.low_battery_wifi_factor.max(1)))
+    } else {
+        Some(wifi_interval)
+    };
+    let (sleep_minutes, refresh_clock) = if night {
+        // 夜间降低刷新频率, 显示睡眠页面时不刷新
+        (
+            config.night_sleep_minutes,
+            page_need_refresh && !config.night_show_sleeping,
+        )
+    } else if !page_need_refresh {
+        (config.idle_sleep_minutes, false)
+    } else if low_battery {
+        (config.low_battery_refresh_minutes, true)
+    } else {
+        (config.refresh_minutes, true)
+    };
+    PowerDecision {
+        sleep_minutes: sleep_minutes.max(1),
+        wifi_connect_interval,
+        refresh_clock,
+        stay_awake: false,
+        low_battery_protect: false,
+        night,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::battery_decoder::{ChargeState, DeviceBatteryType};
+
+    fn battery(level: DeviceBatteryType, state: ChargeState, critical: bool) -> BatteryStatus {
+        BatteryStatus {
+            level: Some(level),
+            state,
+            critical,
+        }
+    }
+
+    fn decide(battery: Option<BatteryStatus>, page: ActivePage, hour: u32) -> PowerDecision {
+        let config = PowerPolicyConfig::default();
+        let night = config.is_night(hour * 60, None);
+        power_decision(&config, battery, page, night, 60, false)
+    }
+
+    #[test]
+    fn normal_battery_refresh_every_minute() {
+        let status = battery(
+            DeviceBatteryType::PercentVbat75_50,
+            ChargeState::Discharging,
+            false,
+        );
+        let decision = decide(Some(status), ActivePage::Home, 12);
+        assert_eq!(
+            decision,
+            PowerDecision {
+                sleep_minutes: 1,
+                wifi_connect_interval: Some(60),
+                refresh_clock: true,
+                stay_awake: false,
+                low_battery_protect: false,
+                night: false,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_battery_is_treated_as_normal() {
+        let decision = decide(None, ActivePage::Sensor, 12);
+        assert_eq!(decision.sleep_minutes, 1);
+        assert!(decision.refresh_clock);
+    }
+
+    #[test]
+    fn charging_stays_awake() {
+        let status = battery(
+            DeviceBatteryType::PercentVbat25_0,
+            ChargeState::Charging,
+            false,
+        );
+        let decision = decide(Some(status), ActivePage::Home, 3);
+        assert!(decision.stay_awake);
+        assert!(decision.refresh_clock);
+        assert_eq!(decision.sleep_minutes, 1);
+    }
+
+    #[test]
+    fn low_battery_slows_down() {
+        let status = battery(
+            DeviceBatteryType::PercentVbat25_0,
+            ChargeState::Discharging,
+            false,
+        );
+        let decision = decide(Some(status), ActivePage::Home, 12);
+        assert_eq!(decision.sleep_minutes, 5);
+        assert_eq!(decision.wifi_connect_interval, Some(240));
+        assert!(decision.refresh_clock);
+
+        // 25-50% 还有一半电, 不算低电量
+        let status = battery(
+            DeviceBatteryType::PercentVbat50_25,
+            ChargeState::Discharging,
+            false,
+        );
+        let decision = decide(Some(status), ActivePage::Home, 12);
+        assert_eq!(decision.sleep_minutes, 1);
+        assert_eq!(decision.wifi_connect_interval, Some(60));
+    }
+
+    #[test]
+    fn critical_battery_only_sleeps() {
+        let status = battery(
+            DeviceBatteryType::PercentVbat25_0,
+            ChargeState::Discharging,
+            true,
+        );
+        let decision = decide(Some(status), ActivePage::Home, 12);
+        assert_eq!(decision.sleep_minutes, 30);
+        assert_eq!(decision.wifi_connect_interval, None);
+        assert!(!decision.refresh_clock);
+        assert!(decision.low_battery_protect);
+    }
+
+    #[test]
+    fn low_battery_mode_lasts_until_charging() {
+        let config = PowerPolicyConfig::default();
+        // 保护中灯不亮读不到电量, 继续保护
+        let decision = power_decision(&config, None, ActivePage::Home, false, 60, true);
+        assert!(decision.low_battery_protect);
+        assert_eq!(decision.sleep_minutes, 30);
+
+        let status = battery(
+            DeviceBatteryType::PercentVbat25_0,
+            ChargeState::Charging,
+            false,
+        );
+        let decision = power_decision(&config, Some(status), ActivePage::Home, false, 60, true);
+        assert!(!decision.low_battery_protect);
+        assert!(decision.stay_awake);
+    }
+
+    #[test]
+    fn night_refreshes_less_often() {
+        let decision = decide(None, ActivePage::Home, 3);
+        assert_eq!(decision.sleep_minutes, 30);
+        assert!(decision.night);
+        assert!(decision.refresh_clock);
+        let decision = decide(None, ActivePage::Home, 23);
+        assert!(decision.night);
+        let decision = decide(None, ActivePage::Home, 7);
+        assert_eq!(decision.sleep_minutes, 1);
+        assert!(!decision.night);
+    }
+
+    #[test]
+    fn night_sleeping_page_stops_clock() {
+        let config = PowerPolicyConfig {
+            night_show_sleeping: true,
+            ..Default::default()
+        };
+        let decision = power_decision(&config, None, ActivePage::Home, true, 60, false);
+        assert_eq!(decision.sleep_minutes, 30);
+        assert!(!decision.refresh_clock);
+    }
+
+    #[test]
+    fn night_follows_sun() {
+        let config = PowerPolicyConfig {
+            night_follow_sun: true,
+            ..Default::default()
+        };
+        let sun = Some((
+            parse_clock_minutes("06:12").unwrap(),
+            parse_clock_minutes("18:45").unwrap(),
+        ));
+        assert!(config.is_night(19 * 60, sun));
+        assert!(config.is_night(6 * 60, sun));
+        assert!(!config.is_night(6 * 60 + 12, sun));
+        assert!(!config.is_night(12 * 60, sun));
+        // 没有天气数据时用配置的小时
+        assert!(config.is_night(3 * 60, None));
+        assert!(!config.is_night(19 * 60, None));
+    }
+
+    #[test]
+    fn parse_clock() {
+        assert_eq!(parse_clock_minutes("06:12"), Some(372));
+        assert_eq!(parse_clock_minutes(" 18:05 "), Some(1085));
+        assert_eq!(parse_clock_minutes("24:00"), None);
+        assert_eq!(parse_clock_minutes("6"), None);
+    }
+
+    #[test]
+    fn night_across_midnight() {
+        let config = PowerPolicyConfig {
+            night_start_hour: 23,
+            night_end_hour: 7,
+            ..Default::default()
+        };
+        assert!(config.is_night(23 * 60, None));
+        assert!(config.is_night(0, None));
+        assert!(config.is_night(6 * 60 + 59, None));
+        assert!(!config.is_night(7 * 60, None));
+        assert!(!config.is_night(12 * 60, None));
+        // 开始和结束相同表示不启用
+        let disabled = PowerPolicyConfig {
+            night_start_hour: 0,
+            night_end_hour: 0,
+            ..Default::default()
+        };
+        assert!(!disabled.is_night(0, None));
+    }
+
+    #[test]
+    fn static_page_sleeps_long() {
+        let decision = decide(None, ActivePage::Image, 12);
+        assert_eq!(decision.sleep_minutes, 60);
+        assert!(!decision.refresh_clock);
+        assert_eq!(decision.wifi_connect_interval, Some(60));
+    }
+}
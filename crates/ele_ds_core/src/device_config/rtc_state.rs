@@ -0,0 +1,193 @@
+use crate::board::battery_decoder::BatteryStatus;
+use crate::file_system::atomic_file::fnv1a;
+use crate::ActivePage;
+
+const RTC_STATE_MAGIC: u32 = 0x5254_4353; // 用来判断 rtc 内存是不是上电后的随机值
+const FLUSH_BOOT_TIMES_INTERVAL: u32 = 60; // 启动次数累计这么多次才写一次配置文件
+const BATTERY_CACHE_BOOT_TIMES: u32 = 10; // 定时唤醒时电池状态缓存的有效启动次数, 超过后重新采样
+const MAX_PROVISION_BACKOFF: u32 = 4; // 自动配网的间隔最多放大到 2^4 倍
+
+/// 需要跨深度睡眠保存, 但是变化很快不适合每次都写 flash 的运行状态
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RtcState {
+    pub boot_times: u32,
+    pub current_page: u32, // ActivePage 的序号, 直接存枚举时内存被破坏会读到非法值
+    pub last_update_weather: u32,
+    pub sleep_deadline: i64,     // 预计唤醒的时间, unix 时间戳秒, 0 表示没有
+    pub battery: u32,            // 最近一次采样的电池状态, BatteryStatus::to_bits(), 0 表示没有
+    pub battery_boot_times: u32, // 采样电池状态时的启动次数
+    pub low_battery: u32,        // 非 0 表示处于电量过低保护中
+    pub sleeping: u32,           // 非 0 表示屏幕正在显示夜间睡眠页面
+    pub wifi_failures: u32,      // 连续连接 wifi 失败的次数
+    pub provisioning: u32,       // 非 0 表示重启后进入配网模式
+    pub auto_provisions: u32,    // 上次连上 wifi 之后自动进入配网的次数
+}
+
+impl RtcState {
+    pub fn is_low_battery(&self) -> bool {
+        self.low_battery != 0
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping != 0
+    }
+
+    pub fn is_provisioning(&self) -> bool {
+        self.provisioning != 0
+    }
+
+    pub fn page(&self) -> Option<ActivePage> {
+        ActivePage::from_index(self.current_page as usize)
+    }
+
+    /// 连续失败次数是否到了自动进入配网的时候. 第一次在 limit 次失败后,
+    /// 之后每次间隔加倍, 路由器坏了很久时不会每隔几次启动就开 10 分钟热点
+    pub fn provision_due(&self, limit: u32) -> bool {
+        limit > 0 && self.wifi_failures >= limit << self.auto_provisions.min(MAX_PROVISION_BACKOFF)
+    }
+
+    /// 缓存的电池状态, 采样后启动次数没超过 BATTERY_CACHE_BOOT_TIMES 时有效
+    pub fn cached_battery(&self) -> Option<BatteryStatus> {
+        if self.boot_times.wrapping_sub(self.battery_boot_times) >= BATTERY_CACHE_BOOT_TIMES {
+            return None;
+        }
+        BatteryStatus::from_bits(self.battery)
+    }
+
+    /// 和上次写到配置文件的状态比较, 是否有值得写 flash 的变化
+    pub fn need_flush(&self, flashed: &RtcState) -> bool {
+        self.current_page != flashed.current_page
+            || self.last_update_weather != flashed.last_update_weather
+            || self.boot_times.wrapping_sub(flashed.boot_times) >= FLUSH_BOOT_TIMES_INTERVAL
+    }
+
+    fn words(&self) -> [u32; 12] {
+        [
+            self.boot_times,
+            self.current_page,
+            self.last_update_weather,
+            self.sleep_deadline as u32,
+            (self.sleep_deadline >> 32) as u32,
+            self.battery,
+            self.battery_boot_times,
+            self.low_battery,
+            self.sleeping,
+            self.wifi_failures,
+            self.provisioning,
+            self.auto_provisions,
+        ]
+    }
+}
+
+/// rtc 内存中保存的内容, flashed 是最近一次写到配置文件的状态
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RtcSlot {
+    pub state: RtcState,
+    pub flashed: RtcState,
+}
+
+#[repr(C)]
+struct RtcMemory {
+    magic: u32,
+    slot: RtcSlot,
+    checksum: u32,
+}
+
+// 放在 rtc 内存, 深度睡眠时保持, 掉电后内容无效, 通过 magic 和校验和判断
+#[link_section = ".rtc.data"]
+static mut RTC_MEMORY: RtcMemory = RtcMemory {
+    magic: 0,
+    slot: RtcSlot {
+        state: RtcState {
+            boot_times: 0,
+            current_page: 0,
+            last_update_weather: 0,
+            sleep_deadline: 0,
+            battery: 0,
+            battery_boot_times: 0,
+            low_battery: 0,
+            sleeping: 0,
+            wifi_failures: 0,
+            provisioning: 0,
+            auto_provisions: 0,
+        },
+        flashed: RtcState {
+            boot_times: 0,
+            current_page: 0,
+            last_update_weather: 0,
+            sleep_deadline: 0,
+            battery: 0,
+            battery_boot_times: 0,
+            low_battery: 0,
+            sleeping: 0,
+            wifi_failures: 0,
+            provisioning: 0,
+            auto_provisions: 0,
+        },
+    },
+    checksum: 0,
+};
+
+/// 按小端字节计算一组 u32 的 fnv-1a, 其他放在 rtc 内存的数据也用它判断内容是否有效
+pub(crate) fn fnv1a_words(words: impl Iterator<Item = u32>) -> u32 {
+    fnv1a(words.flat_map(u32::to_le_bytes))
+}
+
+fn checksum(slot: &RtcSlot) -> u32 {
+    fnv1a_words(slot.state.words().into_iter().chain(slot.flashed.words()))
+}
+
+/// 读取 rtc 内存中的状态, 上电或者内容被破坏时返回 None
+pub fn load() -> Option<RtcSlot> {
+    let memory = unsafe { core::ptr::addr_of!(RTC_MEMORY).read_volatile() };
+    if memory.magic != RTC_STATE_MAGIC || memory.checksum != checksum(&memory.slot) {
+        return None;
+    }
+    Some(memory.slot)
+}
+
+pub fn store(slot: &RtcSlot) {
+    let memory = RtcMemory {
+        magic: RTC_STATE_MAGIC,
+        slot: *slot,
+        checksum: checksum(slot),
+    };
+    unsafe { core::ptr::addr_of_mut!(RTC_MEMORY).write_volatile(memory) };
+}
+
+/// 让 rtc 内存中的状态失效, 重启后和上电一样以配置文件为准
+pub fn clear() {
+    unsafe { core::ptr::addr_of_mut!(RTC_MEMORY.magic).write_volatile(0) };
+}
+
+/// 修改 rtc 内存中的状态, 内容无效时不修改
+pub fn update(f: impl FnOnce(&mut RtcState)) {
+    if let Some(mut slot) = load() {
+        f(&mut slot.state);
+        store(&slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provision_backoff() {
+        let state = |wifi_failures, auto_provisions| RtcState {
+            wifi_failures,
+            auto_provisions,
+            ..Default::default()
+        };
+        assert!(!state(2, 0).provision_due(3));
+        assert!(state(3, 0).provision_due(3));
+        // 自动配网过一次后要失败两倍的次数
+        assert!(!state(3, 1).provision_due(3));
+        assert!(state(6, 1).provision_due(3));
+        assert!(!state(47, 9).provision_due(3));
+        assert!(state(48, 9).provision_due(3));
+        // 0 表示不自动进入
+        assert!(!state(100, 0).provision_due(0));
+    }
+}
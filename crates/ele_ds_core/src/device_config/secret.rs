@@ -0,0 +1,263 @@
+// 配置里的密码和 key. 值保存在加密的 nvs 里 (见 secret_store), 配置文件里不保存,
+// Debug 只打印 ***, 避免出现在日志里
+use crate::device_config::rtc_state::fnv1a_words;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// 保存在加密 nvs 里的配置项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretKey {
+    WifiPassword(u32),  // 每个 wifi 网络一个, 参数是 ssid 的哈希, 见 SecretKey::wifi()
+    LegacyWifiPassword, // 只有一个 wifi 网络时的密码, 升级后移到对应网络
+    WeatherApiKey,
+    UserPassword,
+}
+
+impl SecretKey {
+    /// 每个字节按 u32 参与哈希, 是 nvs 里已经保存的键名, 不能改
+    pub fn wifi(ssid: &str) -> Self {
+        SecretKey::WifiPassword(fnv1a_words(ssid.bytes().map(u32::from)))
+    }
+
+    /// nvs 里的键名, 不能超过 15 个字符
+    pub fn nvs_name(self) -> String {
+        match self {
+            SecretKey::WifiPassword(hash) => format!("wifi_{hash:08x}"),
+            SecretKey::LegacyWifiPassword => "wifi_password".to_string(),
+            SecretKey::WeatherApiKey => "weather_key".to_string(),
+            SecretKey::UserPassword => "user_password".to_string(),
+        }
+    }
+}
+
+/// 值是从哪里来的
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SecretState {
+    #[default]
+    Missing, // 配置文件里没有, 需要从 nvs 读
+    Plain,  // 从配置文件读到的明文, 还没有存进 nvs
+    Stored, // 已经存进 nvs
+}
+
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct Secret {
+    value: String,
+    state: SecretState,
+}
+
+impl Secret {
+    /// 新设置的值, 存进 nvs 后调用 mark_stored()
+    pub fn new(value: &str) -> Self {
+        Self {
+            value: value.to_string(),
+            state: SecretState::Plain,
+        }
+    }
+    /// 默认值, 不写到配置文件
+    pub fn fallback(value: &str) -> Self {
+        Self {
+            value: value.to_string(),
+            state: SecretState::Missing,
+        }
+    }
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+    /// 配置文件里没有这个值
+    pub fn is_missing(&self) -> bool {
+        self.state == SecretState::Missing
+    }
+    /// 明文, 还没有存进 nvs
+    pub fn is_plain(&self) -> bool {
+        self.state == SecretState::Plain
+    }
+    pub fn mark_stored(&mut self) {
+        self.state = SecretState::Stored;
+    }
+    /// 写配置文件时跳过, 只有存不进 nvs 的明文才留在配置文件里, 免得丢掉
+    pub fn skip_in_file(&self) -> bool {
+        self.state != SecretState::Plain
+    }
+    /// 屏幕上显示的值
+    pub fn masked(&self) -> String {
+        if self.value.is_empty() {
+            "(empty)".to_string()
+        } else {
+            "*".repeat(self.value.chars().count().min(8))
+        }
+    }
+}
+
+/// 比较密码, 耗时和第一个不同字符的位置无关
+pub fn password_matches(input: &str, password: &str) -> bool {
+    let (input, password) = (input.as_bytes(), password.as_bytes());
+    let mut diff = input.len() ^ password.len();
+    for i in 0..input.len().max(password.len()) {
+        let a = input.get(i).copied().unwrap_or(0);
+        let b = password.get(i).copied().unwrap_or(0);
+        diff |= usize::from(a ^ b);
+    }
+    diff == 0
+}
+
+/// 对加密 nvs 的修改. 先记下来, 配置文件保存成功后再写, 保存失败时 nvs 不变
+#[derive(Clone, PartialEq, Eq)]
+pub enum SecretChange {
+    Set(SecretKey, String),
+    Remove(SecretKey),
+}
+
+impl fmt::Debug for SecretChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretChange::Set(key, _) => write!(f, "Set({key:?}, ***)"),
+            SecretChange::Remove(key) => write!(f, "Remove({key:?})"),
+        }
+    }
+}
+
+/// 先保存配置文件, 成功后再按顺序写 nvs. 返回写不进 nvs 的值, 调用者要把它们作为明文留在配置文件里.
+/// 删除失败只记日志, 留下的旧值不会再被用到
+pub fn save_then_apply(
+    changes: Vec<SecretChange>,
+    save: impl FnOnce() -> anyhow::Result<()>,
+    mut set: impl FnMut(SecretKey, &str) -> anyhow::Result<()>,
+    mut remove: impl FnMut(SecretKey) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<(SecretKey, String)>> {
+    save()?;
+    let mut failed = Vec::new();
+    for change in changes {
+        match change {
+            SecretChange::Set(key, value) => {
+                if let Err(e) = set(key, &value) {
+                    log::warn!("store {} failed: {e:?}", key.nvs_name());
+                    failed.push((key, value));
+                }
+            }
+            SecretChange::Remove(key) => {
+                if let Err(e) = remove(key) {
+                    log::warn!("remove {} failed: {e:?}", key.nvs_name());
+                }
+            }
+        }
+    }
+    Ok(failed)
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***, {:?})", self.state)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.value)
+    }
+}
+
+/// 配置文件里的旧明文或者手动上传的配置, 读到后由 secret_store 存进 nvs
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Secret::new(&String::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Sample {
+        name: String,
+        #[serde(default, skip_serializing_if = "Secret::skip_in_file")]
+        password: Secret,
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let sample = Sample {
+            name: "home".to_string(),
+            password: Secret::new("my secret"),
+        };
+        let text = format!("{sample:?}");
+        assert!(text.contains("home"));
+        assert!(!text.contains("my secret"));
+        assert_eq!(sample.password.masked(), "********");
+        assert_eq!(Secret::new("").masked(), "(empty)");
+    }
+
+    #[test]
+    fn wifi_key_names() {
+        let home = SecretKey::wifi("home").nvs_name();
+        assert_eq!(home.len(), 13);
+        assert_eq!(home, "wifi_e8da99ca"); // 已经存进 nvs 的键名
+        assert_eq!(home, SecretKey::wifi("home").nvs_name());
+        assert_ne!(home, SecretKey::wifi("office").nvs_name());
+    }
+
+    #[test]
+    fn stored_secret_is_not_written() {
+        let mut sample: Sample =
+            serde_json::from_str(r#"{"name": "home", "password": "my secret"}"#).unwrap();
+        assert!(sample.password.is_plain());
+        assert_eq!(sample.password.expose(), "my secret");
+        // 存不进 nvs 时明文保留在文件里
+        assert!(serde_json::to_string(&sample)
+            .unwrap()
+            .contains("my secret"));
+
+        sample.password.mark_stored();
+        let text = serde_json::to_string(&sample).unwrap();
+        assert_eq!(text, r#"{"name":"home"}"#);
+
+        let sample: Sample = serde_json::from_str(&text).unwrap();
+        assert!(sample.password.is_missing());
+    }
+
+    #[test]
+    fn secret_changes_wait_for_save() {
+        use std::cell::RefCell;
+        let store = RefCell::new(Vec::new());
+        let set = |key: SecretKey, value: &str| {
+            if value.is_empty() {
+                anyhow::bail!("nvs full");
+            }
+            store
+                .borrow_mut()
+                .push(format!("set {} {value}", key.nvs_name()));
+            Ok(())
+        };
+        let remove = |key: SecretKey| {
+            store
+                .borrow_mut()
+                .push(format!("remove {}", key.nvs_name()));
+            Ok(())
+        };
+        let changes = vec![
+            SecretChange::Set(SecretKey::WeatherApiKey, "new key".to_string()),
+            SecretChange::Remove(SecretKey::wifi("home")),
+            SecretChange::Set(SecretKey::UserPassword, String::new()),
+        ];
+
+        // 保存失败时不碰 nvs
+        let result = save_then_apply(changes.clone(), || anyhow::bail!("disk full"), set, remove);
+        assert!(result.is_err());
+        assert!(store.borrow().is_empty());
+
+        let failed = save_then_apply(changes, || Ok(()), set, remove).unwrap();
+        assert_eq!(
+            *store.borrow(),
+            ["set weather_key new key", "remove wifi_e8da99ca"]
+        );
+        assert_eq!(failed, [(SecretKey::UserPassword, String::new())]);
+    }
+
+    #[test]
+    fn compare_password() {
+        assert!(password_matches("secret-pw", "secret-pw"));
+        assert!(!password_matches("secret-p", "secret-pw"));
+        assert!(!password_matches("secret-px", "secret-pw"));
+        assert!(!password_matches("", "secret-pw"));
+    }
+}
@@ -0,0 +1,182 @@
+// 保存的多个 wifi 网络. 连接前先扫描, 扫描到的网络按优先级和信号强度排序依次尝试,
+// 扫描不到的网络 (隐藏网络, 扫描漏掉或者扫描失败) 放到最后尝试. 密码保存在加密 nvs 里, 见 secret_store
+use crate::device_config::secret::Secret;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+fn default_prefix_len() -> u8 {
+    24
+}
+
+/// 静态 ip 设置
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StaticIp {
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    #[serde(default = "default_prefix_len")]
+    pub prefix_len: u8, // 子网掩码长度, 24 即 255.255.255.0
+    #[serde(default)]
+    pub dns: Option<Ipv4Addr>, // 没有设置时用网关
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WifiNetwork {
+    pub ssid: String,
+    #[serde(default, skip_serializing_if = "Secret::skip_in_file")]
+    pub password: Secret, // 保存在加密 nvs 里
+    #[serde(default)]
+    pub priority: u8, // 越大越优先, 同优先级时选信号强的
+    #[serde(default)]
+    pub hidden: bool, // 隐藏网络, 扫描不到也尝试连接
+    #[serde(default)]
+    pub static_ip: Option<StaticIp>, // 没有设置时用 dhcp
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legacy_password: bool, // 从单个网络的旧配置升级来的, 密码还在 nvs 的旧位置, 加载时移过来
+}
+
+impl WifiNetwork {
+    pub fn new(ssid: &str, password: Secret) -> Self {
+        Self {
+            ssid: ssid.to_string(),
+            password,
+            priority: 0,
+            hidden: false,
+            static_ip: None,
+            legacy_password: false,
+        }
+    }
+}
+
+/// 扫描到的热点
+#[derive(Debug, Clone, Copy)]
+pub struct ScannedAp<'a> {
+    pub ssid: &'a str,
+    pub rssi: i8,
+}
+
+/// 同一个 ssid 可能有多个热点, 取信号最强的
+fn best_rssi(ssid: &str, scanned: &[ScannedAp]) -> Option<i8> {
+    scanned
+        .iter()
+        .filter(|ap| ap.ssid == ssid)
+        .map(|ap| ap.rssi)
+        .max()
+}
+
+/// 得到尝试连接的顺序, 返回 networks 的下标. 扫描到的网络先按优先级再按信号强度排序,
+/// 之后是扫描不到的网络, 按优先级排序. 扫描可能漏掉信号弱的热点, 扫描失败时 scanned 为空, 所以都要尝试
+pub fn connect_order(networks: &[WifiNetwork], scanned: &[ScannedAp]) -> Vec<usize> {
+    let mut visible: Vec<(usize, i8)> = networks
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, network)| Some((idx, best_rssi(&network.ssid, scanned)?)))
+        .collect();
+    visible.sort_by_key(|&(idx, rssi)| (std::cmp::Reverse(networks[idx].priority), -(rssi as i16)));
+
+    // 同优先级时隐藏网络在前, 它们本来就扫描不到, 普通网络扫描不到多半是不在附近
+    let mut missing: Vec<usize> = (0..networks.len())
+        .filter(|idx| !visible.iter().any(|(v, _)| v == idx))
+        .collect();
+    missing.sort_by_key(|&idx| {
+        (
+            std::cmp::Reverse(networks[idx].priority),
+            !networks[idx].hidden,
+        )
+    });
+
+    visible
+        .into_iter()
+        .map(|(idx, _)| idx)
+        .chain(missing)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(ssid: &str, priority: u8, hidden: bool) -> WifiNetwork {
+        WifiNetwork {
+            priority,
+            hidden,
+            ..WifiNetwork::new(ssid, Secret::default())
+        }
+    }
+
+    fn ap(ssid: &str, rssi: i8) -> ScannedAp<'_> {
+        ScannedAp { ssid, rssi }
+    }
+
+    #[test]
+    fn priority_then_signal() {
+        let networks = [
+            network("office", 0, false),
+            network("home", 0, false),
+            network("phone", 5, false),
+            network("lab", 0, false),
+        ];
+        let scanned = [
+            ap("home", -70),
+            ap("office", -80),
+            ap("office", -50), // 同名热点取最强的
+            ap("phone", -85),
+            ap("neighbour", -30),
+        ];
+        // 扫描不到的 lab 最后尝试
+        assert_eq!(connect_order(&networks, &scanned), vec![2, 0, 1, 3]);
+    }
+
+    #[test]
+    fn hidden_networks_last() {
+        let networks = [
+            network("cellar", 1, true),
+            network("home", 0, false),
+            network("attic", 3, true),
+        ];
+        assert_eq!(connect_order(&networks, &[ap("home", -60)]), vec![1, 2, 0]);
+        // 扫描到的隐藏网络和普通网络一起排序, 不重复尝试
+        assert_eq!(
+            connect_order(&networks, &[ap("home", -60), ap("cellar", -40)]),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn missed_networks_still_tried() {
+        let networks = [
+            network("office", 0, false),
+            network("home", 2, false),
+            network("cellar", 0, true),
+            network("phone", 2, false),
+        ];
+        // 扫描漏掉的普通网络排在扫描到的后面, 同优先级时隐藏网络在前
+        assert_eq!(
+            connect_order(&networks, &[ap("office", -60)]),
+            vec![0, 1, 3, 2]
+        );
+    }
+
+    #[test]
+    fn scan_failed_tries_all_by_priority() {
+        let networks = [
+            network("office", 0, false),
+            network("home", 2, false),
+            network("cellar", 1, true),
+        ];
+        assert_eq!(connect_order(&networks, &[]), vec![1, 2, 0]);
+        assert!(connect_order(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn parse_static_ip() {
+        let network: WifiNetwork = serde_json::from_str(
+            r#"{"ssid": "office", "static_ip": {"ip": "10.0.0.50", "gateway": "10.0.0.1"}}"#,
+        )
+        .unwrap();
+        let static_ip = network.static_ip.unwrap();
+        assert_eq!(static_ip.ip, Ipv4Addr::new(10, 0, 0, 50));
+        assert_eq!(static_ip.prefix_len, 24);
+        assert_eq!(static_ip.dns, None);
+        assert!(network.password.is_missing());
+    }
+}
@@ -0,0 +1,186 @@
+// 防掉电的文件读写. 写文件时先写到 .tmp 并落盘, 旧文件校验通过才改名成 .bak, 最后把 .tmp 改名成正式文件,
+// 任何时候掉电都至少有一份完整的文件. 文件末尾加一行校验和, 读的时候依次尝试正式文件, .tmp 和 .bak
+use std::fs;
+use std::io::Write;
+
+const CHECKSUM_PREFIX: &str = "\n#checksum:"; // 校验和所在行的前缀, 后面是 8 位十六进制
+
+/// fnv-1a 校验和, rtc 内存和 nvs 键名也用它
+pub fn fnv1a(data: impl IntoIterator<Item = u8>) -> u32 {
+    let mut hash = 0x811c_9dc5_u32;
+    for byte in data {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+pub fn tmp_path(path: &str) -> String {
+    format!("{path}.tmp")
+}
+
+pub fn backup_path(path: &str) -> String {
+    format!("{path}.bak")
+}
+
+/// 在内容后面加上校验和
+pub fn with_checksum(contents: &str) -> String {
+    format!(
+        "{contents}{CHECKSUM_PREFIX}{:08x}\n",
+        fnv1a(contents.bytes())
+    )
+}
+
+/// 去掉并校验文件末尾的校验和. 没有校验和的文件是旧固件写的或者手动上传的, 原样返回
+pub fn strip_checksum(data: &str) -> anyhow::Result<&str> {
+    let Some(pos) = data.rfind(CHECKSUM_PREFIX) else {
+        return Ok(data);
+    };
+    let (contents, trailer) = data.split_at(pos);
+    let expected = trailer[CHECKSUM_PREFIX.len()..].trim();
+    let expected = u32::from_str_radix(expected, 16)
+        .map_err(|e| anyhow::anyhow!("bad checksum line {expected:?}: {e}"))?;
+    let actual = fnv1a(contents.bytes());
+    if actual != expected {
+        anyhow::bail!("checksum mismatch, expected {expected:08x}, actual {actual:08x}");
+    }
+    Ok(contents)
+}
+
+/// 读文件并校验
+fn read_verified(path: &str) -> anyhow::Result<String> {
+    let data = fs::read_to_string(path)?;
+    Ok(strip_checksum(&data)?.to_string())
+}
+
+/// 原子写文件, 带校验和, 同时保留上一份完整的文件作为备份
+pub fn write_atomic(path: &str, contents: &str) -> anyhow::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = tmp_path(path);
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(with_checksum(contents).as_bytes())?;
+        file.sync_all()?;
+    }
+    // fat 上改名时目标文件存在会失败, 先删掉. 只有旧文件完整才作为备份, 避免用坏文件覆盖好的备份
+    if read_verified(path).is_ok() {
+        let backup = backup_path(path);
+        let _ = fs::remove_file(&backup);
+        fs::rename(path, &backup)?;
+    } else {
+        let _ = fs::remove_file(path);
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// 依次用正式文件, .tmp 和 .bak 解析, 返回第一个校验和解析都通过的结果,
+/// 以及是不是从 .tmp 或 .bak 恢复的, 恢复的话调用者应该重新写一次
+pub fn load_with_backup<T>(
+    path: &str,
+    parse: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<(T, bool)> {
+    let mut errors = Vec::new();
+    for (idx, candidate) in [path.to_string(), tmp_path(path), backup_path(path)]
+        .iter()
+        .enumerate()
+    {
+        match read_verified(candidate).and_then(|contents| parse(&contents)) {
+            Ok(value) => {
+                if idx > 0 {
+                    log::warn!("{path} recovered from {candidate}");
+                }
+                return Ok((value, idx > 0));
+            }
+            Err(e) => {
+                log::warn!("load {candidate} failed: {e}");
+                errors.push(format!("{candidate}: {e}"));
+            }
+        }
+    }
+    anyhow::bail!("no usable copy of {path}: {}", errors.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个测试用单独的目录, 避免并行测试互相影响
+    fn test_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("atomic_file_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("config").to_string_lossy().to_string()
+    }
+
+    fn parse_number(contents: &str) -> anyhow::Result<u32> {
+        Ok(contents.trim().parse()?)
+    }
+
+    #[test]
+    fn fnv1a_reference() {
+        assert_eq!(fnv1a(*b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(*b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a(*b"foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn checksum_round_trip() {
+        let data = with_checksum("{\"a\": 1}");
+        assert_eq!(strip_checksum(&data).unwrap(), "{\"a\": 1}");
+        // 没有校验和的旧文件原样返回
+        assert_eq!(strip_checksum("{\"a\": 1}").unwrap(), "{\"a\": 1}");
+        let broken = data.replace("1}", "2}");
+        assert!(strip_checksum(&broken).is_err());
+    }
+
+    #[test]
+    fn write_keeps_backup() {
+        let path = test_path("backup");
+        write_atomic(&path, "1").unwrap();
+        assert!(fs::metadata(backup_path(&path)).is_err());
+        write_atomic(&path, "2").unwrap();
+        assert_eq!(read_verified(&path).unwrap(), "2");
+        assert_eq!(read_verified(&backup_path(&path)).unwrap(), "1");
+        assert!(fs::metadata(tmp_path(&path)).is_err());
+        assert_eq!(load_with_backup(&path, parse_number).unwrap(), (2, false));
+    }
+
+    #[test]
+    fn recover_from_half_written_file() {
+        let path = test_path("half");
+        write_atomic(&path, "1").unwrap();
+        write_atomic(&path, "2").unwrap();
+        // 模拟写到一半掉电
+        let data = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 3]).unwrap();
+        assert_eq!(load_with_backup(&path, parse_number).unwrap(), (1, true));
+
+        // 坏文件不会覆盖好的备份
+        write_atomic(&path, "3").unwrap();
+        assert_eq!(read_verified(&backup_path(&path)).unwrap(), "1");
+        assert_eq!(load_with_backup(&path, parse_number).unwrap(), (3, false));
+    }
+
+    #[test]
+    fn recover_from_tmp_after_rename() {
+        let path = test_path("tmp");
+        write_atomic(&path, "1").unwrap();
+        // 模拟旧文件已经改名成备份, 新文件还没改名就掉电
+        fs::rename(&path, backup_path(&path)).unwrap();
+        fs::write(tmp_path(&path), with_checksum("2")).unwrap();
+        assert_eq!(load_with_backup(&path, parse_number).unwrap(), (2, true));
+    }
+
+    #[test]
+    fn empty_file_falls_back() {
+        let path = test_path("empty");
+        write_atomic(&path, "1").unwrap();
+        write_atomic(&path, "2").unwrap();
+        fs::write(&path, "").unwrap();
+        assert_eq!(load_with_backup(&path, parse_number).unwrap(), (1, true));
+        fs::remove_file(backup_path(&path)).unwrap();
+        assert!(load_with_backup(&path, parse_number).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// http 文件接口和命令行的路径检查. 请求里的路径可能有 //, . 和 .., 先规范化再判断能不能访问
+pub const FAT_ROOT: &str = "/fat";
+pub const SYSTEM_DIR: &str = "/fat/system"; // 配置, 状态和系统资源, 不允许通过 http 上传覆盖
+
+/// 规范化绝对路径, 去掉空段和 ., 处理 .., 超出根目录或者不是绝对路径时返回 None
+pub fn normalize(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(format!("/{}", parts.join("/")))
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    path == dir
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// 允许读取的路径, 返回规范化后的路径. 只能访问 /fat 下面
+pub fn readable_path(path: &str) -> Option<String> {
+    let path = normalize(path)?;
+    is_under(&path, FAT_ROOT).then_some(path)
+}
+
+/// 允许上传的路径, 返回规范化后的路径. 只能写 /fat 下面, 不能写 /fat/system 目录
+pub fn writable_path(path: &str) -> Option<String> {
+    let path = normalize(path)?;
+    (is_under(&path, FAT_ROOT) && path != FAT_ROOT && !is_under(&path, SYSTEM_DIR)).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize("/fat//a/./b").as_deref(), Some("/fat/a/b"));
+        assert_eq!(normalize("/fat/x/../y").as_deref(), Some("/fat/y"));
+        assert_eq!(normalize("/fat/../..").as_deref(), None);
+        assert_eq!(normalize("fat/a").as_deref(), None);
+    }
+
+    #[test]
+    fn readable_paths() {
+        assert_eq!(readable_path("/fat/").as_deref(), Some("/fat"));
+        assert_eq!(
+            readable_path("/fat//system/./config").as_deref(),
+            Some("/fat/system/config")
+        );
+        assert_eq!(readable_path("/fat/../etc"), None);
+        assert_eq!(readable_path("/fatx/a"), None);
+    }
+
+    #[test]
+    fn system_dir_not_writable() {
+        assert_eq!(
+            writable_path("/fat/images/a.bmp").as_deref(),
+            Some("/fat/images/a.bmp")
+        );
+        assert_eq!(writable_path("/fat/system/config"), None);
+        assert_eq!(writable_path("//fat/system/config"), None);
+        assert_eq!(writable_path("/fat/x/../system/config.bak"), None);
+        assert_eq!(writable_path("/fat/system"), None);
+        assert_eq!(writable_path("/fat"), None);
+        assert_eq!(writable_path("/fat/../etc"), None);
+        // 只是名字相同前缀的目录可以写
+        assert_eq!(
+            writable_path("/fat/systemx/a").as_deref(),
+            Some("/fat/systemx/a")
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// 不依赖 esp-idf 的纯逻辑: 按键手势, 电池灯解码, 电源策略, 配置升级和补丁, 文件校验等.
+// 目录结构和固件里一样, 固件里的同名模块直接导出这里的模块
+use serde::{Deserialize, Serialize};
+
+pub mod board;
+pub mod cmd_menu;
+pub mod communication;
+pub mod device_config;
+pub mod file_system;
+pub mod simulator;
+pub mod telemetry;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(usize)]
+pub enum ActivePage {
+    Sensor, // 默认单击左边按键
+    #[default]
+    Home, // 默认单击中间按键
+    Image,  // 默认单击右边按键
+
+    FullTime,    // 暂未分配按键
+    Setting,     // 暂未分配按键
+    FullWeather, // 暂未分配按键
+
+    About,        // 默认三击中间按键
+    Notification, // 默认双击中间按键, 通知中心
+    LowBattery,   // 电量过低时自动显示, 不分配按键
+    Sleeping,     // 夜间自动显示, 不分配按键
+    Provisioning, // 配网模式时显示热点名称和二维码, 不分配按键
+    None,
+}
+
+impl ActivePage {
+    /// 当前页面是否需要在每次收到更新命令时刷新
+    pub fn cur_set_page_is_need_refresh(self) -> bool {
+        if self == ActivePage::Home || self == ActivePage::Sensor {
+            return true;
+        }
+        false
+    }
+
+    /// 有的界面只需要短时间显示, 下一个周期就刷新到home
+    pub fn cur_page_is_not_need_record(self) -> bool {
+        self == ActivePage::About || self == ActivePage::Setting
+    }
+
+    /// 根据电量和时间自动显示的页面, 不保存为当前页面, 结束后要回到原来的页面
+    pub fn is_status_page(self) -> bool {
+        self == ActivePage::LowBattery
+            || self == ActivePage::Sleeping
+            || self == ActivePage::Provisioning
+    }
+
+    /// 根据枚举序号转换, 用于从 rtc 内存恢复页面
+    pub fn from_index(index: usize) -> Option<ActivePage> {
+        [
+            ActivePage::Sensor,
+            ActivePage::Home,
+            ActivePage::Image,
+            ActivePage::FullTime,
+            ActivePage::Setting,
+            ActivePage::FullWeather,
+            ActivePage::About,
+            ActivePage::Notification,
+            ActivePage::LowBattery,
+            ActivePage::Sleeping,
+            ActivePage::Provisioning,
+            ActivePage::None,
+        ]
+        .get(index)
+        .copied()
+    }
+}
@@ -0,0 +1,123 @@
+// 电脑上的按键回放模拟. 录制的按键按原来的间隔交给按键绑定, 记录每一步的动作和页面,
+// 不用板子就能把界面操作写成脚本检查. 解析, 间隔和绑定查找都和固件用同一份代码
+use crate::board::key_gesture::PressedKeyInfo;
+use crate::board::key_record::{parse_key_records, replay_delays, KeyRecord};
+use crate::device_config::key_binding::{find_key_action, KeyAction, KeyBinding};
+use crate::ActivePage;
+use std::time::Duration;
+
+/// 回放中的一步
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimStep {
+    pub at: Duration, // 从回放开始算起的时间, 和固件回放时的等待一样
+    pub key: PressedKeyInfo,
+    pub action: Option<KeyAction>, // 按键触发的动作, 没有绑定时为 None
+    pub page: ActivePage,          // 执行动作之后按键绑定使用的页面
+}
+
+/// 模拟的界面状态, 只跟踪决定按键绑定的当前页面
+pub struct Simulator<'a> {
+    bindings: &'a [KeyBinding],
+    page: ActivePage,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(bindings: &'a [KeyBinding], page: ActivePage) -> Self {
+        Self { bindings, page }
+    }
+
+    pub fn page(&self) -> ActivePage {
+        self.page
+    }
+
+    /// 按一次键, 返回触发的动作. 和固件一样, 状态页面和 None 不会成为当前页面
+    pub fn press(&mut self, key: &PressedKeyInfo) -> Option<KeyAction> {
+        let action = find_key_action(self.bindings, key, self.page).cloned();
+        if let Some(KeyAction::SwitchPage(page)) = &action {
+            if *page != ActivePage::None && !page.is_status_page() {
+                self.page = *page;
+            }
+        }
+        action
+    }
+
+    /// 依次回放录制的按键, 不真的等待
+    pub fn replay(&mut self, records: &[KeyRecord]) -> Vec<SimStep> {
+        let mut at = Duration::ZERO;
+        replay_delays(records)
+            .into_iter()
+            .zip(records)
+            .map(|(delay, record)| {
+                at += delay;
+                let action = self.press(&record.key);
+                SimStep {
+                    at,
+                    key: record.key.clone(),
+                    action,
+                    page: self.page,
+                }
+            })
+            .collect()
+    }
+
+    /// 回放录制文件的内容, 格式和 /api/keys/replay 的请求体一样
+    pub fn replay_str(&mut self, content: &str) -> anyhow::Result<Vec<SimStep>> {
+        Ok(self.replay(&parse_key_records(content)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::key_gesture::KeyClickedType;
+    use crate::device_config::key_binding::default_key_bindings;
+
+    #[test]
+    fn scripted_flow() {
+        let bindings = default_key_bindings();
+        let mut sim = Simulator::new(&bindings, ActivePage::Home);
+        let script = r#"
+            # 打开通知页面, 向下滚动, 再回到主页
+            {"at_ms": 1000, "idx": 1, "click_type": "DoubleClicked"}
+            {"at_ms": 1500, "idx": 2, "click_type": "DoubleClicked"}
+            {"at_ms": 60000, "idx": 1, "click_type": "SingleClicked"}
+            {"at_ms": 60100, "idx": 2, "click_type": "HoldRepeat"}
+        "#;
+        let steps = sim.replay_str(script).unwrap();
+        let pages: Vec<_> = steps.iter().map(|step| step.page).collect();
+        assert_eq!(
+            pages,
+            [
+                ActivePage::Notification,
+                ActivePage::Notification,
+                ActivePage::Home,
+                ActivePage::Home,
+            ]
+        );
+        // 通知页面的双击是滚动, 不是弹窗测试
+        assert_eq!(steps[1].action, Some(KeyAction::NotificationScroll(1)));
+        assert_eq!(steps[3].action, None);
+        assert_eq!(steps[1].at, Duration::from_millis(500));
+        assert_eq!(steps[2].at, Duration::from_millis(5500)); // 长间隔按最大间隔处理
+        assert_eq!(sim.page(), ActivePage::Home);
+    }
+
+    #[test]
+    fn status_page_not_current() {
+        let bindings = vec![KeyBinding::new(
+            0,
+            KeyClickedType::SingleClicked,
+            KeyAction::SwitchPage(ActivePage::Sleeping),
+        )];
+        let mut sim = Simulator::new(&bindings, ActivePage::Image);
+        let key = PressedKeyInfo {
+            idx: 0,
+            click_type: KeyClickedType::SingleClicked,
+        };
+        assert_eq!(
+            sim.press(&key),
+            Some(KeyAction::SwitchPage(ActivePage::Sleeping))
+        );
+        assert_eq!(sim.page(), ActivePage::Image);
+    }
+}
@@ -0,0 +1,388 @@
+// 记录每次唤醒各个阶段的耗时, 当天的累计放在 rtc 内存, 换天时把汇总追加到文件,
+// 用来在用户发现续航变短之前看出哪一步变慢了
+use crate::device_config::rtc_state::fnv1a_words;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TELEMETRY_FILE_PATH: &str = "/fat/system/telemetry"; // 每天的汇总, 一行一个 json
+pub const MAX_TELEMETRY_DAYS: usize = 14; // 文件里最多保存的天数, 超过后丢弃最旧的
+const TELEMETRY_MAGIC: u32 = 0x5445_4c4d; // 用来判断 rtc 内存是不是上电后的随机值
+pub const PHASE_NUM: usize = 6;
+
+/// 统计耗时的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Phase {
+    Boot,         // 复位到读完配置和电池状态
+    SensorRead,   // 读传感器
+    Render,       // ratatui 绘制到显存
+    PanelRefresh, // 墨水屏刷新
+    WifiConnect,  // 连接 wifi
+    Http,         // http 请求
+}
+
+impl Phase {
+    pub const ALL: [Phase; PHASE_NUM] = [
+        Phase::Boot,
+        Phase::SensorRead,
+        Phase::Render,
+        Phase::PanelRefresh,
+        Phase::WifiConnect,
+        Phase::Http,
+    ];
+}
+
+/// 一次唤醒中各阶段的耗时
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CycleTimes {
+    pub phase_ms: [u32; PHASE_NUM],
+    pub phase_count: [u32; PHASE_NUM],
+}
+
+impl CycleTimes {
+    const fn new() -> Self {
+        Self {
+            phase_ms: [0; PHASE_NUM],
+            phase_count: [0; PHASE_NUM],
+        }
+    }
+
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        let idx = phase as usize;
+        self.phase_ms[idx] = self.phase_ms[idx].saturating_add(elapsed.as_millis() as u32);
+        self.phase_count[idx] += 1;
+    }
+}
+
+/// 一天的累计, 放在 rtc 内存
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DailyTelemetry {
+    pub day: u32, // 日期, 比如 20250101, 0 表示时间还没同步
+    pub wakeups: u32,
+    pub awake_ms: u32,
+    pub max_awake_ms: u32,
+    pub phase_ms: [u32; PHASE_NUM],
+    pub phase_count: [u32; PHASE_NUM],
+    pub phase_max_ms: [u32; PHASE_NUM], // 单次唤醒中这个阶段的最长耗时
+}
+
+impl DailyTelemetry {
+    const fn new() -> Self {
+        Self {
+            day: 0,
+            wakeups: 0,
+            awake_ms: 0,
+            max_awake_ms: 0,
+            phase_ms: [0; PHASE_NUM],
+            phase_count: [0; PHASE_NUM],
+            phase_max_ms: [0; PHASE_NUM],
+        }
+    }
+
+    /// 换到 day 这一天, 日期变化时返回前一天的汇总并清空累计. 时间还没同步时记到当天
+    pub fn roll(&mut self, day: u32) -> Option<DailySummary> {
+        if day == self.day || day == 0 {
+            return None;
+        }
+        if self.day == 0 {
+            self.day = day;
+            return None;
+        }
+        let summary = (self.wakeups > 0).then(|| self.summary());
+        *self = DailyTelemetry {
+            day,
+            ..Default::default()
+        };
+        summary
+    }
+
+    pub fn add_cycle(&mut self, cycle: &CycleTimes, awake_ms: u32) {
+        self.wakeups += 1;
+        self.awake_ms = self.awake_ms.saturating_add(awake_ms);
+        self.max_awake_ms = self.max_awake_ms.max(awake_ms);
+        for idx in 0..PHASE_NUM {
+            if cycle.phase_count[idx] == 0 {
+                continue;
+            }
+            self.phase_ms[idx] = self.phase_ms[idx].saturating_add(cycle.phase_ms[idx]);
+            self.phase_count[idx] += cycle.phase_count[idx];
+            self.phase_max_ms[idx] = self.phase_max_ms[idx].max(cycle.phase_ms[idx]);
+        }
+    }
+
+    pub fn summary(&self) -> DailySummary {
+        let phases = Phase::ALL
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.phase_count[*idx] > 0)
+            .map(|(idx, phase)| PhaseSummary {
+                phase: *phase,
+                count: self.phase_count[idx],
+                total_ms: self.phase_ms[idx],
+                avg_ms: self.phase_ms[idx] / self.phase_count[idx],
+                max_ms: self.phase_max_ms[idx],
+            })
+            .collect();
+        DailySummary {
+            day: self.day,
+            wakeups: self.wakeups,
+            total_awake_ms: self.awake_ms,
+            avg_awake_ms: self.awake_ms.checked_div(self.wakeups).unwrap_or(0),
+            max_awake_ms: self.max_awake_ms,
+            phases,
+        }
+    }
+
+    fn words(&self) -> impl Iterator<Item = u32> {
+        [self.day, self.wakeups, self.awake_ms, self.max_awake_ms]
+            .into_iter()
+            .chain(self.phase_ms)
+            .chain(self.phase_count)
+            .chain(self.phase_max_ms)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PhaseSummary {
+    pub phase: Phase,
+    pub count: u32,
+    pub total_ms: u32,
+    pub avg_ms: u32,
+    pub max_ms: u32, // 单次唤醒中的最长耗时
+}
+
+/// 一天的汇总, 保存到文件和 http 返回的内容
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DailySummary {
+    pub day: u32,
+    pub wakeups: u32,
+    pub total_awake_ms: u32,
+    pub avg_awake_ms: u32,
+    pub max_awake_ms: u32,
+    pub phases: Vec<PhaseSummary>,
+}
+
+/// 把一天的汇总追加到按行保存的日志, 只保留最近 keep 天, 解析不了的行直接丢掉
+pub fn append_summary(log: &str, summary: &DailySummary, keep: usize) -> anyhow::Result<String> {
+    let mut lines: Vec<String> = log
+        .lines()
+        .filter(|line| serde_json::from_str::<DailySummary>(line).is_ok())
+        .map(str::to_string)
+        .collect();
+    lines.push(serde_json::to_string(summary)?);
+    let skip = lines.len().saturating_sub(keep);
+    let mut log = lines[skip..].join("\n");
+    log.push('\n');
+    Ok(log)
+}
+
+/// 当前唤醒周期的耗时, 各个线程都可能记录
+static CYCLE: Mutex<CycleTimes> = Mutex::new(CycleTimes::new());
+/// 上一个周期结束时距离启动的时间, 单位: 微秒. 常亮模式下一次启动有多个周期
+static LAST_CYCLE_END_US: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+struct TelemetryMemory {
+    magic: u32,
+    daily: DailyTelemetry,
+    checksum: u32,
+}
+
+// 放在 rtc 内存, 深度睡眠时保持, 掉电后丢失当天的统计
+#[link_section = ".rtc.data"]
+static mut TELEMETRY_MEMORY: TelemetryMemory = TelemetryMemory {
+    magic: 0,
+    daily: DailyTelemetry::new(),
+    checksum: 0,
+};
+
+fn load_daily() -> Option<DailyTelemetry> {
+    let memory = unsafe { core::ptr::addr_of!(TELEMETRY_MEMORY).read_volatile() };
+    if memory.magic != TELEMETRY_MAGIC || memory.checksum != fnv1a_words(memory.daily.words()) {
+        return None;
+    }
+    Some(memory.daily)
+}
+
+fn store_daily(daily: &DailyTelemetry) {
+    let memory = TelemetryMemory {
+        magic: TELEMETRY_MAGIC,
+        daily: *daily,
+        checksum: fnv1a_words(daily.words()),
+    };
+    unsafe { core::ptr::addr_of_mut!(TELEMETRY_MEMORY).write_volatile(memory) };
+}
+
+/// 今天的日期, 时间还没同步时返回 0
+fn today() -> u32 {
+    use chrono::Datelike;
+    let now = chrono::Local::now();
+    if now.year() < 2025 {
+        return 0;
+    }
+    now.year() as u32 * 10000 + now.month() * 100 + now.day()
+}
+
+/// 记录一个阶段的耗时
+pub fn record(phase: Phase, elapsed: Duration) {
+    match CYCLE.lock() {
+        Ok(mut cycle) => cycle.add(phase, elapsed),
+        Err(e) => log::warn!("telemetry mutex poisoned: {e}"),
+    }
+}
+
+/// 离开作用域时记录耗时
+pub struct PhaseTimer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        record(self.phase, self.start.elapsed());
+    }
+}
+
+/// 开始统计一个阶段, 返回值离开作用域时结束, 比如 let _timer = telemetry::timer(Phase::Render);
+pub fn timer(phase: Phase) -> PhaseTimer {
+    PhaseTimer {
+        phase,
+        start: Instant::now(),
+    }
+}
+
+/// 一次唤醒结束, since_boot 是现在距离启动的时间, 唤醒时间从启动 (或者上一个周期结束) 算起.
+/// 把这次的耗时累计到当天, 换天时把前一天的汇总写到文件
+pub fn finish_cycle(since_boot: Duration) {
+    let now_us = since_boot.as_micros() as u64;
+    let last_us = LAST_CYCLE_END_US.swap(now_us, Ordering::Relaxed);
+    let awake = Duration::from_micros(now_us.saturating_sub(last_us));
+    let cycle = match CYCLE.lock() {
+        Ok(mut cycle) => std::mem::take(&mut *cycle),
+        Err(e) => {
+            log::warn!("telemetry mutex poisoned: {e}");
+            return;
+        }
+    };
+    let mut daily = load_daily().unwrap_or_default();
+    if let Some(summary) = daily.roll(today()) {
+        if let Err(e) = save_summary(&summary) {
+            log::warn!("save telemetry failed: {e:?}");
+        }
+    }
+    daily.add_cycle(&cycle, awake.as_millis() as u32);
+    log::info!("telemetry cycle: {cycle:?}");
+    store_daily(&daily);
+}
+
+fn save_summary(summary: &DailySummary) -> anyhow::Result<()> {
+    let log = fs::read_to_string(DEFAULT_TELEMETRY_FILE_PATH).unwrap_or_default();
+    let log = append_summary(&log, summary, MAX_TELEMETRY_DAYS)?;
+    if let Some(parent) = std::path::Path::new(DEFAULT_TELEMETRY_FILE_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(DEFAULT_TELEMETRY_FILE_PATH, log)?;
+    Ok(())
+}
+
+/// 文件里保存的每天汇总, 最后一项是今天到现在为止的累计
+pub fn daily_summaries() -> Vec<DailySummary> {
+    let log = fs::read_to_string(DEFAULT_TELEMETRY_FILE_PATH).unwrap_or_default();
+    let mut summaries: Vec<DailySummary> = log
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    if let Some(daily) = load_daily().filter(|daily| daily.wakeups > 0) {
+        summaries.push(daily.summary());
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle(phases: &[(Phase, u64)]) -> CycleTimes {
+        let mut cycle = CycleTimes::default();
+        for (phase, ms) in phases {
+            cycle.add(*phase, Duration::from_millis(*ms));
+        }
+        cycle
+    }
+
+    #[test]
+    fn daily_summary_aggregates_cycles() {
+        let mut daily = DailyTelemetry {
+            day: 20250101,
+            ..Default::default()
+        };
+        daily.add_cycle(
+            &cycle(&[(Phase::Boot, 300), (Phase::PanelRefresh, 1200)]),
+            2000,
+        );
+        daily.add_cycle(
+            &cycle(&[
+                (Phase::Boot, 500),
+                (Phase::PanelRefresh, 1000),
+                (Phase::Http, 100),
+                (Phase::Http, 200),
+            ]),
+            4000,
+        );
+        let summary = daily.summary();
+        assert_eq!(summary.wakeups, 2);
+        assert_eq!(summary.avg_awake_ms, 3000);
+        assert_eq!(summary.max_awake_ms, 4000);
+        assert_eq!(
+            summary.phases[0],
+            PhaseSummary {
+                phase: Phase::Boot,
+                count: 2,
+                total_ms: 800,
+                avg_ms: 400,
+                max_ms: 500,
+            }
+        );
+        // 一次唤醒中多次 http 请求的最长耗时按这次唤醒的总和算
+        let http = summary.phases.iter().find(|p| p.phase == Phase::Http);
+        assert_eq!(http.map(|p| (p.count, p.max_ms)), Some((2, 300)));
+        assert!(!summary.phases.iter().any(|p| p.phase == Phase::WifiConnect));
+    }
+
+    #[test]
+    fn roll_to_next_day() {
+        let mut daily = DailyTelemetry::default();
+        daily.add_cycle(&cycle(&[(Phase::Boot, 300)]), 1000);
+        // 时间同步之前的记录算到同步后的那一天
+        assert_eq!(daily.roll(20250101), None);
+        assert_eq!(daily.day, 20250101);
+        assert_eq!(daily.roll(0), None);
+        assert_eq!(daily.roll(20250101), None);
+
+        let summary = daily.roll(20250102).unwrap();
+        assert_eq!((summary.day, summary.wakeups), (20250101, 1));
+        assert_eq!(daily.day, 20250102);
+        assert_eq!(daily.wakeups, 0);
+    }
+
+    #[test]
+    fn log_keeps_recent_days() {
+        let mut log = String::from("broken line\n");
+        for day in 1..=5 {
+            let summary = DailyTelemetry {
+                day,
+                ..Default::default()
+            }
+            .summary();
+            log = append_summary(&log, &summary, 3).unwrap();
+        }
+        let days: Vec<u32> = log
+            .lines()
+            .map(|line| serde_json::from_str::<DailySummary>(line).unwrap().day)
+            .collect();
+        assert_eq!(days, vec![3, 4, 5]);
+    }
+}
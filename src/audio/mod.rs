@@ -1,3 +1,4 @@
+use crate::board::peripheral::AudioPeripherals;
 use awedio::manager::Manager;
 use awedio::sounds;
 use awedio::sounds::{open_file, MemorySound};
@@ -5,7 +6,7 @@ use hound::WavReader;
 use std::io::Cursor;
 use std::path::Path;
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 const WAV_DATA: &[u8] = include_bytes!("../../assets/resource/test_resource/test_audio.wav");
@@ -16,23 +17,37 @@ pub enum AudioCmd {
     Music(String),  // 播放音乐, 路径
 }
 
-/// 扬声器线程, 处理各种音频事件
+/// 扬声器线程, 处理各种音频事件, 收到第一个音频事件时才初始化音频外设
 pub fn speaker_task(
-    mut manager: Manager,
+    audio: Arc<Mutex<AudioPeripherals>>,
     rx: std::sync::mpsc::Receiver<AudioCmd>,
     exit: Arc<AtomicBool>,
 ) -> anyhow::Result<()> {
     let beep_run_end = Arc::new(AtomicBool::new(true));
+    let mut audio_manager: Option<Manager> = None;
     while !exit.load(std::sync::atomic::Ordering::Relaxed) {
         let Ok(rx_cmd) = rx.recv() else {
             continue;
         };
         log::info!("rx_cmd: {rx_cmd:?}");
+        if audio_manager.is_none() {
+            let manager = audio
+                .lock()
+                .map_err(|e| anyhow::anyhow!("audio mutex poisoned: {e}"))?
+                .ensure();
+            match manager {
+                Ok(manager) => audio_manager = Some(manager),
+                Err(e) => log::warn!("init audio failed: {e:?}"),
+            }
+        }
+        let Some(manager) = audio_manager.as_mut() else {
+            continue;
+        };
         match rx_cmd {
             AudioCmd::Beep(times, duration) => {
                 log::info!("beep: {times:?}, duration: {duration}");
                 if beep_run_end.load(std::sync::atomic::Ordering::Relaxed) {
-                    play_button_beep(&mut manager, times, duration, beep_run_end.clone());
+                    play_button_beep(manager, times, duration, beep_run_end.clone());
                 } else {
                     log::info!("beep still running");
                 }
@@ -61,18 +76,21 @@ pub fn play_sine_wav(manager: &mut Manager, play_time_ms: u64) {
     log::info!("stopping sine wav");
 }
 
+/// 响铃 times 次, 每次响 duration_ms 后停同样的时间
 pub fn play_button_beep(manager: &mut Manager, times: u32, duration_ms: u64, end: Arc<AtomicBool>) {
     end.store(false, std::sync::atomic::Ordering::Relaxed);
 
-    let beep = sounds::SineWave::new(2700.0);
-    manager.play(Box::new(beep));
-
     let mut m = manager.clone();
     std::thread::spawn(move || {
-        for _ in 0..times {
+        for i in 0..times {
+            let beep = sounds::SineWave::new(2700.0);
+            m.play(Box::new(beep));
             std::thread::sleep(Duration::from_millis(duration_ms));
+            m.clear();
+            if i + 1 < times {
+                std::thread::sleep(Duration::from_millis(duration_ms));
+            }
         }
-        m.clear();
         end.store(true, std::sync::atomic::Ordering::Relaxed);
         log::info!("beep end");
     });
@@ -1,23 +1,9 @@
-use button_driver::config::{DEFAULT_DEBOUNCE, DEFAULT_HOLD, DEFAULT_RELEASE};
-use button_driver::{Button, ButtonConfig, Mode};
+use crate::board::key_gesture::{GestureConfig, KeyGestureGroup};
 use esp_idf_svc::hal::gpio::{AnyInputPin, Input, PinDriver};
 use std::thread::JoinHandle;
 use std::time::Instant;
 
-#[derive(Debug, PartialEq)]
-pub enum KeyClickedType {
-    NoClick,
-    SingleClicked,
-    DoubleClicked,
-    TripleClicked,
-}
-
-/// 按下按键时发送的消息
-#[derive(Debug, PartialEq)]
-pub struct PressedKeyInfo {
-    pub idx: usize,                 // 按键索引, 按照传入的容器顺序
-    pub click_type: KeyClickedType, // 按下按键类型
-}
+pub use crate::board::key_gesture::{KeyClickedType, PressedKeyInfo, FACTORY_RESET_KEYS};
 
 /// 按键设备
 #[derive(Debug)]
@@ -35,17 +21,7 @@ impl DeviceButton {
     ) -> anyhow::Result<DeviceButton> {
         let mut keys = Vec::new();
         for key_pin in key_pins.into_iter() {
-            let key = PinDriver::input(key_pin)?;
-            let btn: Button<PinDriver<'_, _, _>, Instant> = Button::new(
-                key,
-                ButtonConfig {
-                    debounce: DEFAULT_DEBOUNCE * 1.5 as u32,
-                    release: DEFAULT_RELEASE * 1.5 as u32,
-                    hold: DEFAULT_HOLD,
-                    mode: Mode::PullUp,
-                },
-            );
-            keys.push(btn);
+            keys.push(PinDriver::input(key_pin)?);
         }
 
         let exit_clone = exit.clone();
@@ -61,30 +37,22 @@ impl DeviceButton {
         })
     }
 
+    /// 轮询按键电平交给 KeyGestureGroup 识别, 按键低电平有效
     fn key_run(
-        mut keys: Vec<Button<PinDriver<AnyInputPin, Input>, Instant>>,
+        keys: Vec<PinDriver<AnyInputPin, Input>>,
         tx: std::sync::mpsc::Sender<PressedKeyInfo>,
         exit: std::sync::Arc<std::sync::atomic::AtomicBool>,
     ) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut gesture = KeyGestureGroup::new(keys.len(), GestureConfig::default());
         while !exit.load(std::sync::atomic::Ordering::Relaxed) {
-            for (idx, key) in keys.iter_mut().enumerate() {
-                key.tick();
-                let mut click_type = KeyClickedType::NoClick;
-                if key.is_clicked() {
-                    click_type = KeyClickedType::SingleClicked;
-                } else if key.is_double_clicked() {
-                    click_type = KeyClickedType::DoubleClicked;
-                } else if key.is_triple_clicked() {
-                    click_type = KeyClickedType::TripleClicked;
-                }
-                if click_type != KeyClickedType::NoClick {
-                    let key_msg = PressedKeyInfo { idx, click_type };
-                    // log::info!("send key msg: {key_msg:?}");
-                    if let Err(e) = tx.send(key_msg) {
-                        log::error!("key msg send failed: {e:?}");
-                    }
+            let levels: Vec<bool> = keys.iter().map(|key| key.is_low()).collect();
+            let now = start.elapsed().as_millis() as u64;
+            for key_msg in gesture.update(now, &levels) {
+                // log::info!("send key msg: {key_msg:?}");
+                if let Err(e) = tx.send(key_msg) {
+                    log::error!("key msg send failed: {e:?}");
                 }
-                key.reset();
             }
             std::thread::sleep(std::time::Duration::from_millis(10));
         }
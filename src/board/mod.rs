@@ -5,6 +5,8 @@ pub mod peripheral;
 pub mod power_manage;
 pub mod psram;
 
+pub use ele_ds_core::board::{battery_decoder, key_gesture, key_record};
+
 pub mod share_i2c_bus {
     use core::cell::RefCell;
     use embedded_hal::i2c::{ErrorType, I2c, Operation};
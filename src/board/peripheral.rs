@@ -1,30 +1,41 @@
+use crate::audio::play_sine_wav;
+use crate::board::battery_decoder::BatteryStatus;
 use crate::board::button::{DeviceButton, PressedKeyInfo};
 use crate::board::es8388;
 use crate::board::es8388::driver::{Es8388, RunMode};
-use crate::board::power_manage::DeviceBattery;
+use crate::board::power_manage::{DeviceBattery, WakeupCause};
 use crate::board::share_i2c_bus::SharedI2cDevice;
+use crate::device_config::wifi_networks::{connect_order, ScannedAp, WifiNetwork};
 use crate::device_config::DeviceConfig;
 use crate::file_system::nvs_flash_filesystem_init;
+use crate::notification::NotificationCenter;
+use crate::telemetry::{self, Phase};
+use crate::ui::popup::PopupQueue;
 use crate::ActivePage;
 use anyhow::Context;
 use awedio::manager::Manager;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::{Circle, PrimitiveStyle, Rectangle};
 use embedded_sht3x::{Measurement, Repeatability, Sht3x, DEFAULT_I2C_ADDRESS};
+use embedded_svc::ipv4;
 use embedded_svc::ipv4::IpInfo;
 use embedded_svc::wifi;
 use embedded_svc::wifi::AuthMethod;
 use enumset::EnumSet;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::delay::Ets;
-use esp_idf_svc::hal::gpio::{AnyIOPin, AnyInputPin, IOPin, Input, Output, PinDriver};
+use esp_idf_svc::hal::gpio::{
+    AnyIOPin, AnyInputPin, Gpio2, Gpio45, Gpio47, Gpio48, IOPin, Input, Output, PinDriver,
+};
 use esp_idf_svc::hal::i2c::{I2cConfig, I2cDriver};
-use esp_idf_svc::hal::i2s::I2sDriver;
+use esp_idf_svc::hal::i2s::{I2sDriver, I2S0};
 use esp_idf_svc::hal::interrupt::InterruptType;
+use esp_idf_svc::hal::modem::Modem;
 use esp_idf_svc::hal::peripherals::Peripherals;
 use esp_idf_svc::hal::prelude::Hertz;
 use esp_idf_svc::hal::spi;
 use esp_idf_svc::hal::spi::{SpiDeviceDriver, SpiDriver, SpiDriverConfig};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration};
 use esp_idf_svc::nvs::{EspNvsPartition, NvsDefault};
 use esp_idf_svc::wifi::EspWifi;
 use ssd1680::color::Black;
@@ -52,7 +63,10 @@ pub struct Screen {
     pub current_page: ActivePage,
     pub delay: Ets,
     pub last_sensor_status: Option<AllSensorData>,
+    pub battery_status: Option<BatteryStatus>, // 状态栏显示的电量
     pub last_hour: u32,
+    pub notifications: NotificationCenter, // 文件系统挂载后再加载
+    pub popups: PopupQueue,
 }
 
 impl Screen {
@@ -78,7 +92,10 @@ impl Screen {
             current_page: ActivePage::None,
             delay,
             last_sensor_status: None,
+            battery_status: None,
             last_hour: 0,
+            notifications: NotificationCenter::default(),
+            popups: PopupQueue::default(),
         })
     }
     /// 测试屏幕刷新是否正常, 画圆形和方块
@@ -108,37 +125,104 @@ impl Screen {
     }
 }
 
+/// 还没初始化的音频外设引脚
+struct AudioParts {
+    i2s: I2S0,
+    bclk: Gpio47,
+    dout: Gpio45,
+    mclk: Gpio2,
+    ws: Gpio48,
+}
+
+/// 延迟初始化的 wifi 外设, 第一次连网时才创建驱动
+struct WifiParts {
+    modem: Modem,
+    sysloop: EspSystemEventLoop,
+    nvs: EspNvsPartition<NvsDefault>,
+}
+
+/// 延迟初始化的音频外设, 第一次播放声音时才初始化 i2s 和 es8388.
+/// 和 BoardPeripherals 分开加锁, 连 wifi 时拿着板子的锁也不会卡住声音
+pub struct AudioPeripherals {
+    pub manager: Option<Manager>, // 调用 ensure() 后才有
+    pub es8388: Option<Es8388Type>,
+    parts: Option<AudioParts>,
+    iic_bus: Arc<Mutex<I2cDriver<'static>>>,
+}
+
+impl AudioPeripherals {
+    /// 第一次用到声音时初始化 i2s 和 es8388, 返回音频管理器
+    pub fn ensure(&mut self) -> anyhow::Result<Manager> {
+        if let Some(manager) = &self.manager {
+            return Ok(manager.clone());
+        }
+        let parts = self
+            .parts
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("audio peripherals already taken"))?;
+        // i2s相关初始化
+        let i2s_driver = I2sDriver::new_std_tx(
+            parts.i2s,
+            &es8388::driver::default_i2s_config(),
+            parts.bclk, // bclk i2s总线的时钟
+            // peripherals.pins.gpio45,      // din codec支持录音功能可以把麦克风数据回传给单片机, 实际是sd_out
+            parts.dout,       // dout 音频输出, 实际是 sd_in
+            Some(parts.mclk), // mclk 给codec芯片提供的始终
+            parts.ws,         // ws 左右声道选择
+        )
+        .context("Failed to initialize I2S bidirectional driver")?;
+        let backend = awedio_esp32::Esp32Backend::with_defaults(i2s_driver, 1, 44100, 128);
+        let mut audio_manager = backend.start();
+        let es8388_i2c = SharedI2cDevice(self.iic_bus.clone());
+        let mut es8388 = Es8388::new(es8388_i2c, es8388::driver::CHIP_ADDR, RunMode::AdcDac);
+        es8388.init()?;
+        es8388.start()?;
+        // 预热es8388
+        play_sine_wav(&mut audio_manager, 50);
+        log::info!("audio initialized");
+
+        self.es8388 = Some(es8388);
+        self.manager = Some(audio_manager.clone());
+        Ok(audio_manager)
+    }
+}
+
+pub type BoardBattery = DeviceBattery<
+    PinDriver<'static, AnyIOPin, Input>,
+    PinDriver<'static, AnyIOPin, Input>,
+    PinDriver<'static, AnyIOPin, Input>,
+>;
+
 #[allow(dead_code)]
 pub struct BoardPeripherals {
-    pub wifi: EspWifi<'static>,
-    // 音频相关
-    pub audio_manager: Option<Manager>,
-    pub es8388: Es8388Type,
+    pub wifi: Option<EspWifi<'static>>, // 调用 ensure_wifi() 后才有
+    wifi_parts: Option<WifiParts>,
+    pub audio: Arc<Mutex<AudioPeripherals>>, // 音频单独加锁
     pub spk_en: PinDriver<'static, AnyIOPin, Output>,
 
     vout_3v3: PinDriver<'static, AnyIOPin, Output>,
     vout_5v: PinDriver<'static, AnyIOPin, Output>,
     sht3x_rst: PinDriver<'static, AnyIOPin, Output>,
+    iic_bus: Arc<Mutex<I2cDriver<'static>>>,
     pub sht3x: Sht3x<SharedI2cDevice<Arc<Mutex<I2cDriver<'static>>>>, Ets>,
-    pub device_battery: DeviceBattery<
-        PinDriver<'static, AnyIOPin, Input>,
-        PinDriver<'static, AnyIOPin, Input>,
-        PinDriver<'static, AnyIOPin, Input>,
-    >,
+    pub device_battery: Arc<Mutex<BoardBattery>>, // 采样要 1.5 秒, 单独加锁, 采样时不占用板子
 
     pub device_button: DeviceButton,
     pub exit: Arc<AtomicBool>, // 发送信号让读按键线程退出
     pub key_rx: Option<std::sync::mpsc::Receiver<PressedKeyInfo>>,
+    pub key_tx: std::sync::mpsc::Sender<PressedKeyInfo>, // 用来注入按键, 比如唤醒深度睡眠的按键
 
     pub screen: Option<Screen>, // 屏幕对象需要被多个线程处理, 比如修改页面, 刷新页面
 }
 
 #[allow(dead_code)]
 impl BoardPeripherals {
-    pub fn new() -> anyhow::Result<BoardPeripherals> {
+    /// 定时唤醒时只初始化传感器, 屏幕和按键, 音频和 wifi 等用到时再初始化
+    pub fn new(wakeup_cause: WakeupCause) -> anyhow::Result<BoardPeripherals> {
         let peripherals = Peripherals::take()?;
         let sysloop = EspSystemEventLoop::take()?;
         let nvs = EspNvsPartition::<NvsDefault>::take()?;
+        let fast_wake = wakeup_cause == WakeupCause::Timer;
 
         // 基本io口初始化
         let mut vout_3v3 = PinDriver::output(peripherals.pins.gpio10.downgrade())?;
@@ -147,12 +231,15 @@ impl BoardPeripherals {
         vout_5v.set_high()?;
         let mut sht3x_rst = PinDriver::output(peripherals.pins.gpio19.downgrade())?;
         sht3x_rst.set_high()?;
-        let mut device_battery = DeviceBattery::new(
+        // 功放默认关闭, 避免引脚悬空
+        let mut spk_en = PinDriver::output(peripherals.pins.gpio20.downgrade())?;
+        spk_en.set_low()?;
+        // 电量需要采样一段时间, 由调用者决定是否采样
+        let device_battery = DeviceBattery::new(
             PinDriver::input(peripherals.pins.gpio12.downgrade())?,
             PinDriver::input(peripherals.pins.gpio13.downgrade())?,
             PinDriver::input(peripherals.pins.gpio14.downgrade())?,
         );
-        log::info!("current battery: {:?}", device_battery.current_vbat()?);
 
         let i2c_config = I2cConfig {
             baudrate: Hertz(400_000),
@@ -169,7 +256,12 @@ impl BoardPeripherals {
         )?;
 
         // 可能是底层库有bug, 必须先执行一遍i2c的读写操作才能进行后续读传感器, 不然会报错 ESP_ERR_TIMEOUT
-        Self::i2c_scan(&mut i2c_driver);
+        // 定时唤醒时只访问一次传感器地址, 不扫描整条总线
+        if fast_wake {
+            Self::i2c_probe(&mut i2c_driver, DEFAULT_I2C_ADDRESS);
+        } else {
+            Self::i2c_scan(&mut i2c_driver);
+        }
         let iic_bus = Arc::new(Mutex::new(i2c_driver));
         let mut sht3x = Sht3x::new(SharedI2cDevice(iic_bus.clone()), DEFAULT_I2C_ADDRESS, Ets);
         sht3x.repeatability = Repeatability::High;
@@ -183,7 +275,7 @@ impl BoardPeripherals {
         ];
         let exit = Arc::new(AtomicBool::new(false));
         let key_read_exit_clone = exit.clone();
-        let device_button = DeviceButton::new(key_pins, key_tx, key_read_exit_clone)?;
+        let device_button = DeviceButton::new(key_pins, key_tx.clone(), key_read_exit_clone)?;
 
         let spi = peripherals.spi2;
         let sclk = peripherals.pins.gpio4;
@@ -202,50 +294,65 @@ impl BoardPeripherals {
         let spi = SpiDeviceDriver::new(spi, Some(cs), &spi::config::Config::new())?;
         let screen = Screen::new(spi, busy, dc, rst, 128, 296)?;
 
-        let i2s = peripherals.i2s0;
-        // i2s相关初始化
-        let i2s_driver = I2sDriver::new_std_tx(
-            i2s,
-            &es8388::driver::default_i2s_config(),
-            peripherals.pins.gpio47, // bclk i2s总线的时钟
-            // peripherals.pins.gpio45,      // din codec支持录音功能可以把麦克风数据回传给单片机, 实际是sd_out
-            peripherals.pins.gpio45,      // dout 音频输出, 实际是 sd_in
-            Some(peripherals.pins.gpio2), // mclk 给codec芯片提供的始终
-            peripherals.pins.gpio48,      // ws 左右声道选择
-        )
-        .context("Failed to initialize I2S bidirectional driver")?;
-        let backend = awedio_esp32::Esp32Backend::with_defaults(i2s_driver, 1, 44100, 128);
-        let audio_manager = backend.start();
-        let es8388_i2c = SharedI2cDevice(iic_bus.clone());
-        let mut spk_en = PinDriver::output(peripherals.pins.gpio20.downgrade())?;
-        spk_en.set_low()?;
-        let mut es8388 = Es8388::new(es8388_i2c, es8388::driver::CHIP_ADDR, RunMode::AdcDac);
-        es8388.init()?;
-        es8388.start()?;
-        let wifi = EspWifi::new(peripherals.modem, sysloop, Some(nvs.clone()))?;
+        let audio = AudioPeripherals {
+            manager: None,
+            es8388: None,
+            parts: Some(AudioParts {
+                i2s: peripherals.i2s0,
+                bclk: peripherals.pins.gpio47,
+                dout: peripherals.pins.gpio45,
+                mclk: peripherals.pins.gpio2,
+                ws: peripherals.pins.gpio48,
+            }),
+            iic_bus: iic_bus.clone(),
+        };
+        let wifi_parts = WifiParts {
+            modem: peripherals.modem,
+            sysloop,
+            nvs,
+        };
 
         Ok(BoardPeripherals {
-            wifi,
-            es8388,
-            audio_manager: Some(audio_manager),
+            wifi: None,
+            wifi_parts: Some(wifi_parts),
+            audio: Arc::new(Mutex::new(audio)),
             spk_en,
 
             vout_3v3,
             vout_5v,
             sht3x_rst,
+            iic_bus,
             sht3x,
-            device_battery,
+            device_battery: Arc::new(Mutex::new(device_battery)),
             device_button,
 
             exit,
             key_rx: Some(key_rx),
+            key_tx,
 
             screen: Some(screen),
         })
     }
 
+    /// 第一次连网时创建 wifi 驱动
+    pub fn ensure_wifi(&mut self) -> anyhow::Result<&mut EspWifi<'static>> {
+        if self.wifi.is_none() {
+            let parts = self
+                .wifi_parts
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("wifi peripherals already taken"))?;
+            let wifi = EspWifi::new(parts.modem, parts.sysloop, Some(parts.nvs))?;
+            log::info!("wifi driver initialized");
+            self.wifi = Some(wifi);
+        }
+        self.wifi
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("wifi not initialized"))
+    }
+
     /// 一次性读取所有传感器数据接口, 保存在 DeviceStatus
     pub fn read_all_sensor(&mut self) -> anyhow::Result<AllSensorData> {
+        let _timer = telemetry::timer(Phase::SensorRead);
         let sht3x_measure = self
             .sht3x
             .single_measurement()
@@ -253,58 +360,167 @@ impl BoardPeripherals {
         Ok(AllSensorData { sht3x_measure })
     }
 
+    /// 访问一次指定地址, 用于唤醒 i2c 总线
+    pub fn i2c_probe(i2c: &mut I2cDriver, addr: u8) {
+        if let Err(e) = i2c.write(addr, &[], 50) {
+            log::warn!("i2c probe 0x{addr:02X} failed: {e:?}");
+        }
+    }
+
     /// 测试功能, 检查总线上的i2c设备
     pub fn i2c_scan(i2c: &mut I2cDriver) {
         log::info!("Scanning I2C bus...");
-        for addr in 1..127 {
-            if i2c.write(addr, &[], 50).is_ok() {
-                log::info!("Found device at address: 0x{addr:02X}");
-            }
+        for addr in Self::i2c_devices(i2c) {
+            log::info!("Found device at address: 0x{addr:02X}");
         }
         log::info!("Scan complete.");
     }
 
+    /// 返回总线上有应答的地址
+    fn i2c_devices(i2c: &mut I2cDriver) -> Vec<u8> {
+        (1..127)
+            .filter(|addr| i2c.write(*addr, &[], 50).is_ok())
+            .collect()
+    }
+
+    /// 命令行用的 i2c 扫描, 和传感器共用总线
+    pub fn i2c_bus_devices(&self) -> anyhow::Result<Vec<u8>> {
+        let Ok(mut i2c) = self.iic_bus.lock() else {
+            anyhow::bail!("i2c bus mutex poisoned");
+        };
+        Ok(Self::i2c_devices(&mut i2c))
+    }
+
     pub fn init_filesystem_load_config() -> anyhow::Result<DeviceConfig> {
         nvs_flash_filesystem_init()?;
         let device_config = DeviceConfig::load_config()?;
         log::info!("device config: {device_config:?}");
         Ok(device_config)
     }
+    /// 扫描附近的热点, 按 connect_order() 的顺序依次连接保存的网络, 返回 ip 和连上的 ssid.
+    /// timeout 是所有网络加起来的时间, 单位: 秒, 保存的网络再多也不会拿着 board 锁太久
     pub fn wifi_connect(
-        wifi: &mut EspWifi,
-        ssid: &str,
-        passwd: &str,
+        wifi: &mut EspWifi<'static>,
+        networks: &[WifiNetwork],
         timeout: u8,
-    ) -> anyhow::Result<IpInfo> {
-        let ssid = heapless::String::<32>::from_str(ssid)
-            .map_err(|_| anyhow::anyhow!("ssid too long:{ssid}"))?;
+    ) -> anyhow::Result<(IpInfo, String)> {
+        let _timer = telemetry::timer(Phase::WifiConnect);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout as u64);
+        if !wifi.is_started()? {
+            wifi.set_configuration(&wifi::Configuration::Client(Default::default()))?;
+            wifi.start()?;
+        } else if wifi.is_connected()? {
+            // 重新选择网络, 切换 netif 前先断开
+            wifi.disconnect()?;
+        }
+        // 扫描失败时按优先级尝试所有保存的网络
+        let access_points = wifi.scan().unwrap_or_else(|e| {
+            log::warn!("wifi scan failed: {e:?}");
+            Vec::new()
+        });
+        let scanned: Vec<ScannedAp> = access_points
+            .iter()
+            .map(|ap| ScannedAp {
+                ssid: ap.ssid.as_str(),
+                rssi: ap.signal_strength,
+            })
+            .collect();
+        for idx in connect_order(networks, &scanned) {
+            let network = &networks[idx];
+            if std::time::Instant::now() >= deadline {
+                log::warn!("wifi connect time used up, skip {}", network.ssid);
+                break;
+            }
+            // 扫描不到的隐藏网络不知道加密方式, 按 WPA2 尝试
+            let auth_method = access_points
+                .iter()
+                .filter(|ap| ap.ssid.as_str() == network.ssid)
+                .max_by_key(|ap| ap.signal_strength)
+                .map_or(AuthMethod::WPA2Personal, |ap| {
+                    Self::auth_threshold(ap.auth_method)
+                });
+            log::info!("connecting to {}, auth: {auth_method:?}", network.ssid);
+            match Self::wifi_connect_network(wifi, network, auth_method, deadline) {
+                Ok(ip_info) => return Ok((ip_info, network.ssid.clone())),
+                Err(e) => {
+                    log::warn!("connect to {} failed: {e:?}", network.ssid);
+                    if let Err(e) = wifi.disconnect() {
+                        log::warn!("wifi disconnect failed: {e:?}");
+                    }
+                }
+            }
+        }
+        anyhow::bail!("WiFi connect failed, no known network available");
+    }
+
+    /// 热点的加密方式作为连接时的最低要求, WPA2/WPA3 混合的热点按 WPA2 连接
+    fn auth_threshold(auth_method: Option<AuthMethod>) -> AuthMethod {
+        match auth_method {
+            None => AuthMethod::None,
+            Some(AuthMethod::WPA2WPA3Personal) => AuthMethod::WPA2Personal,
+            Some(auth_method) => auth_method,
+        }
+    }
 
+    /// 连接一个网络, 按网络的设置切换静态 ip 或者 dhcp, 到 deadline 还没拿到 ip 算失败
+    fn wifi_connect_network(
+        wifi: &mut EspWifi<'static>,
+        network: &WifiNetwork,
+        auth_method: AuthMethod,
+        deadline: std::time::Instant,
+    ) -> anyhow::Result<IpInfo> {
+        let ssid = heapless::String::<32>::from_str(&network.ssid)
+            .map_err(|_| anyhow::anyhow!("ssid too long:{}", network.ssid))?;
+        let passwd = network.password.expose();
         let password = heapless::String::<64>::from_str(passwd)
-            .map_err(|_| anyhow::anyhow!("passwd too long:{passwd}"))?;
+            .map_err(|_| anyhow::anyhow!("passwd too long: {} bytes", passwd.len()))?;
+
+        let mut netif_conf = NetifConfiguration::wifi_default_client();
+        if let Some(static_ip) = &network.static_ip {
+            if static_ip.prefix_len > 32 {
+                anyhow::bail!("bad prefix length: {}", static_ip.prefix_len);
+            }
+            netif_conf.ip_configuration = Some(ipv4::Configuration::Client(
+                ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                    ip: static_ip.ip,
+                    subnet: ipv4::Subnet {
+                        gateway: static_ip.gateway,
+                        mask: ipv4::Mask(static_ip.prefix_len),
+                    },
+                    dns: Some(static_ip.dns.unwrap_or(static_ip.gateway)),
+                    secondary_dns: None,
+                }),
+            ));
+        }
+        wifi.swap_netif_sta(EspNetif::new_with_conf(&netif_conf)?)?;
 
         let wifi_cfg = wifi::Configuration::Client(wifi::ClientConfiguration {
             ssid,
             password,
-            auth_method: AuthMethod::WPA2Personal,
+            auth_method,
             ..Default::default()
         });
         wifi.set_configuration(&wifi_cfg)?;
-        wifi.start()?;
         wifi.connect()?;
 
-        for i in 1..=timeout {
+        let start = std::time::Instant::now();
+        while std::time::Instant::now() < deadline {
             std::thread::sleep(std::time::Duration::from_secs(1));
             if wifi.is_connected()? {
                 let netif = wifi.sta_netif();
                 if let Ok(ip_info) = netif.get_ip_info() {
                     if !ip_info.ip.is_unspecified() {
-                        log::info!("WiFi connected IP: {:?}, total used time: {i}", ip_info.ip);
+                        log::info!(
+                            "WiFi connected IP: {:?}, total used time: {}",
+                            ip_info.ip,
+                            start.elapsed().as_secs()
+                        );
                         return Ok(ip_info);
                     }
                 }
             }
         }
-        anyhow::bail!("WiFi connect failed");
+        anyhow::bail!("WiFi connect timeout");
     }
 }
 
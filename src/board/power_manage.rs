@@ -1,6 +1,66 @@
+use crate::board::battery_decoder::{
+    decode_trace, BatteryStatus, SAMPLE_INTERVAL_MS, SAMPLE_WINDOW_MS,
+};
+use crate::device_config::rtc_state;
+use crate::telemetry;
 use chrono::Timelike;
 use esp_idf_svc::sys::*;
 
+pub use crate::board::battery_decoder::DeviceBatteryType;
+
+/// 可以唤醒深度睡眠的按键, (gpio, 按键索引), 中间按键 gpio46 不是 RTC IO, 不能唤醒深度睡眠
+pub const WAKEUP_KEY_PINS: [(i32, usize); 2] = [(3, 0), (9, 2)];
+
+/// 启动原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WakeupCause {
+    PowerOn,    // 上电或者复位, 不是从睡眠唤醒
+    Timer,      // 定时器唤醒
+    Key(usize), // 按键唤醒, 按键索引
+    Other(u32), // 其他唤醒源
+}
+
+/// 获取本次启动的原因, 按键唤醒时返回对应的按键索引
+pub fn wakeup_cause() -> WakeupCause {
+    let cause = unsafe { esp_sleep_get_wakeup_cause() };
+    match cause {
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED => WakeupCause::PowerOn,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeupCause::Timer,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => {
+            let status = unsafe { esp_sleep_get_ext1_wakeup_status() };
+            WAKEUP_KEY_PINS
+                .iter()
+                .find(|(gpio, _)| status & (1 << gpio) != 0)
+                .map_or(WakeupCause::Other(cause), |(_, idx)| WakeupCause::Key(*idx))
+        }
+        _ => WakeupCause::Other(cause),
+    }
+}
+
+/// 设置按键唤醒, 按键低电平有效, 任意一个按键按下都唤醒
+fn enable_key_wakeup() -> anyhow::Result<()> {
+    let mut mask = 0u64;
+    for (gpio, _) in WAKEUP_KEY_PINS {
+        mask |= 1 << gpio;
+        unsafe {
+            esp!(rtc_gpio_pullup_en(gpio))?;
+            esp!(rtc_gpio_pulldown_dis(gpio))?;
+        }
+    }
+    unsafe {
+        // 睡眠时保持 RTC 外设供电, 不然上拉会失效
+        esp!(esp_sleep_pd_config(
+            esp_sleep_pd_domain_t_ESP_PD_DOMAIN_RTC_PERIPH,
+            esp_sleep_pd_option_t_ESP_PD_OPTION_ON,
+        ))?;
+        esp!(esp_sleep_enable_ext1_wakeup(
+            mask,
+            esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_LOW,
+        ))?;
+    }
+    Ok(())
+}
+
 pub fn enter_light_sleep_mode() -> anyhow::Result<()> {
     let wakeup_time_us = 10 * 1000 * 1000;
     unsafe {
@@ -32,9 +92,25 @@ pub fn next_minute_left_time() -> u64 {
 
     (seconds_to_wait as u64 * 1_000_000) + (nanos_to_wait / 1_000) as u64
 }
+
+/// 距离 minutes 分钟后的整分钟还有多久, 返回微妙
+pub fn next_minutes_left_time(minutes: u32) -> u64 {
+    next_minute_left_time() + minutes.saturating_sub(1) as u64 * 60 * 1_000_000
+}
+
+/// 启动到现在的时间, 深度睡眠唤醒也算一次启动
+pub fn time_since_boot() -> std::time::Duration {
+    std::time::Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
 pub fn enter_deep_sleep_mode_per_minute() {
+    enter_deep_sleep_mode_minutes(1);
+}
+
+/// 睡眠 minutes 分钟, 按整分钟对齐唤醒
+pub fn enter_deep_sleep_mode_minutes(minutes: u32) {
     let now = chrono::Local::now();
-    let sleep_time_us = next_minute_left_time();
+    let sleep_time_us = next_minutes_left_time(minutes);
 
     log::info!(
         "Current time: {:02}:{:02}:{:02}, aligned sleep for {} us",
@@ -47,12 +123,18 @@ pub fn enter_deep_sleep_mode_per_minute() {
     enter_deep_sleep_mode(sleep_time_us);
 }
 pub fn enter_deep_sleep_mode(sleep_time_us: u64) {
+    // 记录预计唤醒时间, 唤醒后可以知道定时器的误差
+    let deadline = chrono::Local::now().timestamp() + (sleep_time_us / 1_000_000) as i64;
+    rtc_state::update(|state| state.sleep_deadline = deadline);
+    let since_boot = time_since_boot();
+    log::info!("awake time: {} ms", since_boot.as_millis());
+    telemetry::finish_cycle(since_boot);
     unsafe {
         log::info!("sleeping for {sleep_time_us} us");
         esp_sleep_enable_timer_wakeup(sleep_time_us);
-
-        // 可选：设置 RTC GPIO 唤醒
-        // esp_sleep_enable_ext0_wakeup(GPIO_NUM_1, 1); // 高电平唤醒
+    }
+    if let Err(e) = enable_key_wakeup() {
+        log::warn!("enable key wakeup failed: {e:?}");
     }
     unsafe {
         esp_deep_sleep_start();
@@ -63,14 +145,7 @@ pub struct DeviceBattery<VBAT1, VBAT2, VBAT3> {
     vbat_1: VBAT1,
     vbat_2: VBAT2,
     vbat_3: VBAT3,
-}
-#[derive(Debug)]
-pub enum DeviceBatteryType {
-    PercentVbat100,
-    PercentVbat100_75,
-    PercentVbat75_50,
-    PercentVbat50_25,
-    PercentVbat25_0,
+    last_status: Option<BatteryStatus>, // 最近一次解码的状态
 }
 
 impl<VBAT1, VBAT2, VBAT3> DeviceBattery<VBAT1, VBAT2, VBAT3>
@@ -84,39 +159,57 @@ where
             vbat_1,
             vbat_2,
             vbat_3,
+            last_status: None,
         }
     }
 
-    /// 获取实际电量, 根据手册需要延迟一段时间才能读完全部io信息
-    pub fn current_vbat(&mut self) -> anyhow::Result<DeviceBatteryType> {
-        let mut d1_active = false;
-        let mut d2_active = false;
-        let mut d3_active = false;
-
-        for _ in 0..150 {
-            if self.vbat_1.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))? {
-                d1_active = true;
-            }
-            if self.vbat_2.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))? {
-                d2_active = true;
-            }
-            if self.vbat_3.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))? {
-                d3_active = true;
-            }
-            std::thread::sleep(std::time::Duration::from_millis(10));
-        }
-        if d3_active {
-            Ok(DeviceBatteryType::PercentVbat100)
-        } else if d2_active {
-            Ok(DeviceBatteryType::PercentVbat75_50)
-        } else if d1_active {
-            Ok(DeviceBatteryType::PercentVbat50_25)
-        } else {
-            Ok(DeviceBatteryType::PercentVbat25_0)
+    /// 采样三根线 SAMPLE_WINDOW_MS, 每个元素是同一时刻三根线是否为低电平
+    pub fn sample_trace(&mut self) -> anyhow::Result<Vec<[bool; 3]>> {
+        let sample_num = SAMPLE_WINDOW_MS / SAMPLE_INTERVAL_MS;
+        let mut trace = Vec::with_capacity(sample_num as usize);
+        for _ in 0..sample_num {
+            trace.push([
+                self.vbat_1.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))?,
+                self.vbat_2.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))?,
+                self.vbat_3.is_low().map_err(|e| anyhow::anyhow!("{e:?}"))?,
+            ]);
+            std::thread::sleep(std::time::Duration::from_millis(SAMPLE_INTERVAL_MS));
         }
+        Ok(trace)
+    }
+
+    /// 采样三根线一段时间后解码电池状态, 芯片用 pwm 驱动电量灯, 需要采样足够长的时间才能区分闪烁
+    pub fn sample_status(&mut self) -> anyhow::Result<BatteryStatus> {
+        let trace = self.sample_trace()?;
+        let status = decode_trace(&trace, SAMPLE_INTERVAL_MS);
+        self.last_status = Some(status);
+        rtc_state::update(|state| {
+            state.battery = status.to_bits();
+            state.battery_boot_times = state.boot_times;
+        });
+        Ok(status)
+    }
+
+    /// 获取实际电量, 灯都不亮时读不到电量
+    pub fn current_vbat(&mut self) -> anyhow::Result<Option<DeviceBatteryType>> {
+        Ok(self.sample_status()?.level)
+    }
+
+    /// 使用 rtc 内存中缓存的状态, 定时唤醒时跳过采样, 缓存过期时返回 None
+    pub fn restore_cached_status(&mut self) -> Option<BatteryStatus> {
+        let status = rtc_state::load().and_then(|slot| slot.state.cached_battery())?;
+        log::info!("use cached battery status: {status:?}");
+        self.last_status = Some(status);
+        Some(status)
+    }
+
+    /// 最近一次采样的状态
+    pub fn last_status(&self) -> Option<BatteryStatus> {
+        self.last_status
     }
 
+    /// 最近一次采样时是否接着充电器, 没采样过时认为没有充电
     pub fn is_charging(&self) -> bool {
-        true
+        self.last_status.is_some_and(|status| status.is_charging())
     }
 }
@@ -0,0 +1,595 @@
+// 命令的实现, 输出都写到 interface, 错误信息也打印到终端, 不让命令线程退出
+use super::{MyItemType, MyMenuType, ShellContext, ShellInterface, MAX_WORDS};
+use crate::audio::AudioCmd;
+use crate::board::battery_decoder::{decode_trace, format_trace, SAMPLE_INTERVAL_MS};
+use crate::board::key_record::{replay_key_file, DEFAULT_KEY_RECORD_PATH};
+use crate::board::peripheral::BoardPeripherals;
+use crate::communication::net_services;
+use crate::communication::ota::Ota;
+use crate::device_config::apply_patch_effects;
+use crate::device_config::config_patch::ConfigPatch;
+use crate::device_config::factory_reset::factory_reset_and_restart;
+use crate::file_system::fat_path::{readable_path, writable_path, FAT_ROOT, SYSTEM_DIR};
+use crate::file_system::fat_usage;
+use crate::ui::popup::{PopupMsg, PopupSeverity};
+use crate::ui::ScreenEvent;
+use crate::ActivePage;
+use embedded_io::Write;
+use menu::argument_finder;
+use serde_json::Value;
+use std::fs;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> anyhow::Result<MutexGuard<'a, T>> {
+    mutex
+        .lock()
+        .map_err(|e| anyhow::anyhow!("{name} mutex poisoned: {e}"))
+}
+
+/// 执行结果, 失败时打印错误
+fn report(interface: &mut ShellInterface, result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        out!(interface, "Error: {e:#}");
+    }
+}
+
+/// 取参数, 没有传时返回 None
+fn arg<'a>(item: &'a MyItemType, args: &'a [&'a str], name: &'a str) -> Option<&'a str> {
+    argument_finder(item, args, name).ok().flatten()
+}
+
+/// 从第 skip 个参数开始拼回原来的文本, 连续的空格会变成一个
+fn joined_text(args: &[&str], skip: usize) -> String {
+    args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .skip(skip)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 只允许访问 /fat 下面的文件, 返回规范化后的路径
+fn fat_path(path: &str) -> anyhow::Result<String> {
+    readable_path(path).ok_or_else(|| anyhow::anyhow!("path must be under {FAT_ROOT}"))
+}
+
+pub fn cmd_reboot(
+    _menu: &MyMenuType,
+    _item: &MyItemType,
+    _args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    out!(interface, "Rebooting...");
+    unsafe {
+        esp_idf_svc::sys::esp_restart();
+    }
+}
+
+/// 恢复出厂设置, 需要输入 yes 确认, 系统资源文件保留
+pub fn cmd_factory_reset(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    if arg(item, args, "confirm") != Some("yes") {
+        out!(interface, "Canceled, run 'factory_reset yes' to confirm");
+        return;
+    }
+    out!(
+        interface,
+        "Erasing config, passwords and user data, then restarting..."
+    );
+    let _ = interface.flush();
+    factory_reset_and_restart(&context.device_config, Duration::from_millis(200));
+}
+
+/// 回放按键录制文件, 不传路径时使用默认录制文件
+pub fn cmd_replay(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let path = arg(item, args, "path").unwrap_or(DEFAULT_KEY_RECORD_PATH);
+    match replay_key_file(path, context.key_tx.clone()) {
+        Ok(()) => {
+            out!(interface, "replay {path}");
+        }
+        Err(e) => {
+            out!(interface, "replay {path} failed: {e:?}");
+        }
+    }
+}
+
+/// config get [key] 查看配置, config set <key> <value> 修改配置, 和 PATCH /api/config 一样校验
+pub fn cmd_config(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = match arg(item, args, "action") {
+        Some("get") => config_get(arg(item, args, "key"), interface, context),
+        Some("set") => config_set(item, args, interface, context),
+        _ => Err(anyhow::anyhow!("action must be get or set")),
+    };
+    report(interface, result);
+}
+
+fn config_get(
+    key: Option<&str>,
+    interface: &mut ShellInterface,
+    context: &ShellContext,
+) -> anyhow::Result<()> {
+    let config = lock(&context.device_config, "device_config")?
+        .settings
+        .redacted()?;
+    let value = match key {
+        Some(key) => config
+            .get(key)
+            .ok_or_else(|| anyhow::anyhow!("no config field {key}"))?,
+        None => &config,
+    };
+    out!(interface, "{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn config_set(
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &ShellContext,
+) -> anyhow::Result<()> {
+    let Some(key) = arg(item, args, "key") else {
+        anyhow::bail!("usage: config set <key> <value>");
+    };
+    // 超过 MAX_WORDS 的部分已经被 menu 丢掉, 不能当成完整的值保存
+    if args.len() >= MAX_WORDS {
+        anyhow::bail!("value has too many words, write the JSON without spaces");
+    }
+    let text = joined_text(args, 2);
+    if text.is_empty() {
+        anyhow::bail!("usage: config set <key> <value>");
+    }
+    let value = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    let patch: ConfigPatch = serde_json::from_value(serde_json::json!({ key: value }))?;
+    let effects = lock(&context.device_config, "device_config")?.apply_patch(patch)?;
+    apply_patch_effects(effects, &context.device_config, &context.screen_tx);
+    out!(interface, "{key} saved");
+    Ok(())
+}
+
+/// wifi scan 扫描附近网络, wifi connect [ssid] 连接保存的网络, wifi status 查看连接状态
+pub fn cmd_wifi(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = match arg(item, args, "action") {
+        Some("scan") => wifi_scan(interface, context),
+        Some("connect") => wifi_connect(arg(item, args, "ssid"), interface, context),
+        Some("status") => wifi_status(interface, context),
+        _ => Err(anyhow::anyhow!("action must be scan, connect or status")),
+    };
+    report(interface, result);
+}
+
+fn wifi_scan(interface: &mut ShellInterface, context: &ShellContext) -> anyhow::Result<()> {
+    let mut board = lock(&context.board, "board")?;
+    let wifi = board.ensure_wifi()?;
+    if !wifi.is_started()? {
+        wifi.set_configuration(&embedded_svc::wifi::Configuration::Client(
+            Default::default(),
+        ))?;
+        wifi.start()?;
+    }
+    let mut aps = wifi.scan()?;
+    aps.sort_by_key(|ap| std::cmp::Reverse(ap.signal_strength));
+    for ap in aps {
+        out!(
+            interface,
+            "{:>4} dBm  ch {:>2}  {:?}  {}",
+            ap.signal_strength,
+            ap.channel,
+            ap.auth_method,
+            ap.ssid
+        );
+    }
+    Ok(())
+}
+
+fn wifi_connect(
+    ssid: Option<&str>,
+    interface: &mut ShellInterface,
+    context: &ShellContext,
+) -> anyhow::Result<()> {
+    // 和主循环一样, 连接期间不拿配置锁
+    let (networks, max_link_time) = {
+        let config = lock(&context.device_config, "device_config")?;
+        let networks: Vec<_> = config
+            .settings
+            .wifi_networks
+            .iter()
+            .filter(|network| ssid.is_none() || ssid == Some(network.ssid.as_str()))
+            .cloned()
+            .collect();
+        (networks, config.settings.wifi_max_link_time)
+    };
+    if networks.is_empty() {
+        anyhow::bail!("{} is not a saved network", ssid.unwrap_or_default());
+    }
+    let (ip_info, ssid) = {
+        let mut board = lock(&context.board, "board")?;
+        BoardPeripherals::wifi_connect(board.ensure_wifi()?, &networks, max_link_time)?
+    };
+    out!(interface, "connected to {ssid}, ip: {}", ip_info.ip);
+    let mut config = lock(&context.device_config, "device_config")?;
+    config.runtime.ip_info = Some(ip_info);
+    config.runtime.connected_ssid = Some(ssid);
+    net_services::on_wifi_connected(&config.settings.device_name());
+    Ok(())
+}
+
+fn wifi_status(interface: &mut ShellInterface, context: &ShellContext) -> anyhow::Result<()> {
+    let connected = {
+        let board = lock(&context.board, "board")?;
+        match board.wifi.as_ref() {
+            Some(wifi) => wifi.is_connected()?,
+            None => false,
+        }
+    };
+    let config = lock(&context.device_config, "device_config")?;
+    match (&config.runtime.connected_ssid, &config.runtime.ip_info) {
+        (Some(ssid), Some(ip_info)) if connected => {
+            out!(
+                interface,
+                "connected to {ssid}, ip: {}, gateway: {}",
+                ip_info.ip,
+                ip_info.subnet.gateway
+            );
+        }
+        _ => {
+            out!(interface, "not connected");
+        }
+    }
+    out!(interface, "saved networks:");
+    for network in &config.settings.wifi_networks {
+        out!(
+            interface,
+            "  {} (priority {}{}{})",
+            network.ssid,
+            network.priority,
+            if network.hidden { ", hidden" } else { "" },
+            if network.static_ip.is_some() {
+                ", static ip"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+pub fn cmd_ls(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let path = fat_path(arg(item, args, "path").unwrap_or(FAT_ROOT))?;
+        let mut entries = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                    out!(interface, "{:>10}  {name}/", "-");
+                }
+                Ok(metadata) => {
+                    out!(interface, "{:>10}  {name}", metadata.len());
+                }
+                Err(_) => {
+                    out!(interface, "{:>10}  {name}", "?");
+                }
+            }
+        }
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_cat(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let path = fat_path(arg(item, args, "path").unwrap_or_default())?;
+        let mut file = fs::File::open(path)?;
+        let mut buf = [0_u8; 256];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            interface
+                .write_all(&buf[..n])
+                .map_err(|e| anyhow::anyhow!("write failed: {e:?}"))?;
+        }
+        let _ = writeln!(interface);
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_rm(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let path = fat_path(arg(item, args, "path").unwrap_or_default())?;
+        // 和 http 上传一样保护 /fat/system, 配置和系统资源用 config 或者 factory_reset 修改
+        let Some(path) = writable_path(&path) else {
+            anyhow::bail!("can not remove {FAT_ROOT} or files under {SYSTEM_DIR}");
+        };
+        if fs::metadata(&path)?.is_dir() {
+            fs::remove_dir(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        out!(interface, "removed {path}");
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_df(
+    _menu: &MyMenuType,
+    _item: &MyItemType,
+    _args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    let result = fat_usage().map(|(total, free)| {
+        out!(
+            interface,
+            "{FAT_ROOT}: {} KiB total, {} KiB used, {} KiB free",
+            total / 1024,
+            (total - free) / 1024,
+            free / 1024
+        );
+    });
+    report(interface, result);
+}
+
+pub fn cmd_sensor(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let action = arg(item, args, "action");
+        if action == Some("trace") {
+            // 输出原始采样, 保存成 .trace 文件就能在电脑上用 battery_decoder 的测试复现
+            let battery = lock(&context.board, "board")?.device_battery.clone();
+            let trace = lock(&battery, "battery")?.sample_trace()?;
+            for line in format_trace(&trace).lines() {
+                out!(interface, "{line}");
+            }
+            out!(
+                interface,
+                "decoded: {:?}",
+                decode_trace(&trace, SAMPLE_INTERVAL_MS)
+            );
+            return Ok(());
+        }
+        if action != Some("read") {
+            anyhow::bail!("action must be read or trace");
+        }
+        let (data, battery) = {
+            let mut board = lock(&context.board, "board")?;
+            (board.read_all_sensor()?, board.device_battery.clone())
+        };
+        out!(
+            interface,
+            "temperature: {:.2} C, humidity: {:.2} %",
+            data.sht3x_measure.temperature,
+            data.sht3x_measure.humidity
+        );
+        if let Some(battery) = lock(&battery, "battery")?.last_status() {
+            out!(interface, "battery: {battery:?}");
+        }
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_page(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let name = arg(item, args, "name").unwrap_or_default();
+        let page = (0..)
+            .map_while(ActivePage::from_index)
+            .filter(|page| *page != ActivePage::None)
+            .find(|page| format!("{page:?}").eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("no page named {name}"))?;
+        context.screen_tx.send(ScreenEvent::Refresh(page))?;
+        out!(interface, "switch to {page:?}");
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_popup(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let severity = arg(item, args, "level")
+            .map(PopupSeverity::from_str)
+            .transpose()?
+            .unwrap_or_default();
+        let popup = PopupMsg::new("shell", "Shell".to_string(), joined_text(args, 0))
+            .with_severity(severity);
+        context.screen_tx.send(ScreenEvent::Popup(popup))?;
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_beep(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let times = match arg(item, args, "times") {
+            Some(times) => times.parse()?,
+            None => 1,
+        };
+        context.audio_tx.send(AudioCmd::Beep(times, 150))?;
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+/// ota check 只查询有没有新版本, ota update 有新版本时下载升级
+pub fn cmd_ota(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        let connected = lock(&context.device_config, "device_config")?
+            .runtime
+            .ip_info
+            .is_some();
+        if !connected {
+            anyhow::bail!("wifi not connected, try wifi connect");
+        }
+        let ota = Ota::with_default_server()?;
+        match arg(item, args, "action") {
+            Some("check") => match ota.is_need_upgrade()? {
+                Some(upgrade) => {
+                    out!(
+                        interface,
+                        "new firmware {} ({} bytes), current {}",
+                        upgrade.version,
+                        upgrade.pack_size,
+                        env!("BUILD_TIME")
+                    );
+                }
+                None => {
+                    out!(interface, "firmware is up to date: {}", env!("BUILD_TIME"));
+                }
+            },
+            Some("update") => ota.sync_firmware()?,
+            _ => anyhow::bail!("action must be check or update"),
+        }
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_log(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    _context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        if arg(item, args, "action") != Some("level") {
+            anyhow::bail!("action must be level");
+        }
+        let level = log::LevelFilter::from_str(arg(item, args, "level").unwrap_or_default())
+            .map_err(|_| anyhow::anyhow!("level must be off, error, warn, info, debug or trace"))?;
+        // 不能超过编译时的最高等级 CONFIG_LOG_MAXIMUM_LEVEL
+        esp_idf_svc::log::set_target_level("*", level)?;
+        out!(interface, "log level: {level}");
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+pub fn cmd_i2c(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        if arg(item, args, "action") != Some("scan") {
+            anyhow::bail!("action must be scan");
+        }
+        let devices = lock(&context.board, "board")?.i2c_bus_devices()?;
+        for addr in &devices {
+            out!(interface, "found device at 0x{addr:02X}");
+        }
+        out!(interface, "{} devices", devices.len());
+        Ok(())
+    })();
+    report(interface, result);
+}
+
+/// 打印 es8388 的寄存器, 音频还没初始化时先初始化
+pub fn cmd_es8388(
+    _menu: &MyMenuType,
+    item: &MyItemType,
+    args: &[&str],
+    interface: &mut ShellInterface,
+    context: &mut ShellContext,
+) {
+    let result = (|| -> anyhow::Result<()> {
+        if arg(item, args, "action") != Some("dump") {
+            anyhow::bail!("action must be dump");
+        }
+        let audio = lock(&context.board, "board")?.audio.clone();
+        let mut audio = lock(&audio, "audio")?;
+        audio.ensure()?;
+        let Some(es8388) = audio.es8388.as_mut() else {
+            anyhow::bail!("es8388 not initialized");
+        };
+        let registers = es8388.read_all()?;
+        for (row, chunk) in registers.chunks(8).enumerate() {
+            let values: Vec<String> = chunk.iter().map(|value| format!("{value:02X}")).collect();
+            out!(interface, "{:02}: {}", row * 8, values.join(" "));
+        }
+        Ok(())
+    })();
+    report(interface, result);
+}
@@ -0,0 +1,373 @@
+// 命令输出, 写到当前的终端
+macro_rules! out {
+    ($interface:expr, $($arg:tt)*) => {
+        let _ = writeln!($interface, $($arg)*);
+    };
+}
+
+mod commands;
+mod remote;
+
+use ele_ds_core::cmd_menu::telnet;
+
+use crate::audio::AudioCmd;
+use crate::board::button::PressedKeyInfo;
+use crate::board::peripheral::BoardPeripherals;
+use crate::device_config::DeviceConfig;
+use crate::ui::ScreenEvent;
+use commands::*;
+use menu::*;
+pub use remote::{start_remote_shell, REMOTE_SHELL_PORT};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+// 这是一个适配器，让 std::io 能够被嵌入式库使用
+pub enum ShellInterface {
+    Serial,         // 串口, 也就是 stdout
+    Tcp(TcpStream), // 远程命令行, 见 remote
+}
+
+impl embedded_io::ErrorType for ShellInterface {
+    type Error = embedded_io::ErrorKind;
+}
+
+impl embedded_io::Write for ShellInterface {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self {
+            // 将数据写入真正的 stdout
+            ShellInterface::Serial => io::stdout()
+                .write(buf)
+                .map_err(|_| embedded_io::ErrorKind::Other),
+            // telnet 终端换行需要 \r\n
+            ShellInterface::Tcp(stream) => {
+                for (i, line) in buf.split(|byte| *byte == b'\n').enumerate() {
+                    if i > 0 {
+                        stream
+                            .write_all(b"\r\n")
+                            .map_err(|_| embedded_io::ErrorKind::Other)?;
+                    }
+                    stream
+                        .write_all(line)
+                        .map_err(|_| embedded_io::ErrorKind::Other)?;
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        match self {
+            ShellInterface::Serial => io::stdout().flush(),
+            ShellInterface::Tcp(stream) => stream.flush(),
+        }
+        .map_err(|_| embedded_io::ErrorKind::Other)
+    }
+}
+
+/// 命令执行时需要用到的资源
+#[derive(Clone)]
+pub struct ShellContext {
+    pub key_tx: Sender<PressedKeyInfo>, // 回放按键时注入按键通道
+    pub board: Arc<Mutex<BoardPeripherals>>,
+    pub device_config: Arc<Mutex<DeviceConfig>>,
+    pub screen_tx: Sender<ScreenEvent>,
+    pub audio_tx: Sender<AudioCmd>,
+}
+
+type MyMenuType<'a> = Menu<'a, ShellInterface, ShellContext>;
+type MyItemType<'a> = Item<'a, ShellInterface, ShellContext>;
+
+const LINE_BUFFER_LEN: usize = 1024; // 一行命令的最大长度, config set 要能输入 wifi 网络和按键绑定的 json
+const MAX_WORDS: usize = 16; // menu 最多解析的参数个数, 多出来的直接丢掉
+
+// menu 按空格分割参数, 参数个数超过定义的个数会报错, 可以带空格的文本多留几个位置
+const MORE_WORDS: Parameter = Parameter::Optional {
+    parameter_name: "...",
+    help: Some("More words, joined with spaces"),
+};
+
+pub const ROOT_MENU: Menu<ShellInterface, ShellContext> = Menu {
+    label: "esp32",
+    items: &[
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_reboot,
+                parameters: &[],
+            },
+            command: "reboot",
+            help: Some("Restart the device"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_factory_reset,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "confirm",
+                    help: Some("Type yes to erase config, passwords and user data"),
+                }],
+            },
+            command: "factory_reset",
+            help: Some("Restore factory settings and restart"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_replay,
+                parameters: &[Parameter::Optional {
+                    parameter_name: "path",
+                    help: Some("Key record file"),
+                }],
+            },
+            command: "replay",
+            help: Some("Replay recorded keys"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_config,
+                parameters: &[
+                    Parameter::Mandatory {
+                        parameter_name: "action",
+                        help: Some("get or set"),
+                    },
+                    Parameter::Optional {
+                        parameter_name: "key",
+                        help: Some("Config field, all fields when omitted"),
+                    },
+                    Parameter::Optional {
+                        parameter_name: "value",
+                        help: Some(
+                            "JSON value, plain text is taken as a string. \
+                             At most 13 words, write long JSON without spaces",
+                        ),
+                    },
+                    // 一共 MAX_WORDS 个位置
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                ],
+            },
+            command: "config",
+            help: Some("Show or change the configuration"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_wifi,
+                parameters: &[
+                    Parameter::Mandatory {
+                        parameter_name: "action",
+                        help: Some("scan, connect or status"),
+                    },
+                    Parameter::Optional {
+                        parameter_name: "ssid",
+                        help: Some("Saved network to connect, all saved networks when omitted"),
+                    },
+                ],
+            },
+            command: "wifi",
+            help: Some("Scan, connect and show Wi-Fi"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_ls,
+                parameters: &[Parameter::Optional {
+                    parameter_name: "path",
+                    help: Some("Directory under /fat, /fat when omitted"),
+                }],
+            },
+            command: "ls",
+            help: Some("List a directory"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_cat,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "path",
+                    help: Some("File under /fat"),
+                }],
+            },
+            command: "cat",
+            help: Some("Print a file"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_rm,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "path",
+                    help: Some("File or empty directory under /fat, not /fat/system"),
+                }],
+            },
+            command: "rm",
+            help: Some("Remove a file"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_df,
+                parameters: &[],
+            },
+            command: "df",
+            help: Some("Show free space on /fat"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_sensor,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "action",
+                    help: Some("read, or trace to dump raw battery LED samples"),
+                }],
+            },
+            command: "sensor",
+            help: Some("Read the sensors"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_page,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "name",
+                    help: Some("Page name, e.g. home, sensor, about"),
+                }],
+            },
+            command: "page",
+            help: Some("Switch the screen to a page"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_popup,
+                parameters: &[
+                    Parameter::Mandatory {
+                        parameter_name: "text",
+                        help: Some("Popup message"),
+                    },
+                    Parameter::NamedValue {
+                        parameter_name: "level",
+                        argument_name: "LEVEL",
+                        help: Some(
+                            "info, warning, error or alarm, error and alarm wait for a key press",
+                        ),
+                    },
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                    MORE_WORDS,
+                ],
+            },
+            command: "popup",
+            help: Some("Show a popup on the screen"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_beep,
+                parameters: &[Parameter::Optional {
+                    parameter_name: "times",
+                    help: Some("How many beeps, 1 when omitted"),
+                }],
+            },
+            command: "beep",
+            help: Some("Beep the speaker"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_ota,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "action",
+                    help: Some("check, or update to install a newer firmware"),
+                }],
+            },
+            command: "ota",
+            help: Some("Check the upgrade server"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_log,
+                parameters: &[
+                    Parameter::Mandatory {
+                        parameter_name: "action",
+                        help: Some("level"),
+                    },
+                    Parameter::Mandatory {
+                        parameter_name: "level",
+                        help: Some("off, error, warn, info, debug or trace"),
+                    },
+                ],
+            },
+            command: "log",
+            help: Some("Change the log level"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_i2c,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "action",
+                    help: Some("scan"),
+                }],
+            },
+            command: "i2c",
+            help: Some("Scan the i2c bus"),
+        },
+        &Item {
+            item_type: ItemType::Callback {
+                function: cmd_es8388,
+                parameters: &[Parameter::Mandatory {
+                    parameter_name: "action",
+                    help: Some("dump"),
+                }],
+            },
+            command: "es8388",
+            help: Some("Dump the audio codec registers"),
+        },
+    ],
+    entry: None,
+    exit: None,
+};
+pub fn init_cmd(mut context: ShellContext) -> anyhow::Result<()> {
+    let mut stdin = io::stdin();
+    std::thread::Builder::new()
+        .stack_size(20 * 1024)
+        .name(String::from("shell"))
+        .spawn(move || {
+            let mut buffer = [0u8; LINE_BUFFER_LEN];
+            let mut runner =
+                Runner::new(ROOT_MENU, &mut buffer, ShellInterface::Serial, &mut context);
+            log::info!("shell start");
+            println!("\nESP32 Shell Tool Ready (WDT disabled for this thread)");
+            print!("> ");
+            let _ = io::stdout()
+                .flush()
+                .map_err(|e| log::warn!("flush stdout failed: {e}"));
+
+            loop {
+                let mut byte = [0u8; 1];
+                match stdin.read(&mut byte) {
+                    Ok(n) if n > 0 => {
+                        let c = byte[0];
+                        print!("{}", c as char);
+                        let _ = io::stdout().flush();
+
+                        runner.input_byte(c, &mut context);
+
+                        if c == b'\r' || c == b'\n' {
+                            // println!("");
+                            print!("> ");
+                            let _ = io::stdout().flush();
+                        }
+                    }
+                    Ok(_) | Err(_) => {
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
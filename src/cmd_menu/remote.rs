@@ -0,0 +1,174 @@
+// 局域网里的远程命令行, 命令和串口一样. 用 telnet 连接 23 端口, 密码是保存在加密 nvs 里的用户密码,
+// 没有设置密码时不允许登录. 同时只允许一个连接
+use super::telnet::{TelnetFilter, NEGOTIATION};
+use super::{ShellContext, ShellInterface, LINE_BUFFER_LEN, ROOT_MENU};
+use crate::device_config::secret::{password_matches, SecretKey};
+use crate::device_config::secret_store;
+use menu::Runner;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const REMOTE_SHELL_PORT: u16 = 23;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60); // 没有输入时断开, 避免一直占着唯一的连接
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30); // 输入密码的总时间, 超时断开, 不让没登录的连接占着
+const MAX_LOGIN_ATTEMPTS: usize = 3;
+const MAX_LOGIN_DELAY_SECS: u64 = 60;
+const MAX_PASSWORD_LEN: usize = 64;
+
+// 连续登录失败的次数, 跨连接累计, 登录成功后清零. 断开重连不能绕过等待
+static FAILED_LOGINS: AtomicU32 = AtomicU32::new(0);
+
+/// 连接结束时释放占用标志
+struct SessionGuard(Arc<AtomicBool>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// 开始监听远程命令行, 需要在 wifi 驱动创建后调用
+pub fn start_remote_shell(context: ShellContext) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", REMOTE_SHELL_PORT))?;
+    let busy = Arc::new(AtomicBool::new(false));
+    std::thread::Builder::new()
+        .stack_size(4 * 1024)
+        .name(String::from("shell_listen"))
+        .spawn(move || {
+            log::info!("remote shell listening on port {REMOTE_SHELL_PORT}");
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("remote shell accept failed: {e:?}");
+                        continue;
+                    }
+                };
+                if busy.swap(true, Ordering::Relaxed) {
+                    let _ = stream.write_all(b"Another session is active, try later.\r\n");
+                    continue;
+                }
+                let guard = SessionGuard(busy.clone());
+                let context = context.clone();
+                let spawned = std::thread::Builder::new()
+                    .stack_size(20 * 1024)
+                    .name(String::from("shell_remote"))
+                    .spawn(move || {
+                        let _guard = guard;
+                        let peer = stream.peer_addr().ok();
+                        log::info!("remote shell connected from {peer:?}");
+                        if let Err(e) = session(stream, context) {
+                            log::warn!("remote shell session ended: {e:?}");
+                        }
+                        log::info!("remote shell from {peer:?} closed");
+                    });
+                if let Err(e) = spawned {
+                    log::warn!("spawn remote shell failed: {e:?}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// 读取一个字节, 连接关闭时返回 None
+fn read_byte(stream: &mut TcpStream, filter: &mut TelnetFilter) -> anyhow::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if let Some(byte) = filter.push(byte[0]) {
+            return Ok(Some(byte));
+        }
+    }
+}
+
+/// 读取不回显的一行, 用于输入密码. 每次读之前把读超时设成剩下的时间, 一直慢慢发数据也会在 deadline 断开
+fn read_password(
+    stream: &mut TcpStream,
+    filter: &mut TelnetFilter,
+    deadline: Instant,
+) -> anyhow::Result<Option<String>> {
+    let mut line = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            anyhow::bail!("login timed out");
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        match read_byte(stream, filter)? {
+            None => return Ok(None),
+            // 上一行的 \r\n 剩下的 \n
+            Some(b'\n') if line.is_empty() => {}
+            Some(b'\r' | b'\n') => break,
+            Some(0x08 | 0x7f) => {
+                line.pop();
+            }
+            Some(byte) if line.len() < MAX_PASSWORD_LEN => line.push(byte),
+            Some(_) => {}
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+/// 登录成功返回 true
+fn login(stream: &mut TcpStream, filter: &mut TelnetFilter) -> anyhow::Result<bool> {
+    let password = secret_store::get(SecretKey::UserPassword)?.unwrap_or_default();
+    if password.is_empty() {
+        stream.write_all(
+            b"Remote shell disabled, set user_password from the serial shell first.\r\n",
+        )?;
+        return Ok(false);
+    }
+    let mut deadline = Instant::now() + LOGIN_TIMEOUT;
+    for _ in 0..MAX_LOGIN_ATTEMPTS {
+        stream.write_all(b"Password: ")?;
+        let Some(input) = read_password(stream, filter, deadline)? else {
+            return Ok(false);
+        };
+        if password_matches(&input, &password) {
+            FAILED_LOGINS.store(0, Ordering::Relaxed);
+            stream.write_all(b"\r\n")?;
+            return Ok(true);
+        }
+        // 每次失败等待的时间加倍, 降低猜密码的速度
+        let failures = FAILED_LOGINS
+            .fetch_add(1, Ordering::Relaxed)
+            .saturating_add(1);
+        let delay = (1u64 << failures.min(6)).min(MAX_LOGIN_DELAY_SECS);
+        log::warn!("remote shell wrong password, {failures} failures, wait {delay}s");
+        std::thread::sleep(Duration::from_secs(delay));
+        // 失败等待的时间不算在输入时间里
+        deadline += Duration::from_secs(delay);
+        stream.write_all(b"\r\nWrong password.\r\n")?;
+    }
+    log::warn!("remote shell login failed {MAX_LOGIN_ATTEMPTS} times");
+    Ok(false)
+}
+
+fn session(mut stream: TcpStream, mut context: ShellContext) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(LOGIN_TIMEOUT))?;
+    stream.write_all(&NEGOTIATION)?;
+    let mut filter = TelnetFilter::default();
+    if !login(&mut stream, &mut filter)? {
+        return Ok(());
+    }
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.write_all(b"ESP32 remote shell, type help for commands.\r\n")?;
+    let mut buffer = [0u8; LINE_BUFFER_LEN];
+    let interface = ShellInterface::Tcp(stream);
+    let mut runner = Runner::new(ROOT_MENU, &mut buffer, interface, &mut context);
+    // 连接交给 runner 用来输出, 读也通过它
+    loop {
+        let ShellInterface::Tcp(stream) = &mut runner.interface else {
+            return Ok(());
+        };
+        let Some(byte) = read_byte(stream, &mut filter)? else {
+            return Ok(());
+        };
+        runner.input_byte(byte, &mut context);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::communication::http_client::communication::{
     GeneralHttpRequest, GeneralHttpResponse, RequestUserInfo,
 };
+use crate::telemetry::{self, Phase};
 use embedded_svc::http::client::Response;
 use embedded_svc::{http::client::Client, io::Write, utils::io};
 use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
@@ -100,6 +101,7 @@ impl EleDsHttpClient {
         path: &str,
         msg: serde_json::Value,
     ) -> anyhow::Result<(u16, String)> {
+        let _timer = telemetry::timer(Phase::Http);
         let request_str = serde_json::to_string(&GeneralHttpRequest {
             user_info: RequestUserInfo::default(),
             timestamp: GeneralHttpResponse::get_now_timestamp(),
@@ -139,6 +141,7 @@ impl EleDsHttpClient {
     where
         F: FnMut(Response<&mut EspHttpConnection>) -> anyhow::Result<()>,
     {
+        let _timer = telemetry::timer(Phase::Http);
         let url = format!("{}/{}", self.server_address, path);
         log::info!("Start download file from: {url}");
 
@@ -158,13 +161,16 @@ impl EleDsHttpClient {
 
     /// 发送一个GET 请求
     pub fn get_msg(&mut self, full_url: &str) -> anyhow::Result<String> {
+        let _timer = telemetry::timer(Phase::Http);
         let request = self.client.get(full_url)?;
 
         let mut response = request.submit()?;
 
         let status = response.status();
         if status != 200 {
-            anyhow::bail!("GET request failed with status: {status}, url: {full_url}");
+            // 查询参数里可能有 api key, 不打印
+            let path = full_url.split('?').next().unwrap_or_default();
+            anyhow::bail!("GET request failed with status: {status}, url: {path}");
         }
 
         let mut recv_vec = Vec::new();
@@ -1,3 +1,14 @@
+use crate::board::button::PressedKeyInfo;
+use crate::board::key_record::{
+    parse_key_records, replay_key_file, replay_key_records, DEFAULT_KEY_RECORD_PATH,
+};
+use crate::device_config::config_patch::ConfigPatch;
+use crate::device_config::factory_reset::factory_reset_and_restart;
+use crate::device_config::secret::{password_matches, SecretKey};
+use crate::device_config::{apply_patch_effects, secret_store, DeviceConfig};
+use crate::file_system::fat_path;
+use crate::telemetry;
+use crate::ui::ScreenEvent;
 use embedded_svc::http::server::Response;
 use embedded_svc::http::Method;
 use embedded_svc::{http::server::Request, io::Write};
@@ -6,6 +17,14 @@ use std::fs;
 use std::fs::FileType;
 use std::io::{Read, Write as StdWrite};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const HTTP_PORT: u16 = 80; // mdns 广播的也是这个端口
+const MAX_CONFIG_BODY_LEN: usize = 8 * 1024; // 包括按键绑定和多个 wifi 网络也够用
+const MAX_REPLAY_BODY_LEN: usize = 16 * 1024; // 几百个按键, 更长的录制用空请求体回放设备上的文件
+const FACTORY_RESET_DELAY: Duration = Duration::from_secs(1); // 等响应发出去再清除和重启
 
 #[allow(dead_code)]
 pub struct HttpServer<'d> {
@@ -13,8 +32,13 @@ pub struct HttpServer<'d> {
 }
 #[allow(dead_code)]
 impl<'d> HttpServer<'d> {
-    pub fn new() -> anyhow::Result<HttpServer<'d>> {
+    pub fn new(
+        key_tx: Sender<PressedKeyInfo>,
+        device_config: Arc<Mutex<DeviceConfig>>,
+        screen_tx: Sender<ScreenEvent>,
+    ) -> anyhow::Result<HttpServer<'d>> {
         let config = Configuration {
+            http_port: HTTP_PORT,
             stack_size: 10240,
             uri_match_wildcard: true,
             ..Default::default()
@@ -22,6 +46,30 @@ impl<'d> HttpServer<'d> {
         let mut server = EspHttpServer::new(&config)?;
         server.fn_handler("/fat*", Method::Get, Self::list_directory_handler)?;
         server.fn_handler("/fat*", Method::Put, Self::deal_put_file_handler)?;
+        server.fn_handler("/api/keys/replay", Method::Post, move |req| {
+            Self::key_replay_handler(req, key_tx.clone())
+        })?;
+        server.fn_handler("/api/telemetry", Method::Get, Self::telemetry_handler)?;
+        let device_config_get = device_config.clone();
+        server.fn_handler("/api/config", Method::Get, move |req| {
+            Self::get_config_handler(req, &device_config_get)
+        })?;
+        let device_config_patch = device_config.clone();
+        let screen_tx_patch = screen_tx.clone();
+        server.fn_handler("/api/config", Method::Patch, move |req| {
+            Self::patch_config_handler(req, &device_config_patch, &screen_tx_patch)
+        })?;
+        let device_config_export = device_config.clone();
+        server.fn_handler("/api/config/export", Method::Get, move |req| {
+            Self::export_config_handler(req, &device_config_export)
+        })?;
+        let device_config_import = device_config.clone();
+        server.fn_handler("/api/config/import", Method::Post, move |req| {
+            Self::import_config_handler(req, &device_config_import, &screen_tx)
+        })?;
+        server.fn_handler("/api/factory_reset", Method::Post, move |req| {
+            Self::factory_reset_handler(req, &device_config)
+        })?;
         Ok(Self { server })
     }
 
@@ -178,11 +226,17 @@ impl<'d> HttpServer<'d> {
     fn deal_put_file_handler(mut req: Request<&mut EspHttpConnection>) -> anyhow::Result<()> {
         let uri = req.uri();
         log::info!("uri: {uri}");
-        if let Some(parent_path) = Path::new(uri).parent() {
+        // 配置, 状态等文件会在下次保存时覆盖上传的文件, 整个 system 目录都不能上传, 配置用 /api/config 修改
+        let Some(path) = fat_path::writable_path(uri) else {
+            req.into_status_response(403)?
+                .write_all(b"path is read-only, use PATCH /api/config for settings")?;
+            return Ok(());
+        };
+        if let Some(parent_path) = Path::new(&path).parent() {
             log::info!("parent path: {parent_path:?}");
             fs::create_dir_all(parent_path)?;
             let mut buf = [0_u8; 512];
-            let mut file = fs::File::create(uri)?;
+            let mut file = fs::File::create(&path)?;
             loop {
                 if let Ok(read_result) = req.read(&mut buf) {
                     if read_result == 0 {
@@ -197,4 +251,247 @@ impl<'d> HttpServer<'d> {
         response.write_all("".as_bytes())?;
         Ok(())
     }
+
+    /// 返回每天的唤醒耗时汇总, 最后一项是今天到现在为止的累计
+    fn telemetry_handler(req: Request<&mut EspHttpConnection>) -> anyhow::Result<()> {
+        let body = serde_json::to_string(&telemetry::daily_summaries())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(body.as_bytes())?;
+        Ok(())
+    }
+
+    /// 读取整个请求体, 超过 max_len 时返回错误
+    fn read_body(
+        req: &mut Request<&mut EspHttpConnection>,
+        max_len: usize,
+    ) -> anyhow::Result<String> {
+        let mut body = Vec::new();
+        let mut buf = [0_u8; 512];
+        loop {
+            let n = req.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+            if body.len() > max_len {
+                anyhow::bail!("request body larger than {max_len} bytes");
+            }
+        }
+        Ok(String::from_utf8(body)?)
+    }
+
+    /// 去掉密码和 key 的当前配置
+    fn config_json(device_config: &Arc<Mutex<DeviceConfig>>) -> anyhow::Result<String> {
+        let value = {
+            let Ok(config) = device_config.lock() else {
+                anyhow::bail!("device_config mutex poisoned");
+            };
+            config.settings.redacted()?
+        };
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    fn get_config_handler(
+        req: Request<&mut EspHttpConnection>,
+        device_config: &Arc<Mutex<DeviceConfig>>,
+    ) -> anyhow::Result<()> {
+        let body = Self::config_json(device_config)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(body.as_bytes())?;
+        Ok(())
+    }
+
+    /// 修改配置, 请求体是只包含要修改字段的 json, 成功时返回修改后的配置
+    fn patch_config_handler(
+        mut req: Request<&mut EspHttpConnection>,
+        device_config: &Arc<Mutex<DeviceConfig>>,
+        screen_tx: &Sender<ScreenEvent>,
+    ) -> anyhow::Result<()> {
+        // 设置了用户密码后修改配置都要带密码, 不然谁都能改掉密码和服务器地址
+        let password_set = Self::password_set();
+        if password_set {
+            if let Err((status, reason)) = Self::authorize(&req) {
+                req.into_status_response(status)?
+                    .write_all(reason.as_bytes())?;
+                return Ok(());
+            }
+        }
+        let result = Self::read_body(&mut req, MAX_CONFIG_BODY_LEN)
+            .and_then(|body| Ok(serde_json::from_str::<ConfigPatch>(&body)?));
+        let patch = match result {
+            Ok(patch) => patch,
+            Err(e) => {
+                log::warn!("patch config failed: {e:?}");
+                req.into_status_response(400)?
+                    .write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+        };
+        // 用户密码是远程命令行和这些接口的登录密码, 只能在串口命令行设置, 或者带着旧密码修改
+        if patch.user_password.is_some() && !password_set {
+            req.into_status_response(403)?
+                .write_all(b"set user_password from the serial shell first")?;
+            return Ok(());
+        }
+        let result = match device_config.lock() {
+            Ok(mut config) => config.apply_patch(patch),
+            Err(_) => Err(anyhow::anyhow!("device_config mutex poisoned")),
+        };
+        let effects = match result {
+            Ok(effects) => effects,
+            Err(e) => {
+                log::warn!("patch config failed: {e:?}");
+                req.into_status_response(400)?
+                    .write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+        };
+        log::info!("config patched, effects: {effects:?}");
+        apply_patch_effects(effects, device_config, screen_tx);
+        let body = Self::config_json(device_config)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(body.as_bytes())?;
+        Ok(())
+    }
+
+    /// 是否设置了用户密码, 读取失败时按已设置处理, 宁可多要求一次密码
+    fn password_set() -> bool {
+        match secret_store::get(SecretKey::UserPassword) {
+            Ok(password) => password.is_some_and(|password| !password.is_empty()),
+            Err(e) => {
+                log::warn!("read user password failed: {e:?}");
+                true
+            }
+        }
+    }
+
+    /// 检查请求头 Authorization: Bearer <user_password>, 不通过时返回状态码和原因.
+    /// 没有设置用户密码时这些接口都不能用, 先在串口命令行用 config set user_password 设置
+    fn authorize(req: &Request<&mut EspHttpConnection>) -> Result<(), (u16, &'static str)> {
+        let password = match secret_store::get(SecretKey::UserPassword) {
+            Ok(password) => password.unwrap_or_default(),
+            Err(e) => {
+                log::warn!("read user password failed: {e:?}");
+                return Err((500, "secret store unavailable"));
+            }
+        };
+        if password.is_empty() {
+            return Err((403, "set user_password from the serial shell first"));
+        }
+        let token = req
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !password_matches(token, &password) {
+            return Err((
+                401,
+                "wrong or missing Authorization: Bearer <user_password>",
+            ));
+        }
+        Ok(())
+    }
+
+    /// 导出包括密码和 key 的全部设置, 给 /api/config/import 导入到其他设备
+    fn export_config_handler(
+        req: Request<&mut EspHttpConnection>,
+        device_config: &Arc<Mutex<DeviceConfig>>,
+    ) -> anyhow::Result<()> {
+        if let Err((status, reason)) = Self::authorize(&req) {
+            req.into_status_response(status)?
+                .write_all(reason.as_bytes())?;
+            return Ok(());
+        }
+        let settings = {
+            let Ok(config) = device_config.lock() else {
+                anyhow::bail!("device_config mutex poisoned");
+            };
+            config.settings.export()
+        };
+        let body = serde_json::to_string_pretty(&settings)?;
+        req.into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "application/json"),
+                (
+                    "Content-Disposition",
+                    "attachment; filename=\"config.json\"",
+                ),
+            ],
+        )?
+        .write_all(body.as_bytes())?;
+        Ok(())
+    }
+
+    /// 导入 /api/config/export 导出的设置, 和 PATCH 一样先全部校验, 导出文件里没有的字段不修改
+    fn import_config_handler(
+        req: Request<&mut EspHttpConnection>,
+        device_config: &Arc<Mutex<DeviceConfig>>,
+        screen_tx: &Sender<ScreenEvent>,
+    ) -> anyhow::Result<()> {
+        if let Err((status, reason)) = Self::authorize(&req) {
+            req.into_status_response(status)?
+                .write_all(reason.as_bytes())?;
+            return Ok(());
+        }
+        Self::patch_config_handler(req, device_config, screen_tx)
+    }
+
+    /// 恢复出厂设置, 返回响应后清除配置和用户数据并重启
+    fn factory_reset_handler(
+        req: Request<&mut EspHttpConnection>,
+        device_config: &Arc<Mutex<DeviceConfig>>,
+    ) -> anyhow::Result<()> {
+        if let Err((status, reason)) = Self::authorize(&req) {
+            req.into_status_response(status)?
+                .write_all(reason.as_bytes())?;
+            return Ok(());
+        }
+        let device_config = device_config.clone();
+        std::thread::Builder::new()
+            .stack_size(8 * 1024)
+            .name(String::from("factory_reset"))
+            .spawn(move || factory_reset_and_restart(&device_config, FACTORY_RESET_DELAY))?;
+        req.into_status_response(202)?
+            .write_all(b"factory reset, restarting")?;
+        Ok(())
+    }
+
+    /// 回放按键, 请求体是按行的 json 按键记录, 为空时回放默认录制文件
+    fn key_replay_handler(
+        mut req: Request<&mut EspHttpConnection>,
+        key_tx: Sender<PressedKeyInfo>,
+    ) -> anyhow::Result<()> {
+        // 回放的按键和实体按键一样, 比如长按左键会重启进入配网, 要和导出配置一样认证
+        if let Err((status, reason)) = Self::authorize(&req) {
+            req.into_status_response(status)?
+                .write_all(reason.as_bytes())?;
+            return Ok(());
+        }
+        let body = match Self::read_body(&mut req, MAX_REPLAY_BODY_LEN) {
+            Ok(body) => body,
+            Err(e) => {
+                req.into_status_response(400)?
+                    .write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+        };
+        let result = if body.trim().is_empty() {
+            replay_key_file(DEFAULT_KEY_RECORD_PATH, key_tx)
+        } else {
+            parse_key_records(&body).and_then(|records| replay_key_records(records, key_tx))
+        };
+
+        match result {
+            Ok(()) => {
+                req.into_ok_response()?.write_all(b"replay start")?;
+            }
+            Err(e) => {
+                log::warn!("key replay failed: {e:?}");
+                req.into_status_response(400)?
+                    .write_all(e.to_string().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
 }
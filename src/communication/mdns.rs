@@ -0,0 +1,42 @@
+// mdns: 局域网里可以用 <device_name>.local 访问设备, 同时广播 http 服务 (_http._tcp),
+// 不用再到关于页面查 ip. 需要 wifi 驱动已经创建, 网络协议栈初始化后才能启动
+use crate::communication::http_server::HTTP_PORT;
+use esp_idf_svc::mdns::EspMdns;
+use std::sync::Mutex;
+
+// EspMdns 只能 take 一次, 放在这里给修改设备名称时重新广播
+static MDNS: Mutex<Option<EspMdns>> = Mutex::new(None);
+
+fn announce(mdns: &mut EspMdns, device_name: &str) -> anyhow::Result<()> {
+    mdns.set_hostname(device_name)?;
+    mdns.set_instance_name(device_name)?;
+    mdns.remove_services()?;
+    mdns.add_service(Some(device_name), "_http", "_tcp", HTTP_PORT, &[])?;
+    log::info!("mdns: {device_name}.local, http port {HTTP_PORT}");
+    Ok(())
+}
+
+/// 启动 mdns 并广播主机名和 http 服务, 已经启动时重新广播, 每次连上 wifi 都调用
+pub fn start(device_name: &str) -> anyhow::Result<()> {
+    let mut mdns = MDNS
+        .lock()
+        .map_err(|e| anyhow::anyhow!("mdns mutex poisoned: {e}"))?;
+    if let Some(mdns) = mdns.as_mut() {
+        return announce(mdns, device_name);
+    }
+    let mut started = EspMdns::take()?;
+    announce(&mut started, device_name)?;
+    *mdns = Some(started);
+    Ok(())
+}
+
+/// 修改设备名称后重新广播, mdns 没有启动时 (没连 wifi) 不用处理, 下次启动时用新名称
+pub fn set_device_name(device_name: &str) -> anyhow::Result<()> {
+    let mut mdns = MDNS
+        .lock()
+        .map_err(|e| anyhow::anyhow!("mdns mutex poisoned: {e}"))?;
+    match mdns.as_mut() {
+        Some(mdns) => announce(mdns, device_name),
+        None => Ok(()),
+    }
+}
@@ -1,4 +1,9 @@
 pub mod http_client;
 pub mod http_server;
+pub mod mdns;
+pub mod net_services;
 pub mod ota;
+pub mod provisioning;
 pub mod weather;
+
+pub use ele_ds_core::communication::captive_portal;
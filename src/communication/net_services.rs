@@ -0,0 +1,52 @@
+// 依赖网络协议栈的服务: http 服务, 远程命令行和 mdns. 启动时不一定连 wifi (常亮模式, 间隔启动, 之后按键重连),
+// 所以在连上 wifi 时再启动, 启动成功后一直运行到重启
+use crate::cmd_menu::{self, ShellContext};
+use crate::communication::http_server::HttpServer;
+use crate::communication::mdns;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+static CONTEXT: OnceLock<ShellContext> = OnceLock::new();
+// 两个服务分开记录, 启动失败时清掉标志, 下次连上 wifi 再试
+static HTTP_STARTED: AtomicBool = AtomicBool::new(false);
+static SHELL_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// 保存启动服务要用的资源, 在连接 wifi 之前调用
+pub fn init(context: ShellContext) {
+    if CONTEXT.set(context).is_err() {
+        log::warn!("net services already initialized");
+    }
+}
+
+/// wifi 连接成功后调用, http 服务和远程命令行启动成功后就不再启动.
+/// mdns 每次连接都重新广播, 换了网络或者上次启动失败时也能用名称访问.
+/// 调用者一般还拿着配置锁, 所以设备名称由调用者传进来
+pub fn on_wifi_connected(device_name: &str) {
+    if let Err(e) = mdns::start(device_name) {
+        log::warn!("start mdns failed: {e:?}");
+    }
+    let Some(context) = CONTEXT.get() else {
+        log::warn!("net services not initialized");
+        return;
+    };
+    if !HTTP_STARTED.swap(true, Ordering::Relaxed) {
+        match HttpServer::new(
+            context.key_tx.clone(),
+            context.device_config.clone(),
+            context.screen_tx.clone(),
+        ) {
+            // 服务一直运行到重启, EspHttpServer 不能跨线程保存, 直接不释放
+            Ok(server) => std::mem::forget(server),
+            Err(e) => {
+                log::warn!("start http server failed: {e:?}");
+                HTTP_STARTED.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+    if !SHELL_STARTED.swap(true, Ordering::Relaxed) {
+        if let Err(e) = cmd_menu::start_remote_shell(context.clone()) {
+            log::warn!("start remote shell failed: {e:?}");
+            SHELL_STARTED.store(false, Ordering::Relaxed);
+        }
+    }
+}
@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
 const REQUERY_WHETHER_UPGRADE: &str = "/upgrade/query";
+const UPGRADE_SERVER: &str = "https://60.215.128.73:12675";
 pub struct Ota {
     http_client: Arc<Mutex<EleDsHttpClient>>,
 }
@@ -23,6 +24,11 @@ impl Ota {
     pub fn new(http_client: Arc<Mutex<EleDsHttpClient>>) -> anyhow::Result<Self> {
         Ok(Ota { http_client })
     }
+    /// 使用默认的升级服务器
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn with_default_server() -> anyhow::Result<Self> {
+        Self::new(Arc::new(Mutex::new(EleDsHttpClient::new(UPGRADE_SERVER)?)))
+    }
     pub fn is_need_upgrade(&self) -> anyhow::Result<Option<UpgradeQueryResponse>> {
         let device_info = serde_json::json!(&DeviceInfo::default());
         let mut client = self
@@ -0,0 +1,223 @@
+// 配网模式. 开一个开放热点, dns 把所有域名都解析到设备, 手机连上后系统会弹出配网页面,
+// 提交 wifi, 城市和时区后由调用者保存配置并重启. 配网模式只在启动时进入, 不和正常的 http 服务同时运行
+use crate::board::button::{KeyClickedType, PressedKeyInfo};
+use crate::communication::captive_portal::{dns_reply, parse_form, portal_page, ProvisionForm};
+use embedded_svc::http::server::Request;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use embedded_svc::wifi;
+use embedded_svc::wifi::AuthMethod;
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer};
+use esp_idf_svc::wifi::EspWifi;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const PROVISION_TIMEOUT: Duration = Duration::from_secs(10 * 60); // 没人配网时多久退出, 避免一直开着热点耗电
+const MAX_FORM_LEN: usize = 1024;
+
+/// 退出配网的按键, 和默认进入配网的按键一样长按左键, 误碰其它按键不会退出
+pub const PROVISION_CANCEL_KEY: PressedKeyInfo = PressedKeyInfo {
+    idx: 0,
+    click_type: KeyClickedType::LongPressed,
+};
+
+/// 热点名称, 用 mac 地址后两个字节区分不同设备
+pub fn ap_ssid() -> String {
+    let mut mac = [0u8; 6];
+    unsafe { esp_idf_svc::sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    format!("ele-ds-{:02X}{:02X}", mac[4], mac[5])
+}
+
+// 热点实际的 ip, 热点开启后才有, 屏幕上显示用
+static AP_IP: AtomicU32 = AtomicU32::new(0);
+
+/// 热点开启后的 ip, 还没开启时返回 None
+pub fn ap_ip() -> Option<Ipv4Addr> {
+    match AP_IP.load(Ordering::Relaxed) {
+        0 => None,
+        ip => Some(Ipv4Addr::from(ip)),
+    }
+}
+
+/// 配网页面的地址
+pub fn portal_url(ip: Ipv4Addr) -> String {
+    format!("http://{ip}/")
+}
+
+pub struct Provisioning {
+    _server: EspHttpServer<'static>,
+    dns_exit: Arc<AtomicBool>,
+    pub ip: Ipv4Addr,
+}
+
+impl Provisioning {
+    /// 扫描附近的网络后开启热点, dns 和配网页面, 提交的表单从返回的 Receiver 收到
+    pub fn start(
+        wifi: &mut EspWifi<'static>,
+        city_name: &str,
+        time_zone: &str,
+    ) -> anyhow::Result<(Self, Receiver<ProvisionForm>)> {
+        let scanned = Self::scan_ssids(wifi);
+        if wifi.is_started()? {
+            wifi.stop()?;
+        }
+        let ap_config = wifi::AccessPointConfiguration {
+            ssid: heapless::String::<32>::from_str(&ap_ssid())
+                .map_err(|_| anyhow::anyhow!("ap ssid too long"))?,
+            auth_method: AuthMethod::None,
+            channel: 1,
+            max_connections: 4,
+            ..Default::default()
+        };
+        wifi.set_configuration(&wifi::Configuration::AccessPoint(ap_config))?;
+        wifi.start()?;
+        let ip = wifi.ap_netif().get_ip_info()?.ip;
+        AP_IP.store(u32::from(ip), Ordering::Relaxed);
+        log::info!(
+            "provisioning ap {} started, portal: {}",
+            ap_ssid(),
+            portal_url(ip)
+        );
+
+        let dns_exit = Arc::new(AtomicBool::new(false));
+        let dns_exit_task = dns_exit.clone();
+        std::thread::Builder::new()
+            .stack_size(4 * 1024)
+            .name(String::from("dns"))
+            .spawn(move || {
+                if let Err(e) = Self::dns_task(ip, dns_exit_task) {
+                    log::warn!("captive dns failed: {e:?}");
+                }
+            })?;
+
+        let (form_tx, form_rx) = std::sync::mpsc::channel();
+        let server = Self::portal_server(ip, scanned, city_name, time_zone, form_tx)?;
+        Ok((
+            Self {
+                _server: server,
+                dns_exit,
+                ip,
+            },
+            form_rx,
+        ))
+    }
+
+    /// 热点开启后不能再扫描, 先扫描一次作为表单里的输入提示
+    fn scan_ssids(wifi: &mut EspWifi<'static>) -> Vec<String> {
+        let result = (|| -> anyhow::Result<Vec<String>> {
+            if !wifi.is_started()? {
+                wifi.set_configuration(&wifi::Configuration::Client(Default::default()))?;
+                wifi.start()?;
+            }
+            let mut ssids: Vec<String> = Vec::new();
+            for ap in wifi.scan()? {
+                let ssid = ap.ssid.to_string();
+                if !ssid.is_empty() && !ssids.contains(&ssid) {
+                    ssids.push(ssid);
+                }
+            }
+            Ok(ssids)
+        })();
+        result.unwrap_or_else(|e| {
+            log::warn!("scan before provisioning failed: {e:?}");
+            Vec::new()
+        })
+    }
+
+    /// 所有域名都回复设备自己的 ip
+    fn dns_task(ip: Ipv4Addr, exit: Arc<AtomicBool>) -> anyhow::Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:53")?;
+        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut buf = [0u8; 512];
+        while !exit.load(Ordering::Relaxed) {
+            let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            if let Some(reply) = dns_reply(&buf[..len], ip) {
+                if let Err(e) = socket.send_to(&reply, peer) {
+                    log::warn!("dns reply to {peer} failed: {e:?}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn portal_server(
+        ip: Ipv4Addr,
+        scanned: Vec<String>,
+        city_name: &str,
+        time_zone: &str,
+        form_tx: Sender<ProvisionForm>,
+    ) -> anyhow::Result<EspHttpServer<'static>> {
+        let config = Configuration {
+            stack_size: 10240,
+            uri_match_wildcard: true,
+            ..Default::default()
+        };
+        let mut server = EspHttpServer::new(&config)?;
+        let page = portal_page(&scanned, city_name, time_zone, None);
+        server.fn_handler("/", Method::Get, move |req| {
+            req.into_ok_response()?.write_all(page.as_bytes())
+        })?;
+        let (city_name, time_zone) = (city_name.to_string(), time_zone.to_string());
+        server.fn_handler("/save", Method::Post, move |req| {
+            Self::save_handler(req, &scanned, &city_name, &time_zone, &form_tx)
+        })?;
+        // 手机检测网络时访问的地址都跳转到配网页面, 系统就会弹出登录页面
+        let location = portal_url(ip);
+        server.fn_handler("/*", Method::Get, move |req| {
+            req.into_response(302, Some("Found"), &[("Location", location.as_str())])?
+                .write_all(b"")
+        })?;
+        Ok(server)
+    }
+
+    /// 提交配网表单, 校验失败时带着错误信息重新显示表单
+    fn save_handler(
+        mut req: Request<&mut EspHttpConnection>,
+        scanned: &[String],
+        city_name: &str,
+        time_zone: &str,
+        form_tx: &Sender<ProvisionForm>,
+    ) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        let mut buf = [0_u8; 256];
+        loop {
+            let n = req.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+            if body.len() > MAX_FORM_LEN {
+                anyhow::bail!("provision form too large");
+            }
+        }
+        let body = String::from_utf8_lossy(&body);
+        match ProvisionForm::from_pairs(&parse_form(&body)) {
+            Ok(form) => {
+                log::info!("provision form received, ssid: {}", form.ssid);
+                form_tx.send(form)?;
+                req.into_ok_response()?.write_all(
+                    b"<html><head><meta charset='utf-8'></head><body>\
+                      <h2>Saved</h2><p>The device will restart and connect to the new network.</p>\
+                      </body></html>",
+                )?;
+            }
+            Err(e) => {
+                let page = portal_page(scanned, city_name, time_zone, Some(&e.to_string()));
+                req.into_status_response(400)?.write_all(page.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Provisioning {
+    fn drop(&mut self) {
+        self.dns_exit.store(true, Ordering::Relaxed);
+    }
+}
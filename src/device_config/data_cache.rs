@@ -0,0 +1,38 @@
+// 从网络获取的数据缓存, 现在只有天气. 和用户设置分开保存, 文件坏了或者结构变了直接丢掉, 下次联网重新获取
+use crate::communication::weather::WeatherResponse;
+use crate::file_system::atomic_file::{load_with_backup, write_atomic};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const DATA_CACHE_FILE_PATH: &str = "/fat/system/cache"; // 数据缓存保存地址
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct DataCache {
+    pub weather: Option<WeatherResponse>, // 天气数据
+}
+
+impl DataCache {
+    /// 加载缓存, 读不到时为空. legacy 是旧固件保存在配置文件里的天气, 有的话优先使用
+    pub fn load(legacy: Option<&Value>) -> DataCache {
+        if let Some(cache) = legacy.and_then(|value| serde_json::from_value(value.clone()).ok()) {
+            log::info!("weather cache moved out of config file");
+            return cache;
+        }
+        match load_with_backup(DATA_CACHE_FILE_PATH, |contents| {
+            Ok(serde_json::from_str::<DataCache>(contents)?)
+        }) {
+            Ok((cache, _)) => cache,
+            Err(e) => {
+                log::warn!("load data cache failed: {e:?}, drop it");
+                DataCache::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        write_atomic(DATA_CACHE_FILE_PATH, &serde_json::to_string(self)?)?;
+        log::info!("data cache saved to: {DATA_CACHE_FILE_PATH}");
+        Ok(())
+    }
+}
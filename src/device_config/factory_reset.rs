@@ -0,0 +1,75 @@
+// 恢复出厂设置: 删除配置, 加密 nvs 里的密码和 key, 以及用户数据, 然后重启.
+// /fat/system 下面的图片, 字体, 音频等系统资源保留, 其余文件都算用户数据
+use crate::device_config::data_cache::DATA_CACHE_FILE_PATH;
+use crate::device_config::runtime_state::RUNTIME_STATE_FILE_PATH;
+use crate::device_config::{rtc_state, secret_store, DeviceConfig};
+use crate::file_system::fat_path::{FAT_ROOT, SYSTEM_DIR};
+use crate::notification::DEFAULT_NOTIFICATION_FILE_PATH;
+use crate::telemetry::DEFAULT_TELEMETRY_FILE_PATH;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// system 目录里属于用户的文件, 按前缀匹配, 包括 atomic_file 的备份和临时文件
+const SYSTEM_USER_FILES: [&str; 4] = [
+    RUNTIME_STATE_FILE_PATH,
+    DATA_CACHE_FILE_PATH,
+    DEFAULT_TELEMETRY_FILE_PATH,
+    DEFAULT_NOTIFICATION_FILE_PATH,
+];
+
+fn is_user_data(path: &Path) -> bool {
+    !path.starts_with(SYSTEM_DIR)
+        || path
+            .to_str()
+            .is_some_and(|path| SYSTEM_USER_FILES.iter().any(|file| path.starts_with(file)))
+}
+
+/// 删除目录下的用户数据, system 以外删空的目录也删掉
+fn remove_user_data(dir: &Path) -> anyhow::Result<()> {
+    // 先读完目录再删除, 边遍历边删除 fat 可能会跳过文件
+    let entries = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    for path in entries {
+        if path.is_dir() {
+            remove_user_data(&path)?;
+            if !path.starts_with(SYSTEM_DIR) {
+                fs::remove_dir(&path)?;
+            }
+        } else if is_user_data(&path) {
+            fs::remove_file(&path)?;
+            log::info!("removed {path:?}");
+        }
+    }
+    Ok(())
+}
+
+/// 清除所有用户数据, 之后必须重启, 不能再保存配置
+fn wipe() -> anyhow::Result<()> {
+    secret_store::erase_all()?;
+    if let Err(e) = DeviceConfig::delete_config_file() {
+        log::warn!("delete config file failed: {e:?}");
+    }
+    remove_user_data(Path::new(FAT_ROOT))?;
+    rtc_state::clear();
+    // wifi 驱动自己在默认 nvs 里保存的连接信息, 没有初始化 wifi 时会失败, 这时也没有需要清除的
+    let ret = unsafe { esp_idf_svc::sys::esp_wifi_restore() };
+    if ret != esp_idf_svc::sys::ESP_OK {
+        log::info!("esp_wifi_restore: {ret}");
+    }
+    Ok(())
+}
+
+/// 恢复出厂设置后重启, 不会返回. 等待 delay 让调用者先把结果发出去,
+/// 整个过程都拿着配置的锁, 其他线程不会在清除后又把配置写回去
+pub fn factory_reset_and_restart(device_config: &Arc<Mutex<DeviceConfig>>, delay: Duration) -> ! {
+    let _config = device_config.lock().unwrap_or_else(PoisonError::into_inner);
+    log::warn!("factory reset");
+    std::thread::sleep(delay);
+    if let Err(e) = wipe() {
+        log::error!("factory reset failed: {e:?}");
+    }
+    esp_idf_svc::hal::reset::restart();
+}
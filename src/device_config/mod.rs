@@ -1,13 +1,36 @@
-use crate::communication::weather::WeatherResponse;
-use crate::ActivePage;
-use chrono::{Datelike, Local};
-use embedded_svc::ipv4::IpInfo;
+pub mod data_cache;
+pub mod factory_reset;
+pub mod runtime_state;
+pub mod secret_store;
+pub mod settings;
+
+pub use ele_ds_core::device_config::{
+    config_patch, key_binding, migration, power_policy, rtc_state, secret, wifi_networks,
+};
+
+use crate::board::battery_decoder::BatteryStatus;
+use crate::board::button::PressedKeyInfo;
+use crate::board::get_clock_ntp;
+use crate::communication::captive_portal::ProvisionForm;
+use crate::communication::mdns;
+use crate::communication::weather::Weather;
+use crate::device_config::config_patch::{ConfigPatch, PatchEffects};
+use crate::device_config::data_cache::DataCache;
+use crate::device_config::key_binding::{find_key_action, KeyAction};
+use crate::device_config::power_policy::{parse_clock_minutes, power_decision, PowerDecision};
+use crate::device_config::runtime_state::RuntimeState;
+use crate::device_config::settings::Settings;
+use crate::device_config::wifi_networks::WifiNetwork;
+use crate::file_system::atomic_file::{backup_path, tmp_path};
+use crate::ui::ScreenEvent;
+use chrono::{Datelike, Local, Timelike};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 
 pub const DEFAULT_DEVICE_CONFIG_FILE_PATH: &str = "/fat/system/config"; // 默认的配置文件保存地址
+pub const BAD_DEVICE_CONFIG_FILE_PATH: &str = "/fat/system/config.bad"; // 解析失败的配置文件备份, 方便找回 wifi 密码等
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DeviceInfo {
     pub version: String,
@@ -23,129 +46,169 @@ impl Default for DeviceInfo {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct UserInfo {
-    username: String,
-    password: String,
-}
-impl Default for UserInfo {
-    fn default() -> Self {
-        Self {
-            username: "".to_string(),
-            password: "".to_string(),
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+/// 设备配置, 三部分分别保存在不同的文件, 各自修改后调用自己的 save():
+/// 用户设置 (settings), 运行状态 (runtime_state), 从网络获取的数据缓存 (data_cache).
+/// drop 时不保存
+#[derive(Debug, Default)]
 pub struct DeviceConfig {
-    user_info: UserInfo,
-    pub device_info: DeviceInfo,
-    pub wifi_ssid: String,
-    pub wifi_password: String,
-    pub requery_upgrade_time_minutes: u32, // 查询更新版本间隔, 单位: 分钟
-    pub wifi_max_link_time: u8,            // wifi最大连接时间, 秒
-    pub time_zone: String,                 // 时区
-    pub city_name: String,                 // 所在城市地点, 获取天气, 用于查询城市, 可以是中文
-    pub city_name_show: String,            // 和 city_name 对应, 这个是实际屏幕显示的英文名称
-    pub wifi_connect_interval: u32,        // WiFi 连接的电源周期间隔, 和 boot_times 一起用
-    pub boot_times: u32,                   // 重启次数
-    pub current_page: ActivePage,          // 当前活动的页面, 掉电前同步
-    pub weather_api_key: String,           // 获取天气数据api的key
-    pub weather: Option<WeatherResponse>,  // 天气数据
-    pub last_update_weather: u32,          // 最近一次更新天气的小时
-    pub ip_info: Option<IpInfo>,           // 如果网络连接成功就保存ip信息
-}
-
-impl Default for DeviceConfig {
-    fn default() -> Self {
-        Self {
-            user_info: UserInfo::default(),
-            device_info: DeviceInfo::default(),
-            wifi_ssid: "esp-2.4G".to_string(),
-            wifi_password: "12345678..".to_string(),
-            requery_upgrade_time_minutes: 1440,
-            wifi_max_link_time: 30,
-            time_zone: "CST-8".to_string(),
-            city_name: "福州".to_string(),
-            city_name_show: "Fuzhou".to_string(),
-            wifi_connect_interval: 60,
-            boot_times: 0,
-            current_page: ActivePage::default(),
-            weather_api_key: "e7d95a70480a4d6c9140378d9d100d42".to_string(),
-            weather: None,
-            last_update_weather: 0,
-            ip_info: None,
-        }
-    }
+    pub settings: Settings,
+    pub runtime: RuntimeState,
+    pub cache: DataCache,
+    pub device_info: DeviceInfo, // 当前运行的固件, 不保存
 }
 
 impl DeviceConfig {
-    /// 加载配置
+    /// 加载设置, 运行状态和缓存. 旧固件把运行状态和天气也存在配置文件里, 第一次加载时移到各自的文件
     pub fn load_config() -> anyhow::Result<DeviceConfig> {
-        // Self::delete_config_file()?;
-        let config_string = match fs::read_to_string(DEFAULT_DEVICE_CONFIG_FILE_PATH) {
-            Ok(string) => string,
-            Err(e) => {
-                log::info!("load_config failed: {e}");
-                return Self::rebuild_device_config();
-            }
+        let (settings, legacy) = Settings::load()?;
+        let config = DeviceConfig {
+            settings,
+            runtime: RuntimeState::load(legacy.as_ref()),
+            cache: DataCache::load(legacy.as_ref()),
+            device_info: DeviceInfo::default(),
         };
-        let config: DeviceConfig = match serde_json::from_str(&config_string) {
-            Ok(config) => config,
-            Err(e) => {
-                log::warn!("Parse config failed: {e:?}, rebuilding...");
-                Self::rebuild_device_config()?
+        if legacy.is_some() {
+            if let Err(e) = config.runtime.save() {
+                log::warn!("save runtime state failed: {e:?}");
             }
-        };
+            if let Err(e) = config.cache.save() {
+                log::warn!("save data cache failed: {e:?}");
+            }
+        }
         Ok(config)
     }
-    fn rebuild_device_config() -> anyhow::Result<DeviceConfig> {
-        if let Some(parent) = std::path::Path::new(DEFAULT_DEVICE_CONFIG_FILE_PATH).parent() {
-            fs::create_dir_all(parent)?;
+
+    /// 删除配置文件和它的备份, 解析失败的备份里可能有明文密码, 也一起删掉
+    pub fn delete_config_file() -> anyhow::Result<()> {
+        let _ = fs::remove_file(tmp_path(DEFAULT_DEVICE_CONFIG_FILE_PATH));
+        let _ = fs::remove_file(backup_path(DEFAULT_DEVICE_CONFIG_FILE_PATH));
+        let _ = fs::remove_file(BAD_DEVICE_CONFIG_FILE_PATH);
+        fs::remove_file(DEFAULT_DEVICE_CONFIG_FILE_PATH)?;
+        Ok(())
+    }
+
+    /// 保存配网表单, 换了城市时丢掉旧的天气
+    pub fn apply_provision(&mut self, form: &ProvisionForm) -> anyhow::Result<()> {
+        if self.settings.apply_provision(form) {
+            self.clear_weather();
         }
+        self.settings.save()?;
+        self.runtime.sync(false)
+    }
 
-        let default_config = DeviceConfig::default();
-        default_config.save_config()?;
-        Ok(default_config)
+    /// 应用 http 或者命令行提交的修改并保存, 校验不通过时不修改任何字段, 返回需要调用者做的后续处理
+    pub fn apply_patch(&mut self, patch: ConfigPatch) -> anyhow::Result<PatchEffects> {
+        let effects = self.settings.apply_patch(patch)?;
+        if effects.weather {
+            // 旧的天气是别的城市的, 获取失败时也不再显示
+            self.clear_weather();
+            self.runtime.sync(false)?;
+        }
+        Ok(effects)
     }
-    /// 保存数据到文件, 这里保存的配置是格式化后的
-    pub fn save_config(&self) -> anyhow::Result<()> {
-        let config_string = serde_json::to_string_pretty(self)?;
-        if let Some(parent) = std::path::Path::new(DEFAULT_DEVICE_CONFIG_FILE_PATH).parent() {
-            fs::create_dir_all(parent)?;
+
+    /// 丢掉缓存的天气, 下次连上网就更新
+    fn clear_weather(&mut self) {
+        self.cache.weather = None;
+        self.runtime.last_update_weather = u32::MAX; // 不是有效的小时
+        if let Err(e) = self.cache.save() {
+            log::warn!("save data cache failed: {e:?}");
         }
-        let mut file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(DEFAULT_DEVICE_CONFIG_FILE_PATH)?;
-
-        file.write_all(config_string.as_bytes())?;
-        log::info!("config file has beed saved to: {DEFAULT_DEVICE_CONFIG_FILE_PATH}");
-        Ok(())
     }
 
-    pub fn delete_config_file() -> anyhow::Result<()> {
-        fs::remove_file(DEFAULT_DEVICE_CONFIG_FILE_PATH)?;
-        Ok(())
+    /// 重新获取天气, 保存到缓存并记录更新的小时
+    /// https 请求要好几秒, 请求期间不拿配置锁, 免得按键和屏幕线程等着
+    pub fn update_weather(device_config: &Mutex<DeviceConfig>) -> anyhow::Result<()> {
+        let lock = || {
+            device_config
+                .lock()
+                .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))
+        };
+        let weather = {
+            let config = lock()?;
+            Weather::new(
+                &config.settings.city_name,
+                config.settings.weather_api_key.expose(),
+            )
+        };
+        let weather = weather.get_weather_hefeng()?;
+        let mut config = lock()?;
+        config.cache.weather = Some(weather);
+        config.cache.save()?;
+        config.runtime.last_update_weather = Local::now().hour();
+        config.runtime.sync(false)
     }
-    pub fn set_user_info(&mut self, user_info: UserInfo) {
-        self.user_info = user_info;
+
+    /// 关于页面显示的 wifi, 连接上时是当前网络, 否则是优先级最高的网络
+    pub fn display_network(&self) -> Option<&WifiNetwork> {
+        let connected = self.runtime.connected_ssid.as_ref();
+        let networks = &self.settings.wifi_networks;
+        networks
+            .iter()
+            .find(|network| Some(&network.ssid) == connected)
+            .or_else(|| {
+                // 同优先级时取排在前面的
+                networks.iter().rev().max_by_key(|network| network.priority)
+            })
     }
 
-    pub fn is_need_connect_wifi(&self) -> bool {
+    pub fn is_need_connect_wifi(&self, decision: &PowerDecision) -> bool {
+        // 电量过低时不连接
+        let Some(interval) = decision.wifi_connect_interval else {
+            return false;
+        };
         // 没设置间隔时间就每次都连接
-        if self.wifi_connect_interval == 0 || Self::current_time_is_too_old() {
+        if interval == 0 || Self::current_time_is_too_old() {
             return true;
         }
-        self.boot_times % self.wifi_connect_interval == 0
+        self.runtime.boot_times % interval == 0
+    }
+
+    /// 根据电池状态和当前时间得到电源策略, 是否处于电量过低保护记录在 rtc 内存
+    pub fn power_decision(&self, battery: Option<BatteryStatus>) -> PowerDecision {
+        power_decision(
+            &self.settings.power_policy,
+            battery,
+            self.runtime.current_page,
+            self.is_night_now(),
+            self.settings.wifi_connect_interval,
+            rtc_state::load().is_some_and(|slot| slot.state.is_low_battery()),
+        )
+    }
+
+    /// 今天的日出和日落, 单位: 一天中的分钟, 没有天气数据时返回 None
+    pub fn sun_times(&self) -> Option<(u32, u32)> {
+        let today = self.cache.weather.as_ref()?.daily.first()?;
+        Some((
+            parse_clock_minutes(&today.sunrise)?,
+            parse_clock_minutes(&today.sunset)?,
+        ))
+    }
+
+    /// 当前是否在夜间安静时段, 时间还没同步时不算
+    pub fn is_night_now(&self) -> bool {
+        if Self::current_time_is_too_old() {
+            return false;
+        }
+        let now = Local::now();
+        self.settings
+            .power_policy
+            .is_night(now.hour() * 60 + now.minute(), self.sun_times())
+    }
+
+    /// 夜间静音, 和 muted 不同, 只关闭按键和普通弹窗的提示音
+    pub fn night_muted(&self) -> bool {
+        self.settings.power_policy.night_mute && self.is_night_now()
     }
 
-    /// 启动后增加一次启动次数并保存
-    pub fn boot_times_add(&mut self) -> anyhow::Result<()> {
-        self.boot_times += 1;
-        self.save_config()
+    /// 根据按键绑定表查找当前页面下按键对应的动作
+    pub fn key_action(&self, key_info: &PressedKeyInfo) -> Option<KeyAction> {
+        find_key_action(
+            &self.settings.key_bindings,
+            key_info,
+            self.runtime.current_page,
+        )
+        .cloned()
     }
 
     pub fn current_time_is_too_old() -> bool {
@@ -154,10 +217,72 @@ impl DeviceConfig {
     }
 }
 
-impl Drop for DeviceConfig {
-    fn drop(&mut self) {
-        if let Err(e) = self.save_config() {
-            log::warn!("Failed to save device config: {e}");
+/// http 或者命令行修改配置后的处理. 获取天气比较慢, 放到单独的线程, 获取完再刷新页面
+pub fn apply_patch_effects(
+    effects: PatchEffects,
+    device_config: &Arc<Mutex<DeviceConfig>>,
+    screen_tx: &Sender<ScreenEvent>,
+) {
+    if effects.time_zone {
+        let time_zone = device_config
+            .lock()
+            .map(|config| config.settings.time_zone.clone());
+        match time_zone {
+            Ok(time_zone) => {
+                if let Err(e) = get_clock_ntp::set_time_zone(&time_zone) {
+                    log::warn!("set time zone failed: {e:?}");
+                }
+            }
+            Err(e) => log::error!("device_config mutex poisoned: {e:?}"),
         }
     }
+    if effects.mdns {
+        let device_name = device_config
+            .lock()
+            .map(|config| config.settings.device_name());
+        match device_name {
+            Ok(device_name) => {
+                if let Err(e) = mdns::set_device_name(&device_name) {
+                    log::warn!("update mdns failed: {e:?}");
+                }
+            }
+            Err(e) => log::error!("device_config mutex poisoned: {e:?}"),
+        }
+    }
+    let refresh = {
+        let device_config = device_config.clone();
+        let screen_tx = screen_tx.clone();
+        move || {
+            if !effects.refresh {
+                return;
+            }
+            let Ok(page) = device_config
+                .lock()
+                .map(|config| config.runtime.current_page)
+            else {
+                log::error!("device_config mutex poisoned");
+                return;
+            };
+            if let Err(e) = screen_tx.send(ScreenEvent::Refresh(page)) {
+                log::warn!("send screen event failed: {e:?}");
+            }
+        }
+    };
+    if !effects.weather {
+        refresh();
+        return;
+    }
+    let device_config = device_config.clone();
+    let spawned = std::thread::Builder::new()
+        .stack_size(20 * 1024)
+        .name(String::from("weather"))
+        .spawn(move || {
+            if let Err(e) = DeviceConfig::update_weather(&device_config) {
+                log::warn!("update weather failed: {e:?}");
+            }
+            refresh();
+        });
+    if let Err(e) = spawned {
+        log::warn!("spawn weather thread failed: {e:?}");
+    }
 }
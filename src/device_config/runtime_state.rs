@@ -0,0 +1,98 @@
+// 运行状态: 启动次数, 当前页面, 更新天气的小时. 变化很快, 平时只同步到 rtc 内存 (见 rtc_state),
+// 攒够变化才写到单独的状态文件, 不会碰到用户设置. ip 和连上的网络每次启动都重新获取, 只放在内存里
+use crate::device_config::rtc_state::{self, RtcSlot, RtcState};
+use crate::file_system::atomic_file::{load_with_backup, write_atomic};
+use crate::ActivePage;
+use embedded_svc::ipv4::IpInfo;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const RUNTIME_STATE_FILE_PATH: &str = "/fat/system/state"; // 运行状态保存地址
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RuntimeState {
+    pub boot_times: u32,          // 重启次数
+    pub current_page: ActivePage, // 当前活动的页面, 掉电前同步
+    pub last_update_weather: u32, // 最近一次更新天气的小时
+    #[serde(skip)]
+    pub ip_info: Option<IpInfo>, // 如果网络连接成功就保存ip信息
+    #[serde(skip)]
+    pub connected_ssid: Option<String>, // 当前连接的 wifi 网络
+    #[serde(skip)]
+    flashed: RtcState, // 最近一次写到状态文件的运行状态
+}
+
+impl RuntimeState {
+    /// 加载状态文件, 读不到时用默认值. legacy 是旧固件保存在配置文件里的状态, 有的话优先使用
+    pub fn load(legacy: Option<&Value>) -> RuntimeState {
+        if let Some(state) = legacy.and_then(|value| serde_json::from_value(value.clone()).ok()) {
+            log::info!("runtime state moved out of config file");
+            return state;
+        }
+        match load_with_backup(RUNTIME_STATE_FILE_PATH, |contents| {
+            Ok(serde_json::from_str::<RuntimeState>(contents)?)
+        }) {
+            Ok((state, _)) => state,
+            Err(e) => {
+                log::warn!("load runtime state failed: {e:?}, use default");
+                RuntimeState::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        write_atomic(
+            RUNTIME_STATE_FILE_PATH,
+            &serde_json::to_string_pretty(self)?,
+        )?;
+        log::info!("runtime state saved to: {RUNTIME_STATE_FILE_PATH}");
+        Ok(())
+    }
+
+    /// 启动后增加一次启动次数, 由 sync() 决定什么时候写到文件
+    pub fn boot_times_add(&mut self) {
+        self.boot_times += 1;
+    }
+
+    /// 要同步到 rtc 内存的状态, 不在这里的字段保留 rtc 内存中的值
+    fn rtc_state(&self) -> RtcState {
+        RtcState {
+            boot_times: self.boot_times,
+            current_page: self.current_page as u32,
+            last_update_weather: self.last_update_weather,
+            ..rtc_state::load().map(|slot| slot.state).unwrap_or_default()
+        }
+    }
+
+    /// 深度睡眠唤醒后 rtc 内存里的状态比文件新, 用它覆盖文件里的值, 上电时以文件为准
+    pub fn restore(&mut self) {
+        match rtc_state::load() {
+            Some(slot) => {
+                log::info!("restore runtime state from rtc: {slot:?}");
+                self.boot_times = slot.state.boot_times;
+                self.current_page = slot.state.page().unwrap_or(self.current_page);
+                self.last_update_weather = slot.state.last_update_weather;
+                self.flashed = slot.flashed;
+            }
+            None => {
+                log::info!("rtc state invalid, use state file");
+                self.flashed = self.rtc_state();
+            }
+        }
+    }
+
+    /// 把运行状态同步到 rtc 内存, 只有页面或者天气变化, 启动次数累计够了, 或者 force 时才写文件
+    pub fn sync(&mut self, force: bool) -> anyhow::Result<()> {
+        let state = self.rtc_state();
+        if force || state.need_flush(&self.flashed) {
+            self.save()?;
+            self.flashed = state;
+        }
+        rtc_state::store(&RtcSlot {
+            state,
+            flashed: self.flashed,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// 加密 nvs 里的密码和 key. 用 hmac 方案 (见 sdkconfig.defaults): nvs 密钥由 efuse 里的 hmac 密钥推导,
+// 不需要开启 flash 加密. 开启 nvs 加密后默认的 nvs 分区 (wifi 用) 也会加密,
+// 这里用单独的 nvs_sec 分区, 恢复出厂设置时可以整个擦除而不影响 wifi 驱动的数据
+use crate::device_config::secret::{Secret, SecretKey};
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsCustom};
+use esp_idf_svc::sys::{
+    esp, nvs_flash_erase_partition, nvs_flash_generate_keys_v2,
+    nvs_flash_get_default_security_scheme, nvs_flash_read_security_cfg_v2,
+    nvs_flash_secure_init_partition, nvs_sec_cfg_t,
+};
+use std::ffi::CString;
+use std::sync::Mutex;
+
+const SECRET_PARTITION: &str = "nvs_sec"; // 和 partitions.csv 一致
+const SECRET_NAMESPACE: &str = "secrets";
+const MAX_SECRET_LEN: usize = 128;
+
+static STORE: Mutex<Option<EspNvs<NvsCustom>>> = Mutex::new(None);
+
+/// esp-idf-svc 的 NvsEncrypted 只支持 nvs_keys 分区的 flash 加密方案, 这里用 idf 的接口按 hmac 方案
+/// 加密初始化分区, 再按普通分区打开. 分区已经初始化过时 nvs_flash_init_partition 直接返回
+fn open_store() -> anyhow::Result<EspNvs<NvsCustom>> {
+    let name = CString::new(SECRET_PARTITION)?;
+    let mut config = nvs_sec_cfg_t::default();
+    unsafe {
+        let scheme = nvs_flash_get_default_security_scheme();
+        if scheme.is_null() {
+            anyhow::bail!("nvs security scheme not registered, check CONFIG_NVS_ENCRYPTION");
+        }
+        // 第一次使用时 efuse 里还没有 hmac 密钥, 生成并烧写
+        if esp!(nvs_flash_read_security_cfg_v2(scheme, &mut config)).is_err() {
+            log::info!("nvs security config not found, generate keys");
+            esp!(nvs_flash_generate_keys_v2(scheme, &mut config))?;
+        }
+        esp!(nvs_flash_secure_init_partition(name.as_ptr(), &mut config))?;
+    }
+    let partition = EspNvsPartition::<NvsCustom>::take(SECRET_PARTITION)?;
+    Ok(EspNvs::new(partition, SECRET_NAMESPACE, true)?)
+}
+
+/// 第一次使用时打开加密分区, 打开失败下次再试
+fn with_store<T>(f: impl FnOnce(&mut EspNvs<NvsCustom>) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let Ok(mut store) = STORE.lock() else {
+        anyhow::bail!("lock secret store failed");
+    };
+    if store.is_none() {
+        *store = Some(open_store()?);
+        log::info!("secret store opened");
+    }
+    match store.as_mut() {
+        Some(nvs) => f(nvs),
+        None => anyhow::bail!("secret store not opened"),
+    }
+}
+
+pub fn get(key: SecretKey) -> anyhow::Result<Option<String>> {
+    with_store(|nvs| {
+        let mut buf = [0u8; MAX_SECRET_LEN + 1];
+        Ok(nvs.get_str(&key.nvs_name(), &mut buf)?.map(str::to_string))
+    })
+}
+
+pub fn set(key: SecretKey, value: &str) -> anyhow::Result<()> {
+    if value.len() > MAX_SECRET_LEN {
+        anyhow::bail!("{} too long: {} bytes", key.nvs_name(), value.len());
+    }
+    with_store(|nvs| Ok(nvs.set_str(&key.nvs_name(), value)?))
+}
+
+pub fn remove(key: SecretKey) -> anyhow::Result<()> {
+    with_store(|nvs| {
+        nvs.remove(&key.nvs_name())?;
+        Ok(())
+    })
+}
+
+/// 恢复出厂设置, 擦除整个加密分区. efuse 里的 hmac 密钥不变, 下次使用时重新打开
+pub fn erase_all() -> anyhow::Result<()> {
+    let Ok(mut store) = STORE.lock() else {
+        anyhow::bail!("lock secret store failed");
+    };
+    // 分区打开时不能擦除, 先关闭. 关闭时 NvsCustom 会反初始化分区, 重新打开时再加密初始化
+    *store = None;
+    let partition = CString::new(SECRET_PARTITION)?;
+    esp!(unsafe { nvs_flash_erase_partition(partition.as_ptr()) })?;
+    log::info!("secret store erased");
+    Ok(())
+}
+
+/// 加载配置后调用. 配置文件里的明文存进 nvs, 配置文件里没有的从 nvs 读, nvs 里也没有就用默认值.
+/// 返回配置文件是否需要重写, 去掉已经存进 nvs 的明文
+pub fn sync(key: SecretKey, secret: &mut Secret, default: &str) -> bool {
+    if secret.is_plain() {
+        return match set(key, secret.expose()) {
+            Ok(()) => {
+                log::info!("{} moved to secret store", key.nvs_name());
+                secret.mark_stored();
+                true
+            }
+            Err(e) => {
+                log::warn!("store {} failed, keep it in config: {e:?}", key.nvs_name());
+                false
+            }
+        };
+    }
+    match get(key) {
+        Ok(Some(value)) => {
+            *secret = Secret::new(&value);
+            secret.mark_stored();
+        }
+        Ok(None) => {
+            *secret = Secret::fallback(default);
+            if set(key, default).is_ok() {
+                secret.mark_stored();
+            }
+        }
+        Err(e) => {
+            // nvs 不能用时只在内存里使用默认值, 不写到配置文件
+            log::warn!("read {} failed, use default: {e:?}", key.nvs_name());
+            *secret = Secret::fallback(default);
+        }
+    }
+    false
+}
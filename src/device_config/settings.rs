@@ -0,0 +1,485 @@
+// 用户设置: wifi, 城市, 时区, 按键绑定等, 只在用户修改时写配置文件.
+// 运行状态和天气缓存变化快, 分别保存在 runtime_state 和 data_cache 的文件里, 写它们不会碰到这个文件
+use super::{BAD_DEVICE_CONFIG_FILE_PATH, DEFAULT_DEVICE_CONFIG_FILE_PATH};
+use crate::communication::captive_portal::ProvisionForm;
+use crate::communication::provisioning;
+use crate::device_config::config_patch::{redact, ConfigPatch, PatchEffects};
+use crate::device_config::key_binding::{default_key_bindings, KeyBinding};
+use crate::device_config::migration::{self, CONFIG_VERSION};
+use crate::device_config::power_policy::PowerPolicyConfig;
+use crate::device_config::secret::{save_then_apply, Secret, SecretChange, SecretKey};
+use crate::device_config::secret_store;
+use crate::device_config::wifi_networks::WifiNetwork;
+use crate::file_system::atomic_file::{load_with_backup, tmp_path, write_atomic};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserInfo {
+    username: String,
+    #[serde(default, skip_serializing_if = "Secret::skip_in_file")]
+    password: Secret, // 保存在加密 nvs 里
+}
+impl Default for UserInfo {
+    fn default() -> Self {
+        Self {
+            username: "".to_string(),
+            password: Secret::default(),
+        }
+    }
+}
+
+// 配置文件里缺少的字段使用的默认值, Default 也用这些, 两边保持一致
+mod defaults {
+    use super::{Secret, WifiNetwork};
+
+    pub fn wifi_networks() -> Vec<WifiNetwork> {
+        vec![WifiNetwork::new("esp-2.4G", Secret::fallback("12345678.."))]
+    }
+    pub fn requery_upgrade_time_minutes() -> u32 {
+        1440
+    }
+    pub fn wifi_max_link_time() -> u8 {
+        30
+    }
+    pub fn time_zone() -> String {
+        "CST-8".to_string()
+    }
+    pub fn city_name() -> String {
+        "福州".to_string()
+    }
+    pub fn city_name_show() -> String {
+        "Fuzhou".to_string()
+    }
+    pub fn wifi_connect_interval() -> u32 {
+        60
+    }
+    pub fn provision_after_failures() -> u32 {
+        3
+    }
+    pub fn weather_api_key() -> String {
+        "e7d95a70480a4d6c9140378d9d100d42".to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Settings {
+    #[serde(default)]
+    pub config_version: u32, // 配置文件的版本, 见 migration
+    #[serde(default)]
+    pub device_name: String, // mdns 主机名, 用 <device_name>.local 访问, 空表示用热点名称 ele-ds-xxxx
+    #[serde(default)]
+    user_info: UserInfo,
+    #[serde(default = "defaults::wifi_networks")]
+    pub wifi_networks: Vec<WifiNetwork>, // 保存的 wifi 网络, 密码保存在加密 nvs 里, 见 secret_store
+    #[serde(default = "defaults::requery_upgrade_time_minutes")]
+    pub requery_upgrade_time_minutes: u32, // 查询更新版本间隔, 单位: 分钟
+    #[serde(default = "defaults::wifi_max_link_time")]
+    pub wifi_max_link_time: u8, // wifi最大连接时间, 秒
+    #[serde(default = "defaults::provision_after_failures")]
+    pub provision_after_failures: u32, // 连续多少次连不上 wifi 后进入配网模式, 之后的间隔逐次加倍, 0 表示不自动进入
+    #[serde(default = "defaults::time_zone")]
+    pub time_zone: String, // 时区
+    #[serde(default = "defaults::city_name")]
+    pub city_name: String, // 所在城市地点, 获取天气, 用于查询城市, 可以是中文
+    #[serde(default = "defaults::city_name_show")]
+    pub city_name_show: String, // 和 city_name 对应, 这个是实际屏幕显示的英文名称
+    #[serde(default = "defaults::wifi_connect_interval")]
+    pub wifi_connect_interval: u32, // WiFi 连接的电源周期间隔, 和 boot_times 一起用
+    #[serde(default, skip_serializing_if = "Secret::skip_in_file")]
+    pub weather_api_key: Secret, // 获取天气数据api的key, 保存在加密 nvs 里
+    #[serde(default = "default_key_bindings")]
+    pub key_bindings: Vec<KeyBinding>, // 按键绑定表
+    #[serde(default)]
+    pub muted: bool, // 关闭按键和弹窗提示音
+    #[serde(default)]
+    pub key_record_enable: bool, // 把按键录制到文件, 用于复现问题
+    #[serde(default)]
+    pub power_policy: PowerPolicyConfig, // 根据电量和时间调整刷新和睡眠
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            device_name: String::new(),
+            user_info: UserInfo::default(),
+            wifi_networks: defaults::wifi_networks(),
+            requery_upgrade_time_minutes: defaults::requery_upgrade_time_minutes(),
+            wifi_max_link_time: defaults::wifi_max_link_time(),
+            provision_after_failures: defaults::provision_after_failures(),
+            time_zone: defaults::time_zone(),
+            city_name: defaults::city_name(),
+            city_name_show: defaults::city_name_show(),
+            wifi_connect_interval: defaults::wifi_connect_interval(),
+            weather_api_key: Secret::fallback(&defaults::weather_api_key()),
+            key_bindings: default_key_bindings(),
+            muted: false,
+            key_record_enable: false,
+            power_policy: PowerPolicyConfig::default(),
+        }
+    }
+}
+
+/// 密码存进 nvs 后删掉配置文件的其它副本, 里面可能还有明文密码和 api key,
+/// 而 /fat 目录可以通过 http 和命令行查看
+fn remove_plain_copies() {
+    for path in [
+        BAD_DEVICE_CONFIG_FILE_PATH.to_string(),
+        tmp_path(DEFAULT_DEVICE_CONFIG_FILE_PATH),
+    ] {
+        match fs::remove_file(&path) {
+            Ok(()) => log::info!("removed plaintext copy {path}"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("remove {path} failed: {e:?}"),
+        }
+    }
+}
+
+impl Settings {
+    /// 加载设置, 配置文件损坏时从备份恢复, 都不能用时才重建默认设置.
+    /// 第二个返回值是旧配置文件里的运行状态和天气, 给 RuntimeState 和 DataCache 第一次加载时使用
+    pub fn load() -> anyhow::Result<(Settings, Option<Value>)> {
+        match load_with_backup(DEFAULT_DEVICE_CONFIG_FILE_PATH, Self::parse) {
+            Ok(((mut settings, legacy, changed), recovered)) => {
+                let secrets_moved = settings.sync_secrets();
+                // 升级过或者从备份恢复的配置写回正式文件
+                if changed || recovered || secrets_moved {
+                    if let Err(e) = settings.save() {
+                        log::warn!("save loaded config failed: {e:?}");
+                    }
+                }
+                // 再写一次, 让 .bak 也换成不含明文密码的版本, 其它副本直接删掉
+                if secrets_moved {
+                    if let Err(e) = settings.save() {
+                        log::warn!("save loaded config failed: {e:?}");
+                    }
+                    remove_plain_copies();
+                }
+                Ok((settings, legacy))
+            }
+            Err(e) => {
+                // 先备份解析不了的文件再重建, 不直接覆盖掉 wifi 密码和 api key
+                log::warn!("load config failed: {e:?}, rebuilding...");
+                if let Ok(config_string) = fs::read(DEFAULT_DEVICE_CONFIG_FILE_PATH) {
+                    if let Err(e) = fs::write(BAD_DEVICE_CONFIG_FILE_PATH, config_string) {
+                        log::warn!("backup bad config failed: {e:?}");
+                    }
+                }
+                Ok((Self::rebuild()?, None))
+            }
+        }
+    }
+
+    /// 解析配置文件, 旧版本先升级到当前版本, 以前保存在一起的运行状态和天气取出来单独返回.
+    /// 最后一个返回值表示有修改需要写回文件
+    fn parse(config_string: &str) -> anyhow::Result<(Settings, Option<Value>, bool)> {
+        let mut value: Value = serde_json::from_str(config_string)?;
+        let migrated = migration::migrate(&mut value)?;
+        let legacy = migration::take_legacy_runtime(&mut value);
+        let settings = serde_json::from_value(value)?;
+        let changed = migrated || legacy.is_some();
+        Ok((settings, legacy, changed))
+    }
+
+    fn rebuild() -> anyhow::Result<Settings> {
+        if let Some(parent) = std::path::Path::new(DEFAULT_DEVICE_CONFIG_FILE_PATH).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // 密码不在配置文件里, 重建时保留 nvs 里的值
+        let mut settings = Settings::default();
+        settings.sync_secrets();
+        settings.save()?;
+        Ok(settings)
+    }
+
+    /// 保存数据到文件, 这里保存的配置是格式化后的, 写入过程中掉电不会破坏原来的配置
+    pub fn save(&self) -> anyhow::Result<()> {
+        let config_string = serde_json::to_string_pretty(self)?;
+        write_atomic(DEFAULT_DEVICE_CONFIG_FILE_PATH, &config_string)?;
+        log::info!("config file has beed saved to: {DEFAULT_DEVICE_CONFIG_FILE_PATH}");
+        Ok(())
+    }
+
+    /// 实际使用的设备名称, 没有设置时和配网热点同名, 不同设备不会重复
+    pub fn device_name(&self) -> String {
+        if self.device_name.is_empty() {
+            provisioning::ap_ssid()
+        } else {
+            self.device_name.clone()
+        }
+    }
+
+    pub fn set_user_info(&mut self, user_info: UserInfo) {
+        let password = user_info.password.expose().to_string();
+        self.user_info = user_info;
+        self.set_secret(SecretKey::UserPassword, &password);
+    }
+
+    fn secret_mut(&mut self, key: SecretKey) -> Option<&mut Secret> {
+        match key {
+            SecretKey::WifiPassword(_) => self
+                .wifi_networks
+                .iter_mut()
+                .find(|network| SecretKey::wifi(&network.ssid) == key)
+                .map(|network| &mut network.password),
+            SecretKey::LegacyWifiPassword => None,
+            SecretKey::WeatherApiKey => Some(&mut self.weather_api_key),
+            SecretKey::UserPassword => Some(&mut self.user_info.password),
+        }
+    }
+
+    /// 配置文件里的明文存进加密 nvs, 没有的从 nvs 读, 返回是否有明文需要从配置文件里去掉
+    fn sync_secrets(&mut self) -> bool {
+        let mut moved = false;
+        moved |= secret_store::sync(
+            SecretKey::WeatherApiKey,
+            &mut self.weather_api_key,
+            &defaults::weather_api_key(),
+        );
+        moved |= secret_store::sync(SecretKey::UserPassword, &mut self.user_info.password, "");
+        // 升级到多个 wifi 网络之前的密码, 只给从旧配置升级来的那个网络使用, 用完删掉
+        let legacy = secret_store::get(SecretKey::LegacyWifiPassword);
+        for network in &mut self.wifi_networks {
+            let mut default = network.password.expose().to_string();
+            if network.legacy_password {
+                match &legacy {
+                    Ok(password) => {
+                        if let Some(password) = password {
+                            default = password.clone();
+                        }
+                        network.legacy_password = false;
+                        moved = true;
+                    }
+                    // nvs 暂时不能用, 保留标记下次再取
+                    Err(e) => log::warn!("read legacy wifi password failed: {e:?}"),
+                }
+            }
+            moved |= secret_store::sync(
+                SecretKey::wifi(&network.ssid),
+                &mut network.password,
+                &default,
+            );
+        }
+        let legacy_used = !self
+            .wifi_networks
+            .iter()
+            .any(|network| network.legacy_password);
+        if legacy_used && matches!(legacy, Ok(Some(_))) {
+            if let Err(e) = secret_store::remove(SecretKey::LegacyWifiPassword) {
+                log::warn!("remove legacy wifi password failed: {e:?}");
+            }
+        }
+        moved
+    }
+
+    /// 修改密码或者 key, 存不进 nvs 时保留在配置文件里
+    pub fn set_secret(&mut self, key: SecretKey, value: &str) {
+        let mut secret = Secret::new(value);
+        match secret_store::set(key, value) {
+            Ok(()) => secret.mark_stored(),
+            Err(e) => log::warn!("store {} failed: {e:?}", key.nvs_name()),
+        }
+        match self.secret_mut(key) {
+            Some(current) => *current = secret,
+            None => log::warn!("no config item for {}", key.nvs_name()),
+        }
+    }
+
+    /// 修改密码或者 key, 先记到 changes 里, 配置文件保存成功后再写 nvs
+    fn stage_secret(&mut self, key: SecretKey, value: &str, changes: &mut Vec<SecretChange>) {
+        let mut secret = Secret::new(value);
+        secret.mark_stored();
+        match self.secret_mut(key) {
+            Some(current) => *current = secret,
+            None => log::warn!("no config item for {}", key.nvs_name()),
+        }
+        changes.push(SecretChange::Set(key, value.to_string()));
+    }
+
+    /// 保存配网表单, 不写文件. 配置的网络优先级最高, 已有同名网络时更新密码, 保留静态 ip 等设置.
+    /// 返回城市是否变了
+    pub fn apply_provision(&mut self, form: &ProvisionForm) -> bool {
+        let priority = self
+            .wifi_networks
+            .iter()
+            .filter(|network| network.ssid != form.ssid)
+            .map(|network| network.priority)
+            .max()
+            .map_or(0, |priority| priority.saturating_add(1));
+        match self
+            .wifi_networks
+            .iter_mut()
+            .find(|network| network.ssid == form.ssid)
+        {
+            Some(network) => network.priority = priority,
+            None => {
+                let mut network = WifiNetwork::new(&form.ssid, Secret::default());
+                network.priority = priority;
+                self.wifi_networks.push(network);
+            }
+        }
+        self.set_secret(SecretKey::wifi(&form.ssid), &form.password);
+        if let Some(time_zone) = &form.time_zone {
+            self.time_zone = time_zone.clone();
+        }
+        let Some(city_name) = &form.city_name else {
+            return false;
+        };
+        // 屏幕字体只能显示英文, 中文城市名只用来查询天气
+        if city_name.is_ascii() {
+            self.city_name_show = city_name.clone();
+        }
+        self.city_name = city_name.clone();
+        true
+    }
+
+    /// 去掉密码和 key 的设置, 给 http 和命令行查看
+    pub fn redacted(&self) -> anyhow::Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        redact(&mut value);
+        Ok(value)
+    }
+
+    /// 导出所有设置, 包括密码和 key, 导入到其他设备时和 PATCH 一样处理
+    pub fn export(&self) -> ConfigPatch {
+        let wifi_networks = self
+            .wifi_networks
+            .iter()
+            .map(|network| WifiNetwork {
+                password: Secret::new(network.password.expose()),
+                legacy_password: false,
+                ..network.clone()
+            })
+            .collect();
+        ConfigPatch {
+            device_name: Some(self.device_name.clone()),
+            time_zone: Some(self.time_zone.clone()),
+            city_name: Some(self.city_name.clone()),
+            city_name_show: Some(self.city_name_show.clone()),
+            weather_api_key: Some(self.weather_api_key.expose().to_string()),
+            user_password: Some(self.user_info.password.expose().to_string()),
+            requery_upgrade_time_minutes: Some(self.requery_upgrade_time_minutes),
+            wifi_max_link_time: Some(self.wifi_max_link_time),
+            wifi_connect_interval: Some(self.wifi_connect_interval),
+            provision_after_failures: Some(self.provision_after_failures),
+            muted: Some(self.muted),
+            key_record_enable: Some(self.key_record_enable),
+            power_policy: Some(self.power_policy.clone()),
+            key_bindings: Some(self.key_bindings.clone()),
+            wifi_networks: Some(wifi_networks),
+        }
+    }
+
+    /// 应用 http 或者命令行提交的修改并保存, 返回需要调用者做的后续处理.
+    /// 修改在副本上进行, 密码和 key 等配置文件保存成功后才写 nvs, 校验不通过或者保存失败时设置和 nvs 都不变
+    pub fn apply_patch(&mut self, patch: ConfigPatch) -> anyhow::Result<PatchEffects> {
+        patch.validate()?;
+        let mut patched = self.clone();
+        let mut changes = Vec::new();
+        let effects = patched.apply_fields(patch, &mut changes);
+        let failed = save_then_apply(
+            changes,
+            || patched.save(),
+            secret_store::set,
+            secret_store::remove,
+        )?;
+        if !failed.is_empty() {
+            // 存不进 nvs 的值作为明文留在配置文件里, 下次启动时再存
+            for (key, value) in failed {
+                if let Some(secret) = patched.secret_mut(key) {
+                    *secret = Secret::new(&value);
+                }
+            }
+            if let Err(e) = patched.save() {
+                log::warn!("save secrets to config file failed: {e:?}");
+            }
+        }
+        *self = patched;
+        Ok(effects)
+    }
+
+    fn apply_fields(
+        &mut self,
+        patch: ConfigPatch,
+        changes: &mut Vec<SecretChange>,
+    ) -> PatchEffects {
+        let effects = patch.effects();
+        if let Some(device_name) = patch.device_name {
+            self.device_name = device_name;
+        }
+        if let Some(time_zone) = patch.time_zone {
+            self.time_zone = time_zone;
+        }
+        if let Some(city_name) = patch.city_name {
+            self.city_name = city_name;
+        }
+        if let Some(city_name_show) = patch.city_name_show {
+            self.city_name_show = city_name_show;
+        }
+        if let Some(key) = patch.weather_api_key {
+            self.stage_secret(SecretKey::WeatherApiKey, &key, changes);
+        }
+        if let Some(password) = patch.user_password {
+            self.stage_secret(SecretKey::UserPassword, &password, changes);
+        }
+        if let Some(minutes) = patch.requery_upgrade_time_minutes {
+            self.requery_upgrade_time_minutes = minutes;
+        }
+        if let Some(time) = patch.wifi_max_link_time {
+            self.wifi_max_link_time = time;
+        }
+        if let Some(interval) = patch.wifi_connect_interval {
+            self.wifi_connect_interval = interval;
+        }
+        if let Some(failures) = patch.provision_after_failures {
+            self.provision_after_failures = failures;
+        }
+        if let Some(muted) = patch.muted {
+            self.muted = muted;
+        }
+        if let Some(enable) = patch.key_record_enable {
+            self.key_record_enable = enable;
+        }
+        if let Some(policy) = patch.power_policy {
+            self.power_policy = policy;
+        }
+        if let Some(bindings) = patch.key_bindings {
+            self.key_bindings = bindings;
+        }
+        if let Some(networks) = patch.wifi_networks {
+            self.replace_wifi_networks(networks, changes);
+        }
+        effects
+    }
+
+    /// 替换保存的 wifi 网络. 没带密码的网络沿用同名网络的密码, 新网络当成开放网络, 删掉的网络从 nvs 里删除密码
+    fn replace_wifi_networks(
+        &mut self,
+        networks: Vec<WifiNetwork>,
+        changes: &mut Vec<SecretChange>,
+    ) {
+        let mut old = std::mem::replace(&mut self.wifi_networks, networks);
+        let mut passwords = Vec::new();
+        for network in &mut self.wifi_networks {
+            if !network.password.is_missing() {
+                passwords.push((network.ssid.clone(), network.password.expose().to_string()));
+            } else if let Some(pos) = old.iter().position(|o| o.ssid == network.ssid) {
+                network.password = old.remove(pos).password;
+            } else {
+                passwords.push((network.ssid.clone(), String::new()));
+            }
+        }
+        for (ssid, password) in passwords {
+            self.stage_secret(SecretKey::wifi(&ssid), &password, changes);
+        }
+        for removed in old
+            .iter()
+            .filter(|o| !self.wifi_networks.iter().any(|n| n.ssid == o.ssid))
+        {
+            changes.push(SecretChange::Remove(SecretKey::wifi(&removed.ssid)));
+        }
+    }
+}
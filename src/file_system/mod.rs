@@ -1,3 +1,5 @@
+pub use ele_ds_core::file_system::{atomic_file, fat_path};
+
 use anyhow::Context;
 use esp_idf_svc::sys::*;
 use std::ffi::CString;
@@ -53,6 +55,15 @@ pub fn nvs_flash_filesystem_init() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// fat 分区的总容量和剩余空间, 单位: 字节
+pub fn fat_usage() -> anyhow::Result<(u64, u64)> {
+    let mut total = 0u64;
+    let mut free = 0u64;
+    let base_path = CString::new("/fat")?;
+    esp!(unsafe { esp_vfs_fat_info(base_path.as_ptr(), &mut total, &mut free) })?;
+    Ok((total, free))
+}
+
 fn test_fs_rw() -> anyhow::Result<()> {
     let path = "/fat/hello.txt";
     {
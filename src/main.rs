@@ -1,23 +1,38 @@
 use anyhow::anyhow;
 use chrono::Timelike;
-use ele_ds_client_rust::audio::{play_sine_wav, speaker_task, AudioCmd};
-use ele_ds_client_rust::board::button::{KeyClickedType, PressedKeyInfo};
-use ele_ds_client_rust::board::power_manage::next_minute_left_time;
+use ele_ds_client_rust::audio::{speaker_task, AudioCmd};
+use ele_ds_client_rust::board::button::{KeyClickedType, PressedKeyInfo, FACTORY_RESET_KEYS};
+use ele_ds_client_rust::board::key_record::{KeyRecorder, DEFAULT_KEY_RECORD_PATH};
+use ele_ds_client_rust::board::power_manage::{
+    next_minutes_left_time, time_since_boot, wakeup_cause, WakeupCause,
+};
 use ele_ds_client_rust::board::{get_clock_ntp, psram};
-use ele_ds_client_rust::communication::http_server::HttpServer;
-use ele_ds_client_rust::communication::weather::Weather;
+use ele_ds_client_rust::communication::net_services;
+use ele_ds_client_rust::communication::provisioning::{
+    Provisioning, PROVISION_CANCEL_KEY, PROVISION_TIMEOUT,
+};
+use ele_ds_client_rust::device_config::factory_reset::factory_reset_and_restart;
+use ele_ds_client_rust::device_config::key_binding::KeyAction;
+use ele_ds_client_rust::device_config::power_policy::PowerDecision;
+use ele_ds_client_rust::device_config::rtc_state;
 use ele_ds_client_rust::device_config::DeviceConfig;
-use ele_ds_client_rust::ui::popup::PopupMsg;
+use ele_ds_client_rust::notification::NotificationCenter;
+use ele_ds_client_rust::telemetry::{self, Phase};
+use ele_ds_client_rust::ui::popup::{PopupMsg, PopupSeverity};
 use ele_ds_client_rust::ui::ScreenEvent;
 use ele_ds_client_rust::{
-    board::peripheral::BoardPeripherals,
-    communication::{http_client, ota},
+    board::peripheral::{BoardBattery, BoardPeripherals, Screen},
+    cmd_menu::{self, ShellContext},
+    communication::ota,
     ui, ActivePage,
 };
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 
+// 睡眠前等待弹窗关闭的最长时间, 超过后弹窗只留在通知中心
+const MAX_POPUP_AWAKE: std::time::Duration = std::time::Duration::from_secs(180);
+
 #[allow(clippy::arc_with_non_send_sync)]
 fn main() -> anyhow::Result<()> {
     esp_idf_svc::sys::link_patches();
@@ -25,13 +40,9 @@ fn main() -> anyhow::Result<()> {
     // Bind the log crate to the ESP Logging facilities
     esp_idf_svc::log::EspLogger::initialize_default();
     log::info!("system start, build info: {} 12", env!("BUILD_TIME"));
-    let mut board = BoardPeripherals::new()?;
-    let mut manger = board
-        .audio_manager
-        .take()
-        .ok_or_else(|| anyhow!("audio_manager not initialized"))?;
-    // 预热es8388
-    play_sine_wav(&mut manger, 50);
+    let wakeup_cause = wakeup_cause();
+    log::info!("wakeup cause: {wakeup_cause:?}");
+    let mut board = BoardPeripherals::new(wakeup_cause)?;
     let mut screen = board
         .screen
         .take()
@@ -40,21 +51,67 @@ fn main() -> anyhow::Result<()> {
     let Ok(mut device_config) = BoardPeripherals::init_filesystem_load_config() else {
         anyhow::bail!("no device config found");
     };
-    device_config.ip_info = None; // 每次上电都清空, 避免在没连上WiFi时读到了上次的ip
-    get_clock_ntp::set_time_zone(device_config.time_zone.as_str())?;
-    device_config.boot_times_add()?;
-    let power_on_ui_page = device_config.current_page;
+    get_clock_ntp::set_time_zone(device_config.settings.time_zone.as_str())?;
+    device_config.runtime.restore();
+    device_config.runtime.boot_times_add();
+    device_config.runtime.sync(false)?;
+    if let (WakeupCause::Timer, Some(slot)) = (wakeup_cause, rtc_state::load()) {
+        let drift = chrono::Local::now().timestamp() - slot.state.sleep_deadline;
+        log::info!("wakeup drift: {drift}s");
+    }
+    let low_battery_mode = rtc_state::load().is_some_and(|slot| slot.state.is_low_battery());
+    // 定时唤醒时使用 rtc 内存里缓存的电池状态, 省掉采样的时间, 低电量保护中要采样才知道有没有接上充电器
+    if low_battery_mode
+        || wakeup_cause != WakeupCause::Timer
+        || lock_battery(&board.device_battery)?
+            .restore_cached_status()
+            .is_none()
+    {
+        log::info!(
+            "current battery: {:?}",
+            lock_battery(&board.device_battery)?.sample_status()?
+        );
+    }
+    let boot_battery = lock_battery(&board.device_battery)?.last_status();
+    let power_on_ui_page = device_config.runtime.current_page;
+    let power_decision = device_config.power_decision(boot_battery);
+    log::info!("power decision: {power_decision:?}");
+    telemetry::record(Phase::Boot, time_since_boot());
     let device_config = Arc::new(Mutex::new(device_config));
     let device_config_ui = device_config.clone();
 
     // 赋值屏幕默认传感器数据
     let sensor_data = board.read_all_sensor()?;
     screen.last_sensor_status = Some(sensor_data);
+    screen.battery_status = boot_battery;
+    screen.notifications = NotificationCenter::load();
+
+    if power_decision.low_battery_protect {
+        // 已经在保护中时屏幕上还是充电提示, 不用再刷新, 也不启动 wifi 和音频
+        if !low_battery_mode {
+            ui::mouse_food_test(&mut screen, device_config.clone(), ActivePage::LowBattery)?;
+            rtc_state::update(|state| state.low_battery = 1);
+        }
+        device_config
+            .lock()
+            .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?
+            .runtime
+            .sync(true)?;
+        ele_ds_client_rust::board::power_manage::enter_deep_sleep_mode_minutes(
+            power_decision.sleep_minutes,
+        );
+    } else if low_battery_mode {
+        log::info!("charger connected, leave low battery mode");
+        rtc_state::update(|state| state.low_battery = 0);
+    }
 
     // 退出标志
     let screen_exit = board.exit.clone();
     let key_exit = board.exit.clone();
     let speaker_exit = board.exit.clone();
+    // 弹窗需要按键确认时, 按键只用来关闭弹窗
+    let popup_wait_ack = Arc::new(AtomicBool::new(false));
+    let popup_wait_ack_key = popup_wait_ack.clone();
 
     // 线程通信
     let (audio_tx, audio_rx) = std::sync::mpsc::channel();
@@ -64,19 +121,68 @@ fn main() -> anyhow::Result<()> {
         .take()
         .ok_or_else(|| anyhow!("key_rx not initialized"))?;
 
+    if rtc_state::load().is_some_and(|slot| slot.state.is_provisioning()) {
+        // 先清掉标志, 配网过程中出错重启后不会一直停在配网模式
+        rtc_state::update(|state| state.provisioning = 0);
+        if let Err(e) = provisioning_mode(&mut board, &mut screen, &device_config, &key_rx) {
+            log::warn!("provisioning failed: {e:?}");
+        }
+        esp_idf_svc::hal::reset::restart();
+    }
+
     let screen_tx_main = screen_tx.clone();
-    // 上电同步掉电时的页面, 避免保存的页面和实际不一样
-    screen_tx_main.send(ScreenEvent::Refresh(power_on_ui_page))?;
+    let key_tx_shell = board.key_tx.clone();
+    let audio_tx_ui = audio_tx.clone();
+    if low_battery_mode {
+        // 刚接上充电器, 从充电提示回到原来的页面
+        screen_tx_main.send(ScreenEvent::Refresh(power_on_ui_page))?;
+    } else if let WakeupCause::Key(idx) = wakeup_cause {
+        // 按键唤醒时屏幕还是睡眠前的页面, 直接把唤醒的按键当成单击处理
+        board.key_tx.send(PressedKeyInfo {
+            idx,
+            click_type: KeyClickedType::SingleClicked,
+        })?;
+    } else if switch_night_page(
+        &power_decision,
+        &device_config
+            .lock()
+            .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?,
+        &screen_tx_main,
+    )? {
+        log::info!("night page changed, night: {}", power_decision.night);
+    } else if wakeup_cause != WakeupCause::Timer || power_decision.refresh_clock {
+        // 上电同步掉电时的页面, 避免保存的页面和实际不一样, 定时唤醒时由电源策略决定是否刷新
+        screen_tx_main.send(ScreenEvent::Refresh(power_on_ui_page))?;
+    }
     // 屏幕刷新线程
     let _ui_handle = std::thread::Builder::new()
         .stack_size(1024 * 10)
         .name(String::from("epd"))
         .spawn(move || {
-            ui::screen_task(screen, device_config_ui, screen_exit, screen_rx);
+            ui::screen_task(
+                screen,
+                device_config_ui,
+                screen_exit,
+                screen_rx,
+                audio_tx_ui,
+                popup_wait_ack,
+            );
         });
 
+    // 音频和电池有自己的锁, 连 wifi 时拿着 board 锁也不会卡住
+    let audio = board.audio.clone();
+    let device_battery = board.device_battery.clone();
     // 按键命令接收线程
     let board = Arc::new(Mutex::new(board));
+    let shell_context = ShellContext {
+        key_tx: key_tx_shell,
+        board: board.clone(),
+        device_config: device_config.clone(),
+        screen_tx: screen_tx.clone(),
+        audio_tx: audio_tx.clone(),
+    };
+    cmd_menu::init_cmd(shell_context.clone())?;
+    net_services::init(shell_context);
     let board_key = board.clone();
     let device_config_key = device_config.clone();
     let _key_handle = std::thread::Builder::new()
@@ -89,62 +195,215 @@ fn main() -> anyhow::Result<()> {
                 key_rx,
                 board_key,
                 device_config_key,
+                popup_wait_ack_key,
             );
         });
 
-    let _audio_handle = std::thread::Builder::new().stack_size(30 * 1024).spawn(|| {
-        if let Err(e) = speaker_task(manger, audio_rx, speaker_exit) {
-            log::warn!("audio task failed: {e:?}");
-        }
-    });
-    {
+    let _audio_handle = std::thread::Builder::new()
+        .stack_size(30 * 1024)
+        .spawn(move || {
+            if let Err(e) = speaker_task(audio, audio_rx, speaker_exit) {
+                log::warn!("audio task failed: {e:?}");
+            }
+        });
+    // http 服务, 远程命令行和 mdns 在第一次连上 wifi 时启动
+    let need_connect_wifi = device_config
+        .lock()
+        .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?
+        .is_need_connect_wifi(&power_decision);
+    if need_connect_wifi {
         let mut board = board
             .lock()
             .map_err(|e| anyhow::anyhow!("board mutex poisoned: {e}"))?;
-        if device_config
-            .lock()
-            .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?
-            .is_need_connect_wifi()
-        {
-            if let Err(e) = connect_net(&mut board, device_config.clone()) {
-                log::warn!("connect_net failed: {e:?}");
-            }
+        if let Err(e) = connect_net(&mut board, device_config.clone()) {
+            log::warn!("connect_net failed: {e:?}");
         }
     }
-    let _http_server = HttpServer::new()?;
     let mut loop_times = 0; // 不断电情况下的循环次数, 可以控制一些第一次循环不执行的功能
     loop {
-        let mut sleep_time = u64::MAX;
-        let mut board = board
+        let sensors_data = board
             .lock()
-            .map_err(|e| anyhow::anyhow!("lock board failed: {e:?}"))?;
-        let sensors_data = board.read_all_sensor()?;
+            .map_err(|e| anyhow::anyhow!("lock board failed: {e:?}"))?
+            .read_all_sensor()?;
         screen_tx_main.send(ScreenEvent::UpdateSensorsData(sensors_data))?;
+        // 第一次循环直接用启动时的状态, 采样要 1.5s, 不拿 board 锁
+        let battery_status = {
+            let mut battery = lock_battery(&device_battery)?;
+            match battery.last_status() {
+                Some(status) if loop_times == 0 => status,
+                _ => battery.sample_status()?,
+            }
+        };
+        screen_tx_main.send(ScreenEvent::UpdateBatteryStatus(battery_status))?;
+        log::info!("battery status: {battery_status:?}");
         log::info!("last sensor_status: {sensors_data:?}");
 
         /* 界面更新区分两种情况:
-            1. 如果一直在运行状态时每分钟更新时间, 这时要发信号
-            2. 如果是从深度睡眠唤醒, 这时就不要再发信号了, 但是还没做
+            1. 如果一直在运行状态时按电源策略的间隔更新时间, 这时要发信号
+            2. 如果是从深度睡眠唤醒, 启动时已经发过信号了
         */
-        if let Ok(config) = device_config.lock() {
-            // 每分钟更新屏幕, 实际刷不刷新屏幕由屏幕自己决定
-            if loop_times > 1 {
-                screen_tx_main.send(ScreenEvent::Refresh(config.current_page))?;
+        let decision = {
+            let mut config = device_config
+                .lock()
+                .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?;
+            let decision = config.power_decision(Some(battery_status));
+            // 实际刷不刷新屏幕由屏幕自己决定
+            if decision.low_battery_protect {
+                screen_tx_main.send(ScreenEvent::Refresh(ActivePage::LowBattery))?;
+                rtc_state::update(|state| state.low_battery = 1);
+            } else if switch_night_page(&decision, &config, &screen_tx_main)? {
+                log::info!("night page changed, night: {}", decision.night);
+            } else if loop_times > 1 && decision.refresh_clock {
+                screen_tx_main.send(ScreenEvent::Refresh(config.runtime.current_page))?;
             }
-            // 如果当前是主页面或者传感器页面就定时刷新数据, 不然的话就睡眠最大时间
-            if config.current_page.cur_set_page_is_need_refresh() {
-                sleep_time = next_minute_left_time();
-            }
-            config.save_config()?;
+            // 电量过低时可能随时掉电, 直接写到配置文件
+            config
+                .runtime
+                .sync(battery_status.critical || decision.low_battery_protect)?;
+            decision
         };
-        let charge_flag = board.device_battery.is_charging();
-        drop(board); // 确保在充电时即使释放锁, 免得阻塞别的线程
+        log::info!("power decision: {decision:?}");
+        // 常亮模式下不会重启, 没连上时按连接间隔在循环里重连
+        let reconnect = decision.stay_awake
+            && loop_times > 0
+            && decision
+                .wifi_connect_interval
+                .is_some_and(|interval| interval == 0 || loop_times % interval == 0)
+            && device_config
+                .lock()
+                .is_ok_and(|config| config.runtime.ip_info.is_none());
+        if reconnect {
+            let mut board = board
+                .lock()
+                .map_err(|e| anyhow::anyhow!("board mutex poisoned: {e}"))?;
+            if let Err(e) = connect_net(&mut board, device_config.clone()) {
+                log::warn!("connect_net failed: {e:?}");
+            }
+        }
         loop_times += 1;
         psram::check_psram();
-        if charge_flag {
-            std::thread::sleep(std::time::Duration::from_micros(sleep_time));
+        if decision.stay_awake {
+            telemetry::finish_cycle(time_since_boot());
+            std::thread::sleep(std::time::Duration::from_micros(next_minutes_left_time(
+                decision.sleep_minutes,
+            )));
         } else {
-            ele_ds_client_rust::board::power_manage::enter_deep_sleep_mode_per_minute();
+            wait_popups_closed(&screen_tx_main);
+            wait_screen_idle(&screen_tx_main);
+            ele_ds_client_rust::board::power_manage::enter_deep_sleep_mode_minutes(
+                decision.sleep_minutes,
+            );
+        }
+    }
+}
+
+fn lock_battery(
+    battery: &Mutex<BoardBattery>,
+) -> anyhow::Result<std::sync::MutexGuard<'_, BoardBattery>> {
+    battery
+        .lock()
+        .map_err(|e| anyhow::anyhow!("battery mutex poisoned: {e}"))
+}
+
+/// 进入或离开夜间时切换睡眠页面, 返回是否发送了刷新
+fn switch_night_page(
+    decision: &PowerDecision,
+    config: &DeviceConfig,
+    screen_tx: &Sender<ScreenEvent>,
+) -> anyhow::Result<bool> {
+    let show = decision.night && config.settings.power_policy.night_show_sleeping;
+    let shown = rtc_state::load().is_some_and(|slot| slot.state.is_sleeping());
+    if show == shown {
+        return Ok(false);
+    }
+    rtc_state::update(|state| state.sleeping = show as u32);
+    let page = if show {
+        ActivePage::Sleeping
+    } else {
+        config.runtime.current_page
+    };
+    screen_tx.send(ScreenEvent::Refresh(page))?;
+    Ok(true)
+}
+
+/// 配网模式, 收到配网表单后保存配置, 超时或者长按左键退出, 之后由调用者重启
+fn provisioning_mode(
+    board: &mut BoardPeripherals,
+    screen: &mut Screen,
+    device_config: &Arc<Mutex<DeviceConfig>>,
+    key_rx: &Receiver<PressedKeyInfo>,
+) -> anyhow::Result<()> {
+    log::info!("enter provisioning mode");
+    let (city_name, time_zone) = {
+        let config = device_config
+            .lock()
+            .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?;
+        (
+            config.settings.city_name.clone(),
+            config.settings.time_zone.clone(),
+        )
+    };
+    let (provisioning, form_rx) =
+        Provisioning::start(board.ensure_wifi()?, &city_name, &time_zone)?;
+    log::info!("provisioning portal on {}", provisioning.ip);
+    ui::mouse_food_test(screen, device_config.clone(), ActivePage::Provisioning)?;
+
+    let deadline = std::time::Instant::now() + PROVISION_TIMEOUT;
+    while std::time::Instant::now() < deadline {
+        if key_rx
+            .try_recv()
+            .is_ok_and(|key| key == PROVISION_CANCEL_KEY)
+        {
+            log::info!("provisioning canceled by key");
+            return Ok(());
+        }
+        let Ok(form) = form_rx.recv_timeout(std::time::Duration::from_millis(500)) else {
+            continue;
+        };
+        {
+            let mut config = device_config
+                .lock()
+                .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?;
+            config.apply_provision(&form)?;
+        }
+        // 等浏览器收到保存成功的页面再重启
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        return Ok(());
+    }
+    log::info!("provisioning timeout");
+    Ok(())
+}
+
+/// 重启后进入配网模式, auto 表示连不上 wifi 时自动进入, 记下次数给下次退避
+fn request_provisioning(device_config: &mut DeviceConfig, auto: bool) {
+    rtc_state::update(|state| {
+        state.provisioning = 1;
+        state.wifi_failures = 0;
+        state.auto_provisions += auto as u32;
+    });
+    if let Err(e) = device_config.runtime.sync(true) {
+        log::warn!("save config before provisioning failed: {e:?}");
+    }
+    esp_idf_svc::hal::reset::restart();
+}
+
+/// 等屏幕线程处理完之前的事件, 避免刷新到一半就进入深度睡眠
+fn wait_screen_idle(screen_tx: &Sender<ScreenEvent>) {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    if screen_tx.send(ScreenEvent::Sync(done_tx)).is_ok() {
+        if let Err(e) = done_rx.recv_timeout(std::time::Duration::from_secs(10)) {
+            log::warn!("wait screen idle failed: {e:?}");
+        }
+    }
+}
+
+/// 睡眠前等弹窗超时或者被确认, 睡眠后弹窗队列就没有了, 需要确认的弹窗会直接消失.
+/// 最多等 MAX_POPUP_AWAKE, 没人确认的弹窗还能在通知中心里看到
+fn wait_popups_closed(screen_tx: &Sender<ScreenEvent>) {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    if screen_tx.send(ScreenEvent::WaitPopups(done_tx)).is_ok() {
+        if let Err(e) = done_rx.recv_timeout(MAX_POPUP_AWAKE) {
+            log::warn!("popups still showing before sleep: {e:?}");
         }
     }
 }
@@ -157,65 +416,162 @@ fn ket_task(
     key_rx: Receiver<PressedKeyInfo>,
     board_key: Arc<Mutex<BoardPeripherals>>,
     device_config_key: Arc<Mutex<DeviceConfig>>,
+    popup_wait_ack: Arc<AtomicBool>,
 ) {
+    let record_enable = device_config_key
+        .lock()
+        .is_ok_and(|config| config.settings.key_record_enable);
+    let mut recorder = if record_enable {
+        KeyRecorder::new(DEFAULT_KEY_RECORD_PATH)
+            .map_err(|e| log::warn!("create key recorder failed: {e:?}"))
+            .ok()
+    } else {
+        None
+    };
     while !key_exit.load(std::sync::atomic::Ordering::Relaxed) {
         let Ok(key_info) = key_rx.recv() else {
             log::warn!("key receive failed");
             continue;
         };
         log::info!("key_info: {key_info:?}");
-        let mut need_beep = true;
-        match key_info.click_type {
-            KeyClickedType::NoClick => {}
-            KeyClickedType::SingleClicked => {
-                let cur_set_page = ActivePage::from_event(key_info.idx, 1);
-                if let Err(e) = screen_tx.send(ScreenEvent::Refresh(cur_set_page)) {
-                    log::warn!("refresh active_page failed: {e:?}");
-                }
+        if let Some(recorder) = recorder.as_mut() {
+            if let Err(e) = recorder.record(&key_info) {
+                log::warn!("record key failed: {e:?}");
             }
-            KeyClickedType::DoubleClicked => {
-                if let Err(e) = screen_tx.send(ScreenEvent::Popup(PopupMsg::new(
-                    "Warning".to_string(),
-                    "test".to_string(),
-                ))) {
-                    log::warn!("Popup failed: {e:?}");
-                }
+        }
+        // 恢复出厂设置不走按键绑定, 弹窗等待确认时也能用
+        if key_info == FACTORY_RESET_KEYS {
+            let popup = PopupMsg::new(
+                "key",
+                "Factory reset".to_string(),
+                "Erasing settings and restarting...".to_string(),
+            )
+            .with_severity(PopupSeverity::Warning);
+            if let Err(e) = screen_tx.send(ScreenEvent::Popup(popup)) {
+                log::warn!("send screen event failed: {e:?}");
             }
-            KeyClickedType::TripleClicked => {
-                let cur_set_page = ActivePage::from_event(key_info.idx, 3);
-                if cur_set_page == ActivePage::None {
-                    let Ok(mut board) = board_key.lock() else {
-                        log::error!("board mutex poisoned");
-                        continue;
-                    };
-                    match key_info.idx {
-                        0 => {
-                            if let Err(e) = connect_net(&mut board, device_config_key.clone()) {
-                                log::warn!("connect failed: {e:?}");
-                            }
-                        }
-                        1 => {}
-                        2 => {
-                            need_beep = false;
-                            if let Err(e) = audio_tx
-                                .send(AudioCmd::Music("/fat/system/audio/audio.wav".to_string()))
-                            {
-                                log::warn!("audio send failed: {e:?}");
-                            }
-                            log::info!("send music");
-                        }
-                        _ => {}
-                    }
-                } else if let Err(e) = screen_tx.send(ScreenEvent::Refresh(cur_set_page)) {
-                    log::warn!("refresh active_page failed: {e:?}");
+            wait_screen_idle(&screen_tx);
+            factory_reset_and_restart(&device_config_key, std::time::Duration::ZERO);
+        }
+        if popup_wait_ack.load(std::sync::atomic::Ordering::Relaxed) {
+            // 一次长按会先后发送 LongPressed 和 Released, 只认单击和长按, 免得一次确认两个弹窗
+            if matches!(
+                key_info.click_type,
+                KeyClickedType::SingleClicked | KeyClickedType::LongPressed
+            ) {
+                if let Err(e) = screen_tx.send(ScreenEvent::PopupAck) {
+                    log::warn!("popup ack failed: {e:?}");
                 }
+                key_beep(&audio_tx, &device_config_key);
             }
+            continue;
         }
-        if need_beep {
-            if let Err(e) = audio_tx.send(AudioCmd::Beep(1, 150)) {
+        let action = match device_config_key.lock() {
+            Ok(config) => config.key_action(&key_info),
+            Err(e) => {
+                log::error!("device_config mutex poisoned: {e:?}");
+                continue;
+            }
+        };
+        let Some(action) = action else {
+            log::info!("no action bind to key: {key_info:?}");
+            continue;
+        };
+        if action.need_beep() {
+            key_beep(&audio_tx, &device_config_key);
+        }
+        run_key_action(
+            action,
+            &audio_tx,
+            &screen_tx,
+            &board_key,
+            &device_config_key,
+        );
+    }
+}
+
+/// 按键提示音, 静音或者夜间时不响
+fn key_beep(audio_tx: &Sender<AudioCmd>, device_config: &Arc<Mutex<DeviceConfig>>) {
+    if device_config
+        .lock()
+        .is_ok_and(|config| config.settings.muted || config.night_muted())
+    {
+        return;
+    }
+    if let Err(e) = audio_tx.send(AudioCmd::Beep(1, 150)) {
+        log::warn!("audio send failed: {e:?}");
+    }
+}
+
+/// 执行按键绑定的动作
+fn run_key_action(
+    action: KeyAction,
+    audio_tx: &Sender<AudioCmd>,
+    screen_tx: &Sender<ScreenEvent>,
+    board_key: &Arc<Mutex<BoardPeripherals>>,
+    device_config_key: &Arc<Mutex<DeviceConfig>>,
+) {
+    log::info!("run key action: {action:?}");
+    let screen_event = match action {
+        KeyAction::SwitchPage(page) => ScreenEvent::Refresh(page),
+        KeyAction::FullRefresh => ScreenEvent::FullRefresh,
+        KeyAction::NotificationScroll(step) => ScreenEvent::NotificationScroll(step),
+        KeyAction::NotificationClear => ScreenEvent::NotificationClear,
+        KeyAction::TestPopup => ScreenEvent::Popup(
+            PopupMsg::new("key", "Warning".to_string(), "test".to_string())
+                .with_severity(PopupSeverity::Warning)
+                .with_timeout(std::time::Duration::from_secs(10)),
+        ),
+        KeyAction::ReconnectWifi => {
+            let Ok(mut board) = board_key.lock() else {
+                log::error!("board mutex poisoned");
+                return;
+            };
+            if let Err(e) = connect_net(&mut board, device_config_key.clone()) {
+                log::warn!("connect failed: {e:?}");
+            }
+            return;
+        }
+        KeyAction::PlaySound(path) => {
+            if let Err(e) = audio_tx.send(AudioCmd::Music(path)) {
                 log::warn!("audio send failed: {e:?}");
             }
+            log::info!("send music");
+            return;
         }
+        KeyAction::ToggleMute => {
+            if let Ok(mut config) = device_config_key.lock() {
+                config.settings.muted = !config.settings.muted;
+                log::info!("muted: {}", config.settings.muted);
+                if let Err(e) = config.settings.save() {
+                    log::warn!("save config failed: {e:?}");
+                }
+            }
+            return;
+        }
+        KeyAction::StartProvisioning => {
+            match device_config_key.lock() {
+                Ok(mut config) => request_provisioning(&mut config, false),
+                Err(e) => log::error!("device_config mutex poisoned: {e:?}"),
+            }
+            return;
+        }
+        KeyAction::OtaCheck => {
+            let connected = device_config_key
+                .lock()
+                .is_ok_and(|config| config.runtime.ip_info.is_some());
+            if !connected {
+                log::warn!("wifi not connected, skip ota check");
+                return;
+            }
+            if let Err(e) = after_wifi_established() {
+                log::warn!("ota check failed: {e:?}");
+            }
+            return;
+        }
+    };
+    if let Err(e) = screen_tx.send(screen_event) {
+        log::warn!("send screen event failed: {e:?}");
     }
 }
 
@@ -224,44 +580,66 @@ fn connect_net(
     board: &mut BoardPeripherals,
     device_config: Arc<Mutex<DeviceConfig>>,
 ) -> anyhow::Result<()> {
-    let Ok(mut device_config) = device_config.lock() else {
+    // 连接期间不拿配置锁, 免得按键和屏幕线程等着
+    let (networks, max_link_time) = {
+        let Ok(device_config) = device_config.lock() else {
+            anyhow::bail!("lock failed");
+        };
+        (
+            device_config.settings.wifi_networks.clone(),
+            device_config.settings.wifi_max_link_time,
+        )
+    };
+    let result = BoardPeripherals::wifi_connect(board.ensure_wifi()?, &networks, max_link_time);
+    let Ok(mut config) = device_config.lock() else {
         anyhow::bail!("lock failed");
     };
-    if let Ok(ip_info) = BoardPeripherals::wifi_connect(
-        &mut board.wifi,
-        &device_config.wifi_ssid.clone(),
-        &device_config.wifi_password.clone(),
-        device_config.wifi_max_link_time,
-    ) {
-        device_config.ip_info = Some(ip_info);
-        if let Err(e) = after_wifi_established() {
-            log::warn!("after_wifi_established failed: {e:?}");
+    let (ip_info, ssid) = match result {
+        Ok(connected) => {
+            rtc_state::update(|state| {
+                state.wifi_failures = 0;
+                state.auto_provisions = 0;
+            });
+            connected
         }
-        if DeviceConfig::current_time_is_too_old() {
-            if let Err(e) = get_clock_ntp::set_ntp_time(
-                device_config.wifi_max_link_time / 2,
-                device_config.time_zone.as_str(),
-            ) {
-                log::warn!("failed to set NTP time: {e:?}");
+        Err(e) => {
+            log::warn!("wifi connect failed: {e:?}");
+            rtc_state::update(|state| state.wifi_failures += 1);
+            let limit = config.settings.provision_after_failures;
+            if let Some(slot) = rtc_state::load().filter(|slot| slot.state.provision_due(limit)) {
+                log::warn!(
+                    "wifi failed {} times, enter provisioning mode",
+                    slot.state.wifi_failures
+                );
+                request_provisioning(&mut config, true);
             }
+            return Ok(());
         }
-        if let Err(e) = update_weather_per_hour(&mut device_config) {
-            log::warn!("update_weather_per_hour failed: {e:?}");
+    };
+    config.runtime.ip_info = Some(ip_info);
+    config.runtime.connected_ssid = Some(ssid);
+    net_services::on_wifi_connected(&config.settings.device_name());
+    let ntp_timeout = config.settings.wifi_max_link_time / 2;
+    let time_zone = config.settings.time_zone.clone();
+    // 后面的网络请求比较慢, 不拿配置锁
+    drop(config);
+    if let Err(e) = after_wifi_established() {
+        log::warn!("after_wifi_established failed: {e:?}");
+    }
+    if DeviceConfig::current_time_is_too_old() {
+        if let Err(e) = get_clock_ntp::set_ntp_time(ntp_timeout, time_zone.as_str()) {
+            log::warn!("failed to set NTP time: {e:?}");
         }
     }
+    if let Err(e) = update_weather_per_hour(&device_config) {
+        log::warn!("update_weather_per_hour failed: {e:?}");
+    }
     Ok(())
 }
 
 /// wifi 连接成功要做的一些内容
-#[allow(clippy::arc_with_non_send_sync)]
 pub fn after_wifi_established() -> anyhow::Result<()> {
-    // 创建http客户端
-    let client = Arc::new(Mutex::new(http_client::EleDsHttpClient::new(
-        "https://60.215.128.73:12675",
-    )?));
-    let client_ota = client.clone();
-    let ota = ota::Ota::new(client_ota);
-    match ota {
+    match ota::Ota::with_default_server() {
         Ok(ota) => {
             if let Err(e) = ota.sync_firmware() {
                 log::error!("sync_firmware failed: {e}");
@@ -272,15 +650,15 @@ pub fn after_wifi_established() -> anyhow::Result<()> {
     Ok(())
 }
 /// 每小时更新一次时间, 默认都返回 default_data , 除非 get_ui_need_data()失败
-fn update_weather_per_hour(config: &mut DeviceConfig) -> anyhow::Result<()> {
+fn update_weather_per_hour(device_config: &Arc<Mutex<DeviceConfig>>) -> anyhow::Result<()> {
     let now = chrono::Local::now().hour();
-    if config.last_update_weather != now {
-        config.weather = Option::from(
-            Weather::new(&config.city_name, &config.weather_api_key).get_weather_hefeng()?,
-        );
-        config.last_update_weather = now;
-        config.save_config()?;
-        Ok(())
+    let last_update = device_config
+        .lock()
+        .map_err(|e| anyhow::anyhow!("device_config mutex poisoned: {e}"))?
+        .runtime
+        .last_update_weather;
+    if last_update != now {
+        DeviceConfig::update_weather(device_config)
     } else {
         Ok(())
     }
@@ -0,0 +1,102 @@
+use crate::file_system::atomic_file::{load_with_backup, write_atomic};
+use crate::ui::popup::{PopupMsg, PopupSeverity};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const DEFAULT_NOTIFICATION_FILE_PATH: &str = "/fat/system/notifications"; // 通知记录保存地址
+pub const MAX_NOTIFICATION_NUM: usize = 20; // 最多保存的通知条数, 超过后丢弃最旧的
+
+/// 一条通知记录, 每次弹窗都会生成一条
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationRecord {
+    pub timestamp: i64, // 收到通知的时间戳, 秒
+    pub source: String, // 通知来源, 比如 key, ota
+    pub title: String,
+    pub msg: String,
+    #[serde(default)]
+    pub severity: PopupSeverity,
+    pub read: bool, // 是否已经在通知页面看过
+}
+
+/// 通知中心, 按时间倒序保存最近的通知
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct NotificationCenter {
+    records: VecDeque<NotificationRecord>,
+    #[serde(skip)]
+    scroll: usize, // 通知页面当前滚动到的位置, 不需要保存
+}
+
+impl NotificationCenter {
+    /// 从文件加载通知记录, 正式文件坏了用 .tmp 或 .bak, 都不能用就返回空记录
+    pub fn load() -> Self {
+        match load_with_backup(DEFAULT_NOTIFICATION_FILE_PATH, |contents| {
+            Ok(serde_json::from_str::<Self>(contents)?)
+        }) {
+            Ok((center, _)) => center,
+            Err(e) => {
+                log::info!("load notifications failed: {e:?}, use empty");
+                Self::default()
+            }
+        }
+    }
+
+    /// 原子写文件, 写的时候掉电不会丢掉之前的通知
+    pub fn save(&self) -> anyhow::Result<()> {
+        write_atomic(
+            DEFAULT_NOTIFICATION_FILE_PATH,
+            &serde_json::to_string(self)?,
+        )
+    }
+
+    /// 记录一条弹窗消息并保存
+    pub fn record_popup(&mut self, popup: &PopupMsg) -> anyhow::Result<()> {
+        self.records.push_front(NotificationRecord {
+            timestamp: chrono::Local::now().timestamp(),
+            source: popup.source.clone(),
+            title: popup.title.clone(),
+            msg: popup.msg.clone(),
+            severity: popup.severity,
+            read: false,
+        });
+        self.records.truncate(MAX_NOTIFICATION_NUM);
+        self.scroll = 0;
+        self.save()
+    }
+
+    pub fn records(&self) -> &VecDeque<NotificationRecord> {
+        &self.records
+    }
+
+    pub fn unread_count(&self) -> usize {
+        self.records.iter().filter(|r| !r.read).count()
+    }
+
+    /// 把通知页面上显示出来的记录标记为已读, 从 scroll 开始的 count 条, 只有状态变化才写文件
+    pub fn mark_shown_read(&mut self, count: usize) -> anyhow::Result<()> {
+        let mut changed = false;
+        for record in self.records.iter_mut().skip(self.scroll).take(count) {
+            changed |= !record.read;
+            record.read = true;
+        }
+        if !changed {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// 滚动列表, step 为负数向上滚动
+    pub fn scroll_by(&mut self, step: i32) {
+        let max = self.records.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(step as isize).min(max);
+    }
+
+    pub fn clear_all(&mut self) -> anyhow::Result<()> {
+        self.records.clear();
+        self.scroll = 0;
+        self.save()
+    }
+}
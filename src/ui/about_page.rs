@@ -5,6 +5,7 @@ use std::default::Default;
 #[derive(Default)]
 pub struct AboutPage {
     pub ip_addr: String,
+    pub device_name: String,
     pub connect_wifi: String,
     pub wifi_password: String,
     pub soft_version: String,
@@ -18,7 +19,7 @@ impl AboutPage {
             ("wifi ssid", self.connect_wifi.to_string()),
             ("wifi password", self.wifi_password.to_string()),
             ("ip addr", self.ip_addr.to_string()),
-            ("ble name", self.ip_addr.to_string()),
+            ("device name", format!("{}.local", self.device_name)),
             ("soft version", self.soft_version.to_string()),
         ];
         show_contents_from_chunks(f, main_area, &contents);
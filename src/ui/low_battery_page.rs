@@ -0,0 +1,36 @@
+use crate::ui::{general_block, UiInfo};
+use mousefood::prelude::{Alignment, Constraint, Direction, Frame, Layout};
+use mousefood::ratatui::widgets::{Block, Paragraph};
+use std::default::Default;
+
+/// 电量过低时显示, 提示充电, 显示后设备长时间睡眠直到检测到充电
+#[derive(Default)]
+pub struct LowBatteryPage {
+    pub sleep_minutes: u32, // 睡眠多久检查一次充电
+    pub ui_info: UiInfo,
+}
+impl LowBatteryPage {
+    pub fn low_battery_page(&mut self, f: &mut Frame) {
+        let main_area = general_block(f, &self.ui_info);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([Constraint::Min(3), Constraint::Length(2)])
+            .split(main_area);
+
+        f.render_widget(
+            Paragraph::new("Battery is low\n\nPlease charge")
+                .alignment(Alignment::Center)
+                .block(Block::bordered().title(" Low Battery ")),
+            chunks[0],
+        );
+        f.render_widget(
+            Paragraph::new(format!(
+                "Wi-Fi, sound and clock are off.\nCheck charging every {} min.",
+                self.sleep_minutes
+            ))
+            .alignment(Alignment::Center),
+            chunks[1],
+        );
+    }
+}
@@ -1,11 +1,21 @@
+use crate::audio::AudioCmd;
+use crate::board::battery_decoder::BatteryStatus;
 use crate::board::peripheral::{AllSensorData, Screen};
+use crate::communication::captive_portal::wifi_qr_text;
+use crate::communication::provisioning::{self, PROVISION_TIMEOUT};
 use crate::communication::weather::WeatherResponse;
 use crate::device_config::DeviceConfig;
+use crate::notification::NotificationCenter;
+use crate::telemetry::{self, Phase};
 use crate::ui::about_page::AboutPage;
 use crate::ui::home_page::HomePageInfo;
 use crate::ui::image_page::ImagePageInfo;
+use crate::ui::low_battery_page::LowBatteryPage;
+use crate::ui::notification_page::{NotificationPage, NOTIFICATION_PAGE_LINES};
 use crate::ui::popup::PopupMsg;
+use crate::ui::provisioning_page::{draw_qr_code, ProvisioningPage, QR_AREA};
 use crate::ui::sensor_page::SensorPage;
+use crate::ui::sleeping_page::SleepingPage;
 use crate::ActivePage;
 use anyhow::anyhow;
 use mousefood::prelude::{
@@ -16,8 +26,9 @@ use mousefood::ratatui::widgets::{Block, Paragraph};
 use mousefood::{fonts, EmbeddedBackend, EmbeddedBackendConfig};
 use ssd1680::prelude::Display;
 use std::sync::atomic::AtomicBool;
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 macro_rules! hw_try {
     ($e:expr, $msg:expr) => {
@@ -27,15 +38,20 @@ macro_rules! hw_try {
 pub mod about_page;
 pub mod home_page;
 mod image_page;
+pub mod low_battery_page;
+pub mod notification_page;
 pub mod popup;
+pub mod provisioning_page;
 pub mod sensor_page;
+pub mod sleeping_page;
 
 type RenderClosure<'a> = Box<dyn FnOnce(&mut Frame) + 'a>;
 
 #[derive(Default)]
 pub struct UiInfo {
     pub net_state: bool,
-    pub battery: u8,
+    pub battery: Option<BatteryStatus>,
+    pub unread: usize, // 未读通知数量
 }
 
 /// 屏幕事件,
@@ -43,23 +59,47 @@ pub enum ScreenEvent {
     Refresh(ActivePage),
     UpdateSensorsData(AllSensorData),
     Popup(PopupMsg),
+    NotificationScroll(i32), // 通知页面滚动, 负数向上
+    NotificationClear,       // 清空全部通知
+    PopupAck,                // 按键确认当前弹窗
+    FullRefresh,             // 先把屏幕刷白再重绘当前页面, 用来清除残影
+    UpdateBatteryStatus(BatteryStatus),
+    Sync(Sender<()>), // 处理完之前的事件后回复, 用来等待屏幕刷新结束, 比如睡眠前
+    WaitPopups(Sender<()>), // 所有弹窗都关闭后回复, 弹窗只在内存里, 睡眠前要等它们超时或者被确认
 }
 
 pub fn mouse_food_test(
     screen: &mut Screen,
     device_config: Arc<Mutex<DeviceConfig>>,
     set_active_page: ActivePage,
-    popup_msg: Option<PopupMsg>,
 ) -> anyhow::Result<()> {
     if set_active_page == screen.current_page && !set_active_page.cur_set_page_is_need_refresh()
         || set_active_page == ActivePage::None
     {
         return Ok(());
     }
-    display_select_page(screen, set_active_page, device_config, popup_msg)?;
+    draw_page(screen, device_config, set_active_page)
+}
+
+/// 不判断页面是否变化, 直接重绘指定页面, 有弹窗时叠加显示在页面上面
+fn draw_page(
+    screen: &mut Screen,
+    device_config: Arc<Mutex<DeviceConfig>>,
+    set_active_page: ActivePage,
+) -> anyhow::Result<()> {
+    display_select_page(screen, set_active_page, device_config)?;
     if !set_active_page.cur_page_is_not_need_record() {
         screen.current_page = set_active_page;
     }
+    // 弹窗盖住了列表时不算看过, 新弹窗的记录要等关闭弹窗后显示出来才标记
+    if set_active_page == ActivePage::Notification && screen.popups.current().is_none() {
+        if let Err(e) = screen
+            .notifications
+            .mark_shown_read(NOTIFICATION_PAGE_LINES)
+        {
+            log::warn!("mark notifications read failed: {e:?}");
+        }
+    }
 
     // 由于ratatui的canvas限制, 显示的最小像素是字符而不是屏幕上的像素点, 这会导致分辨率降低, 为了显示bmp只能拉到外面最后绘制实际的显存
     if set_active_page == ActivePage::Image {
@@ -76,6 +116,15 @@ pub fn mouse_food_test(
             log::warn!("load image failed: {e:?}");
         }
     }
+    if set_active_page == ActivePage::Provisioning {
+        if let Err(e) = draw_qr_code(
+            &mut screen.bw_buf,
+            &wifi_qr_text(&provisioning::ap_ssid()),
+            QR_AREA,
+        ) {
+            log::warn!("draw qr code failed: {e:?}");
+        }
+    }
     // 单独解构这些, 避免借用问题
     let Screen {
         ref mut ssd1680,
@@ -83,6 +132,7 @@ pub fn mouse_food_test(
         ref mut bw_buf,
         ..
     } = &mut *screen;
+    let _timer = telemetry::timer(Phase::PanelRefresh);
     hw_try!(ssd1680.init(delay), "Ssd1680 init");
     hw_try!(ssd1680.update_bw_frame(bw_buf.buffer()), "Ssd1680 update");
     hw_try!(ssd1680.display_frame(delay), "Ssd1680 display");
@@ -95,8 +145,8 @@ fn display_select_page(
     screen: &mut Screen,
     set_active_page: ActivePage,
     device_config: Arc<Mutex<DeviceConfig>>,
-    popup_msg: Option<PopupMsg>,
 ) -> anyhow::Result<()> {
+    let _timer = telemetry::timer(Phase::Render);
     let config = EmbeddedBackendConfig {
         font_regular: fonts::MONO_6X13,
         ..Default::default()
@@ -104,7 +154,14 @@ fn display_select_page(
     let backend = EmbeddedBackend::new(&mut screen.bw_buf, config);
     let mut terminal = Terminal::new(backend)?;
 
-    let func = get_display_func(screen.last_sensor_status, set_active_page, device_config)?;
+    let func = get_display_func(
+        screen.last_sensor_status,
+        screen.battery_status,
+        &screen.notifications,
+        set_active_page,
+        device_config,
+    )?;
+    let popup_msg = screen.popups.current();
     terminal.draw(|f| {
         func(f);
         if let Some(popup_msg) = popup_msg {
@@ -117,6 +174,8 @@ fn display_select_page(
 /// 返回显示的页面, display_select_page() 使用
 fn get_display_func<'d>(
     last_sensor_status: Option<AllSensorData>,
+    battery: Option<BatteryStatus>,
+    notifications: &NotificationCenter,
     set_active_page: ActivePage,
     device_config: Arc<Mutex<DeviceConfig>>,
 ) -> anyhow::Result<RenderClosure<'d>> {
@@ -124,13 +183,18 @@ fn get_display_func<'d>(
         .lock()
         .map_err(|_| anyhow!("Mutex lock error"))?;
     let weather_response = device_config
+        .cache
         .weather
         .clone()
         .unwrap_or(WeatherResponse::default());
-    device_config.current_page = set_active_page;
+    // 低电量和睡眠页面是临时显示的, 结束后要回到原来的页面
+    if !set_active_page.is_status_page() {
+        device_config.runtime.current_page = set_active_page;
+    }
     let ui_info = UiInfo {
         net_state: false,
-        battery: 10,
+        battery,
+        unread: notifications.unread_count(),
     };
 
     let func: RenderClosure = match set_active_page {
@@ -146,7 +210,7 @@ fn get_display_func<'d>(
                 weather_info: weather_response
                     .get_ui_need_data()
                     .unwrap_or(Default::default()),
-                city: device_config.city_name_show.clone(),
+                city: device_config.settings.city_name_show.clone(),
                 ui_info,
             };
             Box::new(move |f| home.home_page(f))
@@ -159,20 +223,63 @@ fn get_display_func<'d>(
             Box::new(move |f| image.image_page(f))
         }
         ActivePage::About => {
-            let ip = if let Some(ip_info) = device_config.ip_info {
+            let ip = if let Some(ip_info) = device_config.runtime.ip_info {
                 ip_info.ip.to_string()
             } else {
                 "Wifi unlink".to_string()
             };
             let mut about = AboutPage {
                 ip_addr: ip,
-                connect_wifi: device_config.wifi_ssid.clone(),
-                wifi_password: device_config.wifi_password.clone(),
+                device_name: device_config.settings.device_name(),
+                connect_wifi: device_config
+                    .display_network()
+                    .map_or_else(|| "(none)".to_string(), |network| network.ssid.clone()),
+                wifi_password: device_config
+                    .display_network()
+                    .map(|network| network.password.masked())
+                    .unwrap_or_default(),
                 soft_version: device_config.device_info.version.clone(),
                 ui_info,
             };
             Box::new(move |f| about.about_page(f))
         }
+        ActivePage::Notification => {
+            let mut notification = NotificationPage {
+                records: notifications.records().iter().cloned().collect(),
+                scroll: notifications.scroll(),
+                ui_info,
+            };
+            Box::new(move |f| notification.notification_page(f))
+        }
+        ActivePage::LowBattery => {
+            let mut low_battery = LowBatteryPage {
+                sleep_minutes: device_config.settings.power_policy.critical_sleep_minutes,
+                ui_info,
+            };
+            Box::new(move |f| low_battery.low_battery_page(f))
+        }
+        ActivePage::Sleeping => {
+            let (_, night_end) = device_config
+                .settings
+                .power_policy
+                .night_window(device_config.sun_times());
+            let mut sleeping = SleepingPage {
+                wake_time: format!("{:02}:{:02}", night_end / 60, night_end % 60),
+                sensor_data: last_sensor_status.unwrap_or_default(),
+                ui_info,
+            };
+            Box::new(move |f| sleeping.sleeping_page(f))
+        }
+        ActivePage::Provisioning => {
+            let mut provisioning = ProvisioningPage {
+                ap_ssid: provisioning::ap_ssid(),
+                portal_url: provisioning::ap_ip()
+                    .map_or_else(|| "starting...".to_string(), provisioning::portal_url),
+                timeout_minutes: PROVISION_TIMEOUT.as_secs() / 60,
+                ui_info,
+            };
+            Box::new(move |f| provisioning.provisioning_page(f))
+        }
         _ => anyhow::bail!("Not find selected page: {set_active_page:?}"),
     };
     Ok(func)
@@ -183,35 +290,82 @@ pub(super) fn general_block(f: &mut Frame, info: &UiInfo) -> Rect {
     let outer_block = Block::bordered()
         .border_style(Style::new().black())
         .title(format!(
-            " Net: {} Battery: {}% ",
+            " Net: {} Battery: {} {}",
             if info.net_state {
                 "Connect"
             } else {
                 "Disconnect"
             },
-            info.battery
+            battery_title(info.battery),
+            if info.unread > 0 {
+                format!("Msg: {} ", info.unread)
+            } else {
+                String::new()
+            }
         ));
     let main_area = outer_block.inner(f.area());
     f.render_widget(outer_block, f.area());
     main_area
 }
 
+/// 状态栏的电量区间, 充电时加 +, 电量过低时加 !, 读不到电量时显示 --
+fn battery_title(battery: Option<BatteryStatus>) -> String {
+    let Some(status) = battery else {
+        return "--".to_string();
+    };
+    let percent = status
+        .level
+        .map_or("--".to_string(), |level| level.percent_text());
+    let mark = if status.critical {
+        "!"
+    } else if status.is_charging() {
+        "+"
+    } else {
+        ""
+    };
+    format!("{percent}{mark}")
+}
+
 /// 屏幕刷新任务
 pub fn screen_task(
     mut screen: Screen,
     device_config_ui: Arc<Mutex<DeviceConfig>>,
     screen_exit: Arc<AtomicBool>,
     screen_rx: Receiver<ScreenEvent>,
+    audio_tx: Sender<AudioCmd>,
+    popup_wait_ack: Arc<AtomicBool>,
 ) {
+    let mut popup_waiters: Vec<Sender<()>> = Vec::new();
     while !screen_exit.load(std::sync::atomic::Ordering::Relaxed) {
-        let Ok(event) = screen_rx.recv() else {
-            continue;
+        if screen.popups.current().is_none() {
+            for done_tx in popup_waiters.drain(..) {
+                let _ = done_tx.send(());
+            }
+        }
+        // 有弹窗时最多等到弹窗超时, 超时后关闭弹窗
+        let wait_time = screen
+            .popups
+            .next_timeout(Instant::now())
+            .unwrap_or(Duration::from_secs(3600));
+        let event = match screen_rx.recv_timeout(wait_time) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => {
+                if screen.popups.expire(Instant::now()) {
+                    popup_changed(
+                        &mut screen,
+                        device_config_ui.clone(),
+                        &audio_tx,
+                        &popup_wait_ack,
+                    );
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => continue,
         };
         match event {
             ScreenEvent::Refresh(cur_set_page) => {
                 log::info!("cur_set page: {cur_set_page:?}");
-                if let Err(e) =
-                    mouse_food_test(&mut screen, device_config_ui.clone(), cur_set_page, None)
+                if let Err(e) = mouse_food_test(&mut screen, device_config_ui.clone(), cur_set_page)
                 {
                     log::warn!("refresh screen failed: {e:?}");
                 };
@@ -219,19 +373,117 @@ pub fn screen_task(
             ScreenEvent::UpdateSensorsData(sensors_data) => {
                 screen.last_sensor_status = Some(sensors_data);
             }
+            ScreenEvent::UpdateBatteryStatus(status) => {
+                screen.battery_status = Some(status);
+            }
+            ScreenEvent::Sync(done_tx) => {
+                let _ = done_tx.send(());
+            }
+            ScreenEvent::WaitPopups(done_tx) => popup_waiters.push(done_tx),
             ScreenEvent::Popup(msg) => {
-                let cur_page = screen.current_page;
-                if let Err(e) =
-                    mouse_food_test(&mut screen, device_config_ui.clone(), cur_page, Some(msg))
-                {
-                    log::warn!("show popup screen failed: {e:?}");
-                };
+                if let Err(e) = screen.notifications.record_popup(&msg) {
+                    log::warn!("record notification failed: {e:?}");
+                }
+                if screen.popups.push(msg, Instant::now()) {
+                    popup_changed(
+                        &mut screen,
+                        device_config_ui.clone(),
+                        &audio_tx,
+                        &popup_wait_ack,
+                    );
+                }
+            }
+            ScreenEvent::FullRefresh => {
+                if let Err(e) = full_refresh(&mut screen, device_config_ui.clone()) {
+                    log::warn!("full refresh screen failed: {e:?}");
+                }
+            }
+            ScreenEvent::PopupAck => {
+                if screen.popups.ack(Instant::now()) {
+                    popup_changed(
+                        &mut screen,
+                        device_config_ui.clone(),
+                        &audio_tx,
+                        &popup_wait_ack,
+                    );
+                }
+            }
+            ScreenEvent::NotificationScroll(step) => {
+                screen.notifications.scroll_by(step);
+                redraw_notification_page(&mut screen, device_config_ui.clone());
+            }
+            ScreenEvent::NotificationClear => {
+                if let Err(e) = screen.notifications.clear_all() {
+                    log::warn!("clear notifications failed: {e:?}");
+                }
+                redraw_notification_page(&mut screen, device_config_ui.clone());
             }
         }
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
 }
 
+/// 把整个屏幕刷成白色后重绘当前页面
+fn full_refresh(
+    screen: &mut Screen,
+    device_config: Arc<Mutex<DeviceConfig>>,
+) -> anyhow::Result<()> {
+    {
+        let Screen {
+            ref mut ssd1680,
+            ref mut delay,
+            ..
+        } = &mut *screen;
+        let _timer = telemetry::timer(Phase::PanelRefresh);
+        hw_try!(ssd1680.init(delay), "Ssd1680 init");
+        hw_try!(ssd1680.clear_bw_frame(), "Ssd1680 clear");
+        hw_try!(ssd1680.display_frame(delay), "Ssd1680 display");
+    }
+    let cur_page = screen.current_page;
+    draw_page(screen, device_config, cur_page)
+}
+
+/// 当前显示的弹窗变化后重绘页面, 新弹窗按等级响铃, 没有弹窗时恢复原页面
+fn popup_changed(
+    screen: &mut Screen,
+    device_config: Arc<Mutex<DeviceConfig>>,
+    audio_tx: &Sender<AudioCmd>,
+    popup_wait_ack: &AtomicBool,
+) {
+    popup_wait_ack.store(
+        screen.popups.is_wait_ack(),
+        std::sync::atomic::Ordering::Relaxed,
+    );
+    let (muted, night_muted) = device_config.lock().map_or((false, false), |config| {
+        (config.settings.muted, config.night_muted())
+    });
+    // 夜间只有闹钟响铃
+    if let Some(popup) = screen
+        .popups
+        .current()
+        .filter(|popup| !muted && (!night_muted || popup.severity.ring_at_night()))
+    {
+        let (times, duration) = popup.severity.beep();
+        if let Err(e) = audio_tx.send(AudioCmd::Beep(times, duration)) {
+            log::warn!("popup beep send failed: {e:?}");
+        }
+    }
+    let cur_page = screen.current_page;
+    if let Err(e) = draw_page(screen, device_config, cur_page) {
+        log::warn!("show popup screen failed: {e:?}");
+    }
+}
+
+/// 通知页面内容变化后, 如果当前正显示通知页面就重绘
+fn redraw_notification_page(screen: &mut Screen, device_config: Arc<Mutex<DeviceConfig>>) {
+    if screen.current_page != ActivePage::Notification {
+        return;
+    }
+    if let Err(e) = draw_page(screen, device_config, ActivePage::Notification) {
+        log::warn!("refresh notification page failed: {e:?}");
+    }
+}
+
 // 绘制 大数字 的函数 使用 canvas
 pub(crate) fn draw_big_digit(
     ctx: &mut Context,
@@ -0,0 +1,57 @@
+use crate::notification::NotificationRecord;
+use crate::ui::{general_block, UiInfo};
+use chrono::TimeZone;
+use mousefood::prelude::{Alignment, Frame};
+use mousefood::ratatui::widgets::{Block, Paragraph};
+use std::default::Default;
+
+pub const NOTIFICATION_PAGE_LINES: usize = 5; // 屏幕高 128, 6x13 字体去掉两层边框后能显示的通知条数
+
+#[derive(Default)]
+pub struct NotificationPage {
+    pub records: Vec<NotificationRecord>,
+    pub scroll: usize,
+    pub ui_info: UiInfo,
+}
+impl NotificationPage {
+    pub fn notification_page(&mut self, f: &mut Frame) {
+        let main_area = general_block(f, &self.ui_info);
+        let block = Block::bordered().title(format!(
+            " Notification {}/{} ",
+            (self.scroll + 1).min(self.records.len()),
+            self.records.len()
+        ));
+        if self.records.is_empty() {
+            f.render_widget(
+                Paragraph::new("No notification")
+                    .alignment(Alignment::Center)
+                    .block(block),
+                main_area,
+            );
+            return;
+        }
+
+        // 每条通知占一行, 未读的前面加 *
+        let lines = self
+            .records
+            .iter()
+            .skip(self.scroll)
+            .map(|r| {
+                let time = chrono::Local
+                    .timestamp_opt(r.timestamp, 0)
+                    .single()
+                    .map(|t| t.format("%m/%d %H:%M").to_string())
+                    .unwrap_or_default();
+                let unread = if r.read { ' ' } else { '*' };
+                format!("{unread}{time} [{}] {}: {}", r.source, r.title, r.msg)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        f.render_widget(
+            Paragraph::new(lines)
+                .alignment(Alignment::Left)
+                .block(block),
+            main_area,
+        );
+    }
+}
@@ -1,12 +1,79 @@
 use mousefood::prelude::{Alignment, Constraint, Frame, Layout, Rect};
 use mousefood::ratatui::layout::Flex;
 use mousefood::ratatui::widgets::{Block, Clear, Paragraph, Wrap};
+use serde::{Deserialize, Serialize};
 use std::default::Default;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-#[derive(Default)]
+pub const DEFAULT_POPUP_TIMEOUT: Duration = Duration::from_secs(30); // 弹窗默认显示时间
+
+/// 弹窗等级, 数值越大优先级越高
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PopupSeverity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Alarm, // 闹钟和提醒, 不是故障, 夜间安静时段也会响
+}
+
+impl PopupSeverity {
+    /// 不同等级的提示音, (响铃次数, 时间_ms)
+    pub fn beep(self) -> (u32, u64) {
+        match self {
+            PopupSeverity::Info => (1, 100),
+            PopupSeverity::Warning => (2, 200),
+            PopupSeverity::Error => (3, 400),
+            PopupSeverity::Alarm => (5, 500),
+        }
+    }
+
+    /// 错误和闹钟要按键确认才关闭, 不会没人看到就超时消失
+    pub fn need_ack(self) -> bool {
+        matches!(self, PopupSeverity::Error | PopupSeverity::Alarm)
+    }
+
+    /// 夜间静音时是否仍然响铃
+    pub fn ring_at_night(self) -> bool {
+        self == PopupSeverity::Alarm
+    }
+}
+
+impl FromStr for PopupSeverity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "info" => Ok(PopupSeverity::Info),
+            "warning" => Ok(PopupSeverity::Warning),
+            "error" => Ok(PopupSeverity::Error),
+            "alarm" => Ok(PopupSeverity::Alarm),
+            _ => anyhow::bail!("unknown popup level: {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PopupMsg {
-    title: String,
-    msg: String,
+    pub source: String, // 弹窗来源, 记录到通知中心
+    pub title: String,
+    pub msg: String,
+    pub severity: PopupSeverity,
+    pub timeout: Duration, // 显示多久后自动关闭, need_ack 为 true 时无效
+    pub need_ack: bool,    // 是否需要按键确认才关闭
+}
+impl Default for PopupMsg {
+    fn default() -> Self {
+        Self {
+            source: String::new(),
+            title: String::new(),
+            msg: String::new(),
+            severity: PopupSeverity::default(),
+            timeout: DEFAULT_POPUP_TIMEOUT,
+            need_ack: false,
+        }
+    }
 }
 impl PopupMsg {
     /*pub fn build_popup_msg(&self, screen: &mut Screen) -> anyhow::Result<()> {
@@ -33,9 +100,32 @@ impl PopupMsg {
         Ok(())
     }*/
 
-    pub fn new(title: String, msg: String) -> Self {
-        Self { title, msg }
+    pub fn new(source: &str, title: String, msg: String) -> Self {
+        Self {
+            source: source.to_string(),
+            title,
+            msg,
+            ..Default::default()
+        }
     }
+
+    /// 设置等级, 同时按等级决定是否需要确认, 之后可以用 with_need_ack() 修改
+    pub fn with_severity(mut self, severity: PopupSeverity) -> Self {
+        self.severity = severity;
+        self.need_ack = severity.need_ack();
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_need_ack(mut self, need_ack: bool) -> Self {
+        self.need_ack = need_ack;
+        self
+    }
+
     pub fn show_popup(&self, f: &mut Frame, area: Option<Rect>) {
         let block = Block::bordered().title(self.title.as_str());
         let area = area.unwrap_or(f.area());
@@ -56,3 +146,152 @@ fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
     let [area] = horizontal.areas(area);
     area
 }
+
+/// 弹窗队列, 同时只显示一个, 优先显示等级高的, 同等级先来先显示
+#[derive(Default)]
+pub struct PopupQueue {
+    pending: Vec<PopupMsg>,
+    showing: Option<(PopupMsg, Instant)>, // 正在显示的弹窗和开始显示的时间
+}
+
+impl PopupQueue {
+    /// 加入一个弹窗, 如果正在显示的弹窗被替换返回 true
+    pub fn push(&mut self, msg: PopupMsg, now: Instant) -> bool {
+        self.pending.push(msg);
+        // 等级更高的弹窗抢占当前弹窗, 被抢占的重新排队
+        let preempt = match &self.showing {
+            Some((showing, _)) => self.highest_pending_severity() > Some(showing.severity),
+            None => true,
+        };
+        if preempt {
+            if let Some((showing, _)) = self.showing.take() {
+                self.pending.insert(0, showing);
+            }
+            self.show_next(now);
+        }
+        preempt
+    }
+
+    pub fn current(&self) -> Option<&PopupMsg> {
+        self.showing.as_ref().map(|(msg, _)| msg)
+    }
+
+    /// 当前弹窗是否在等待按键确认
+    pub fn is_wait_ack(&self) -> bool {
+        self.current().is_some_and(|msg| msg.need_ack)
+    }
+
+    /// 距离当前弹窗超时还有多久, 没有弹窗或者需要确认时返回 None
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let (msg, start) = self.showing.as_ref()?;
+        if msg.need_ack {
+            return None;
+        }
+        Some((*start + msg.timeout).saturating_duration_since(now))
+    }
+
+    /// 处理超时, 当前弹窗被关闭时返回 true
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.next_timeout(now) != Some(Duration::ZERO) {
+            return false;
+        }
+        self.showing = None;
+        self.show_next(now);
+        true
+    }
+
+    /// 按键确认当前弹窗, 当前弹窗被关闭时返回 true
+    pub fn ack(&mut self, now: Instant) -> bool {
+        if self.showing.take().is_none() {
+            return false;
+        }
+        self.show_next(now);
+        true
+    }
+
+    fn highest_pending_severity(&self) -> Option<PopupSeverity> {
+        self.pending.iter().map(|msg| msg.severity).max()
+    }
+
+    fn show_next(&mut self, now: Instant) {
+        let Some(severity) = self.highest_pending_severity() else {
+            return;
+        };
+        if let Some(idx) = self.pending.iter().position(|msg| msg.severity == severity) {
+            let msg = self.pending.remove(idx);
+            self.showing = Some((msg, now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn popup(title: &str, severity: PopupSeverity) -> PopupMsg {
+        PopupMsg::new("test", title.to_string(), String::new()).with_severity(severity)
+    }
+
+    fn current_title(queue: &PopupQueue) -> Option<&str> {
+        queue.current().map(|msg| msg.title.as_str())
+    }
+
+    #[test]
+    fn queue_order() {
+        let now = Instant::now();
+        let mut queue = PopupQueue::default();
+        assert!(queue.push(popup("info 1", PopupSeverity::Info), now));
+        assert!(!queue.push(popup("info 2", PopupSeverity::Info), now));
+        // 等级高的抢占, 被抢占的排在同等级前面
+        assert!(queue.push(popup("warning", PopupSeverity::Warning), now));
+        assert_eq!(current_title(&queue), Some("warning"));
+
+        let later = now + DEFAULT_POPUP_TIMEOUT;
+        assert!(queue.expire(later));
+        assert_eq!(current_title(&queue), Some("info 1"));
+        assert!(queue.ack(later));
+        assert_eq!(current_title(&queue), Some("info 2"));
+        assert!(queue.ack(later));
+        assert_eq!(current_title(&queue), None);
+        assert!(!queue.ack(later));
+    }
+
+    #[test]
+    fn error_waits_for_ack() {
+        let now = Instant::now();
+        let mut queue = PopupQueue::default();
+        queue.push(popup("error", PopupSeverity::Error), now);
+        assert!(queue.is_wait_ack());
+        assert_eq!(queue.next_timeout(now), None);
+        assert!(!queue.expire(now + Duration::from_secs(3600)));
+
+        queue.push(popup("info", PopupSeverity::Info), now);
+        assert!(queue.ack(now));
+        assert_eq!(current_title(&queue), Some("info"));
+        assert!(!queue.is_wait_ack());
+        assert_eq!(
+            queue.next_timeout(now + Duration::from_secs(10)),
+            Some(DEFAULT_POPUP_TIMEOUT - Duration::from_secs(10))
+        );
+        assert!(!queue.expire(now + Duration::from_secs(10)));
+        assert!(queue.expire(now + DEFAULT_POPUP_TIMEOUT));
+    }
+
+    #[test]
+    fn alarm_preempts_error() {
+        let now = Instant::now();
+        let mut queue = PopupQueue::default();
+        queue.push(popup("error", PopupSeverity::Error), now);
+        assert!(queue.push(popup("alarm", PopupSeverity::Alarm), now));
+        assert_eq!(current_title(&queue), Some("alarm"));
+        assert!(queue.is_wait_ack());
+        assert!(queue.ack(now));
+        assert_eq!(current_title(&queue), Some("error"));
+        assert!(PopupSeverity::Alarm.ring_at_night());
+        assert!(!PopupSeverity::Error.ring_at_night());
+        assert_eq!(
+            "Alarm".parse::<PopupSeverity>().ok(),
+            Some(PopupSeverity::Alarm)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::ui::{general_block, UiInfo};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use mousefood::prelude::{Alignment, Constraint, Direction, Frame, Layout, Rect};
+use mousefood::ratatui::widgets::{Block, Paragraph};
+use qrcodegen::{QrCode, QrCodeEcc};
+use ssd1680::color::{Black, White};
+use ssd1680::prelude::DisplayAnyIn;
+use std::default::Default;
+
+/// 二维码所在的区域, 单位是字符, 在页面右边
+pub const QR_AREA: Rect = Rect {
+    x: 33,
+    y: 1,
+    width: 15,
+    height: 7,
+};
+
+/// 配网模式页面, 显示热点名称和配网地址, 右边的二维码由 draw_qr_code() 直接画到显存
+#[derive(Default)]
+pub struct ProvisioningPage {
+    pub ap_ssid: String,
+    pub portal_url: String,
+    pub timeout_minutes: u64, // 没人配网时多久退出
+    pub ui_info: UiInfo,
+}
+impl ProvisioningPage {
+    pub fn provisioning_page(&mut self, f: &mut Frame) {
+        let main_area = general_block(f, &self.ui_info);
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(20), Constraint::Length(QR_AREA.width)])
+            .split(main_area);
+
+        f.render_widget(
+            Paragraph::new(format!(
+                "1. Join Wi-Fi:\n   {}\n2. Open:\n   {}\nExit in {} min or hold left key",
+                self.ap_ssid, self.portal_url, self.timeout_minutes
+            ))
+            .alignment(Alignment::Left)
+            .block(Block::bordered().title(" Wi-Fi Setup ")),
+            chunks[0],
+        );
+    }
+}
+
+/// 把二维码画到 area 中间, 周围留一个模块宽的空白. ratatui 的最小单位是字符, 只能直接画显存
+pub fn draw_qr_code(buf: &mut DisplayAnyIn, text: &str, area: Rect) -> anyhow::Result<()> {
+    let qr = QrCode::encode_text(text, QrCodeEcc::Low)?;
+    let char_w = 6;
+    let char_h = 13;
+    let area_w = area.width as i32 * char_w;
+    let area_h = area.height as i32 * char_h;
+    let modules = qr.size() + 2;
+    let scale = (area_w.min(area_h) / modules).max(1);
+    let origin = Point::new(
+        area.x as i32 * char_w + (area_w - modules * scale) / 2,
+        area.y as i32 * char_h + (area_h - modules * scale) / 2,
+    );
+
+    let side = (modules * scale) as u32;
+    Rectangle::new(origin, Size::new(side, side))
+        .into_styled(PrimitiveStyle::with_fill(White))
+        .draw(buf)
+        .map_err(|e| anyhow::anyhow!("Draw error: {e:?}"))?;
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if !qr.get_module(x, y) {
+                continue;
+            }
+            let top_left = origin + Point::new((x + 1) * scale, (y + 1) * scale);
+            Rectangle::new(top_left, Size::new(scale as u32, scale as u32))
+                .into_styled(PrimitiveStyle::with_fill(Black))
+                .draw(buf)
+                .map_err(|e| anyhow::anyhow!("Draw error: {e:?}"))?;
+        }
+    }
+    Ok(())
+}
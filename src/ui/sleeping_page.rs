@@ -0,0 +1,38 @@
+use crate::board::peripheral::AllSensorData;
+use crate::ui::{general_block, UiInfo};
+use mousefood::prelude::{Alignment, Constraint, Direction, Frame, Layout};
+use mousefood::ratatui::widgets::{Block, Paragraph};
+use std::default::Default;
+
+/// 夜间显示, 不显示时钟, 整夜只刷新一次
+#[derive(Default)]
+pub struct SleepingPage {
+    pub wake_time: String, // 夜间结束的时间, 比如 06:12
+    pub sensor_data: AllSensorData,
+    pub ui_info: UiInfo,
+}
+impl SleepingPage {
+    pub fn sleeping_page(&mut self, f: &mut Frame) {
+        let main_area = general_block(f, &self.ui_info);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([Constraint::Min(3), Constraint::Length(1)])
+            .split(main_area);
+
+        f.render_widget(
+            Paragraph::new(format!("Zz...\n\nQuiet until {}", self.wake_time))
+                .alignment(Alignment::Center)
+                .block(Block::bordered().title(" Good Night ")),
+            chunks[0],
+        );
+        f.render_widget(
+            Paragraph::new(format!(
+                "{:.1} C  {:.1} %",
+                self.sensor_data.sht3x_measure.temperature, self.sensor_data.sht3x_measure.humidity
+            ))
+            .alignment(Alignment::Center),
+            chunks[1],
+        );
+    }
+}